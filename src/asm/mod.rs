@@ -0,0 +1,108 @@
+// `chip8 asm` and `chip8 disasm`: turn source into a ROM and back again.
+//
+// Sources ending in .8o are compiled as Octo, anything else as Cowgod/Chipper assembly.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use super::cpu;
+use super::cpu::disassembler::{self, Syntax};
+use super::cpu::symbols::Symbols;
+
+#[derive(Args)]
+pub struct AsmArgs {
+    #[arg(help = "Source file to assemble (.8o for Octo), or - for stdin")]
+    pub source: String,
+
+    #[arg(short, long, value_name = "FILE", help = "Where to write the ROM (default: the source name with .ch8)")]
+    pub output: Option<String>,
+
+    #[arg(long, help = "Also write labels and source lines to a .sym file next to the ROM, for the debugger")]
+    pub symbols: bool,
+}
+
+#[derive(Args)]
+pub struct DisasmArgs {
+    #[arg(help = "ROM file to disassemble; labels from a .sym file next to it are used")]
+    pub rom: String,
+
+    #[arg(long, default_value = "cowgod", value_parser = disassembler::SYNTAX_NAMES, help = "Assembly syntax; cowgod and chipper listings reassemble with `chip8 asm`")]
+    pub syntax: String,
+}
+
+// File extensions ROMs go by: plain CHIP-8, SUPER-CHIP and XO-CHIP
+pub const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+fn is_octo(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("8o"))
+}
+
+// Anything read_program can load: a ROM or an Octo source
+pub fn is_program(path: &Path) -> bool {
+    is_octo(path)
+        || path
+            .extension()
+            .is_some_and(|extension| ROM_EXTENSIONS.iter().any(|rom| extension.eq_ignore_ascii_case(rom)))
+}
+
+fn compile_octo(path: &Path) -> Result<(Vec<u8>, Symbols), Box<dyn std::error::Error>> {
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(cpu::octo::compile(&source, &path.display().to_string())?)
+}
+
+// The bytes to load for a program file and its symbols: ROMs as they are with whatever the .sym
+// file next to them holds, Octo sources compiled first
+pub fn read_program(path: &Path) -> Result<(Vec<u8>, Symbols), Box<dyn std::error::Error>> {
+    if is_octo(path) {
+        return compile_octo(path);
+    }
+
+    let rom = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let symbol_file = path.with_extension("sym");
+    let symbols = if symbol_file.is_file() { Symbols::load(&symbol_file)? } else { Symbols::default() };
+    Ok((rom, symbols))
+}
+
+pub fn assemble(args: &AsmArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (rom, symbols) = if args.source == "-" {
+        if args.symbols {
+            return Err("--symbols needs a source file rather than stdin".into());
+        }
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        (cpu::assembler::assemble(&source)?, Symbols::default())
+    } else if is_octo(Path::new(&args.source)) {
+        compile_octo(Path::new(&args.source))?
+    } else {
+        cpu::assembler::assemble_file(Path::new(&args.source))?
+    };
+
+    let output = match &args.output {
+        Some(output) => PathBuf::from(output),
+        None if args.source == "-" => return Err("--output is required when reading from stdin".into()),
+        None => Path::new(&args.source).with_extension("ch8"),
+    };
+    fs::write(&output, &rom)?;
+    eprintln!("Wrote {} bytes to {}", rom.len(), output.display());
+
+    if args.symbols {
+        let symbol_file = output.with_extension("sym");
+        fs::write(&symbol_file, symbols.to_text())?;
+        eprintln!("Wrote {} labels to {}", symbols.labels.len(), symbol_file.display());
+    }
+
+    Ok(())
+}
+
+pub fn disassemble(args: &DisasmArgs, mut cpu: cpu::CPU) -> Result<(), Box<dyn std::error::Error>> {
+    let (rom, symbols) = read_program(Path::new(&args.rom))?;
+    cpu.load_program(&rom)?;
+
+    let syntax = Syntax::from_name(&args.syntax).unwrap_or_default();
+    print!("{}", disassembler::to_source(&cpu.disassemble_program(syntax, &symbols.labels), syntax));
+
+    Ok(())
+}
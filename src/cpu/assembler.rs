@@ -0,0 +1,426 @@
+// Two-pass assembler for the Cowgod/Chipper mnemonics the disassembler prints, so a listing can
+// be edited and turned back into a ROM. The program is assembled to run from 0x200.
+//
+//   SPEED   EQU 3                 ; constants, also written SPEED = 3
+//   loop:   LD V0, SPEED          ; labels end in ':', comments start with ';'
+//           JP loop
+//   sprite: DB 0xF0, #90, $10010000   ; hex as 0x or #, binary as $ or 0b
+//           DW loop + 2
+//           INCLUDE "sprites.asm" ; relative to the including file
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::instruction::Instruction;
+use super::symbols::{SourceRef, Symbols};
+use super::MEMORY_SIZE;
+
+const ORIGIN: usize = 0x200;
+
+// Deep enough for any sane project, shallow enough to stop a file that includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// A source line after includes are expanded, remembering where it came from
+struct SourceLine {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.number, message: message.into() }
+    }
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+enum Operand {
+    V(u8),
+    VRange(u8, u8),
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Pitch,
+    Audio,
+    Long(i64),
+    Value(i64),
+}
+
+const MNEMONICS: [&str; 30] = [
+    "CLS", "RET", "SYS", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "LD", "ADD",
+    "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "DB", "DW",
+];
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = vec![];
+    expand(source, "<source>", Path::new("."), 0, &mut lines)?;
+    Assembler::default().run(&lines).map(|(rom, _)| rom)
+}
+
+// The ROM along with its labels and which line each address came from
+pub fn assemble_file(path: &Path) -> Result<(Vec<u8>, Symbols), AsmError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| AsmError { file: path.display().to_string(), line: 0, message: e.to_string() })?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut lines = vec![];
+    expand(&source, &path.display().to_string(), dir, 0, &mut lines)?;
+    Assembler::default().run(&lines)
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split(';').next().unwrap_or("").trim()
+}
+
+// Splices INCLUDE files into the line list
+fn expand(source: &str, file: &str, dir: &Path, depth: usize, lines: &mut Vec<SourceLine>) -> Result<(), AsmError> {
+    for (index, text) in source.lines().enumerate() {
+        let line = SourceLine { file: file.to_string(), number: index + 1, text: text.to_string() };
+        let (label, code) = split_label(strip_comment(text));
+
+        let (word, rest) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        if !word.eq_ignore_ascii_case("INCLUDE") {
+            lines.push(line);
+            continue;
+        }
+        // A label in front of an INCLUDE points at the start of the included code
+        if let Some(label) = label {
+            lines.push(SourceLine { file: line.file.clone(), number: line.number, text: format!("{}:", label) });
+        }
+
+        let name = rest.trim().trim_matches('"');
+        if name.is_empty() {
+            return Err(line.error("INCLUDE needs a file name"));
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(line.error(format!("includes nested more than {} deep", MAX_INCLUDE_DEPTH)));
+        }
+
+        let path: PathBuf = dir.join(name);
+        let included = fs::read_to_string(&path).map_err(|e| line.error(format!("can't include {}: {}", path.display(), e)))?;
+        let included_dir = path.parent().unwrap_or(dir).to_path_buf();
+        expand(&included, &path.display().to_string(), &included_dir, depth + 1, lines)?;
+    }
+
+    Ok(())
+}
+
+// "name: rest" gives the label and the rest of the line
+fn split_label(code: &str) -> (Option<&str>, &str) {
+    match code.split_once(':') {
+        Some((label, rest)) if !label.contains(char::is_whitespace) => (Some(label), rest.trim()),
+        _ => (None, code),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        (hex.to_string(), 16)
+    } else if let Some(binary) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('$')) {
+        (binary.to_string(), 2)
+    } else {
+        (lower, 10)
+    };
+    i64::from_str_radix(&digits, radix).ok()
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn in_range(value: i64, max: i64, what: &str) -> Result<i64, String> {
+    if (0..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} {:#X} is out of range (0-{:#X})", what, value, max))
+    }
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    map: Symbols,
+}
+
+impl Assembler {
+    fn run(&mut self, lines: &[SourceLine]) -> Result<(Vec<u8>, Symbols), AsmError> {
+        // Pass 1: work out where everything goes so labels can be used before they are defined
+        let mut statements = vec![];
+        let mut address = ORIGIN;
+        for line in lines {
+            if let Some(statement) = self.define(line, address)? {
+                let start = address;
+                address += size(&statement);
+                if address > MEMORY_SIZE {
+                    return Err(line.error(format!("program doesn't fit in memory, the most is {} bytes", MEMORY_SIZE - ORIGIN)));
+                }
+                statements.push((line, start, statement));
+            }
+        }
+
+        // Pass 2: encode
+        let mut rom = Vec::with_capacity(address - ORIGIN);
+        for (line, address, statement) in statements {
+            self.emit(&statement, &mut rom).map_err(|message| line.error(message))?;
+            let source = SourceRef { file: line.file.clone(), line: line.number, text: line.text.trim().to_string() };
+            self.map.lines.insert(address as u16, source);
+        }
+        Ok((rom, std::mem::take(&mut self.map)))
+    }
+
+    // Records labels and constants, and returns whatever the line assembles to
+    fn define(&mut self, line: &SourceLine, address: usize) -> Result<Option<Statement>, AsmError> {
+        let mut code = strip_comment(&line.text);
+
+        // NAME EQU value / NAME = value
+        let words: Vec<&str> = code.split_whitespace().take(2).collect();
+        if let [name, keyword] = words.as_slice()
+            && (keyword.eq_ignore_ascii_case("EQU") || *keyword == "=")
+        {
+            let value = code[name.len()..].trim_start()[keyword.len()..].trim();
+            if !is_identifier(name) {
+                return Err(line.error(format!("'{}' is not a valid constant name", name)));
+            }
+            let value = self.evaluate(value).map_err(|message| line.error(message))?;
+            self.add_symbol(line, name, value)?;
+            return Ok(None);
+        }
+
+        let (label, rest) = split_label(code);
+        if let Some(label) = label {
+            if !is_identifier(label) {
+                return Err(line.error(format!("'{}' is not a valid label", label)));
+            }
+            self.add_symbol(line, label, address as i64)?;
+            self.map.labels.insert(address as u16, label.to_string());
+        }
+        code = rest;
+
+        if code.is_empty() {
+            return Ok(None);
+        }
+
+        let (mnemonic, operands) = match code.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.split(',').map(|operand| operand.trim().to_string()).collect()),
+            None => (code, vec![]),
+        };
+        if operands.iter().any(String::is_empty) {
+            return Err(line.error("empty operand"));
+        }
+
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        if !MNEMONICS.contains(&mnemonic.as_str()) {
+            return Err(line.error(format!("unknown instruction '{}'", mnemonic)));
+        }
+
+        Ok(Some(match mnemonic.as_str() {
+            "DB" => Statement::Bytes(operands),
+            "DW" => Statement::Words(operands),
+            _ => Statement::Instruction { mnemonic, operands },
+        }))
+    }
+
+    fn add_symbol(&mut self, line: &SourceLine, name: &str, value: i64) -> Result<(), AsmError> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(line.error(format!("'{}' is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    // Sums and differences of numbers and symbols, e.g. sprites + 5
+    fn evaluate(&self, text: &str) -> Result<i64, String> {
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+
+        for c in text.chars().chain(std::iter::once('+')) {
+            match c {
+                '+' | '-' => {
+                    let word = term.trim();
+                    if word.is_empty() {
+                        return Err(format!("missing value in '{}'", text));
+                    }
+                    let value = match parse_number(word) {
+                        Some(value) => value,
+                        None => *self.symbols.get(word).ok_or_else(|| format!("undefined symbol '{}'", word))?,
+                    };
+                    total += sign * value;
+                    sign = if c == '-' { -1 } else { 1 };
+                    term.clear();
+                }
+                _ => term.push(c),
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        if let Some(x) = register(text) {
+            return Ok(Operand::V(x));
+        }
+        if let Some((first, last)) = text.split_once('-')
+            && let (Some(x), Some(y)) = (register(first.trim()), register(last.trim()))
+        {
+            return Ok(Operand::VRange(x, y));
+        }
+
+        let upper = text.to_ascii_uppercase();
+        Ok(match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "R" => Operand::R,
+            "PITCH" => Operand::Pitch,
+            "AUDIO" => Operand::Audio,
+            _ if upper.starts_with("LONG ") => Operand::Long(self.evaluate(&text[5..])?),
+            _ => Operand::Value(self.evaluate(text)?),
+        })
+    }
+
+    fn emit(&self, statement: &Statement, rom: &mut Vec<u8>) -> Result<(), String> {
+        match statement {
+            Statement::Bytes(values) => {
+                for value in values {
+                    rom.push(in_range(self.evaluate(value)?, 0xFF, "byte")? as u8);
+                }
+            }
+            Statement::Words(values) => {
+                for value in values {
+                    let word = in_range(self.evaluate(value)?, 0xFFFF, "word")? as u16;
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            Statement::Instruction { mnemonic, operands } => {
+                let operands = operands.iter().map(|text| self.operand(text)).collect::<Result<Vec<_>, _>>()?;
+                let instruction = encode(mnemonic, &operands)?;
+                rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                if let [Operand::I, Operand::Long(address)] = operands.as_slice() {
+                    let address = in_range(*address, 0xFFFF, "address")? as u16;
+                    rom.extend_from_slice(&address.to_be_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn size(statement: &Statement) -> usize {
+    match statement {
+        Statement::Bytes(values) => values.len(),
+        Statement::Words(values) => values.len() * 2,
+        Statement::Instruction { mnemonic, operands } => {
+            let long = mnemonic == "LD" && operands.len() == 2 && operands[1].to_ascii_uppercase().starts_with("LONG ");
+            if long { 4 } else { 2 }
+        }
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+    use Operand::*;
+
+    let nibble = |value: i64| in_range(value, 0xF, "nibble").map(|n| n as u8);
+    let byte = |value: i64| in_range(value, 0xFF, "byte").map(|nn| nn as u8);
+    let address = |value: i64| in_range(value, 0xFFF, "address").map(|nnn| nnn as u16);
+
+    Ok(match (mnemonic, operands) {
+        ("CLS", []) => Instruction::Clear,
+        ("RET", []) => Instruction::Return,
+        ("SYS", [Value(a)]) => Instruction::Sys { nnn: address(*a)? },
+        ("SCD", [Value(n)]) => Instruction::ScrollDown { n: nibble(*n)? },
+        ("SCU", [Value(n)]) => Instruction::ScrollUp { n: nibble(*n)? },
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LoRes,
+        ("HIGH", []) => Instruction::HiRes,
+        ("JP", [Value(a)]) => Instruction::Jump { nnn: address(*a)? },
+        ("JP", [V(0), Value(a)]) => Instruction::JumpOffset { nnn: address(*a)? },
+        ("CALL", [Value(a)]) => Instruction::Call { nnn: address(*a)? },
+        ("SE", [V(x), Value(nn)]) => Instruction::SkipEqImm { x: *x, nn: byte(*nn)? },
+        ("SE", [V(x), V(y)]) => Instruction::SkipEqReg { x: *x, y: *y },
+        ("SNE", [V(x), Value(nn)]) => Instruction::SkipNeImm { x: *x, nn: byte(*nn)? },
+        ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg { x: *x, y: *y },
+        ("LD", [V(x), Value(nn)]) => Instruction::LoadImm { x: *x, nn: byte(*nn)? },
+        ("LD", [V(x), V(y)]) => Instruction::Move { x: *x, y: *y },
+        ("LD", [I, Value(a)]) => Instruction::LoadI { nnn: address(*a)? },
+        ("LD", [I, Long(_)]) => Instruction::LoadILong,
+        ("LD", [V(x), Dt]) => Instruction::GetDelay { x: *x },
+        ("LD", [V(x), K]) => Instruction::WaitKey { x: *x },
+        ("LD", [Dt, V(x)]) => Instruction::SetDelay { x: *x },
+        ("LD", [St, V(x)]) => Instruction::SetSound { x: *x },
+        ("LD", [F, V(x)]) => Instruction::Font { x: *x },
+        ("LD", [Hf, V(x)]) => Instruction::BigFont { x: *x },
+        ("LD", [B, V(x)]) => Instruction::Bcd { x: *x },
+        ("LD", [Pitch, V(x)]) => Instruction::Pitch { x: *x },
+        ("LD", [IndirectI, V(x)]) => Instruction::Store { x: *x },
+        ("LD", [V(x), IndirectI]) => Instruction::Load { x: *x },
+        ("LD", [IndirectI, VRange(x, y)]) => Instruction::StoreRange { x: *x, y: *y },
+        ("LD", [VRange(x, y), IndirectI]) => Instruction::LoadRange { x: *x, y: *y },
+        ("LD", [R, V(x)]) => Instruction::SaveFlags { x: *x },
+        ("LD", [V(x), R]) => Instruction::LoadFlags { x: *x },
+        ("LD", [Audio, IndirectI]) => Instruction::Audio,
+        ("ADD", [V(x), Value(nn)]) => Instruction::AddImm { x: *x, nn: byte(*nn)? },
+        ("ADD", [V(x), V(y)]) => Instruction::Add { x: *x, y: *y },
+        ("ADD", [I, V(x)]) => Instruction::AddI { x: *x },
+        ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => Instruction::SubReverse { x: *x, y: *y },
+        // Without VY the shift reads VX, which is what both quirk settings agree on
+        ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+        ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+        ("RND", [V(x), Value(nn)]) => Instruction::Random { x: *x, nn: byte(*nn)? },
+        ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw { x: *x, y: *y, n: nibble(*n)? },
+        ("SKP", [V(x)]) => Instruction::SkipKey { x: *x },
+        ("SKNP", [V(x)]) => Instruction::SkipNotKey { x: *x },
+        ("PLANE", [Value(n)]) => Instruction::Plane { n: nibble(*n)? },
+        _ => return Err(format!("invalid operands for {}", mnemonic)),
+    })
+}
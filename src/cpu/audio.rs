@@ -0,0 +1,304 @@
+// Sound for the sound timer. The CPU hands Audio one 60 Hz frame at a time, and Audio turns it
+// into samples for whichever sink it was given: the sound card (with the `sound` feature), the
+// terminal bell, a WAV file, or nothing at all. While the sound timer is non-zero the frame is a
+// tone, otherwise silence. XO-CHIP pattern playback can feed its own samples through the same
+// sink later.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::clock::TIMER_HZ;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / TIMER_HZ) as usize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+pub const WAVEFORM_NAMES: [&str; 4] = ["square", "triangle", "sawtooth", "sine"];
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    // The wave at a point of its cycle, phase 0 to 1, between -1 and 1
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub volume: f32,    // 0 to 1
+    pub frequency: u32, // Hz
+    pub waveform: Waveform,
+}
+
+impl Default for AudioSettings {
+    fn default() -> AudioSettings {
+        AudioSettings { volume: 0.25, frequency: 440, waveform: Waveform::Square }
+    }
+}
+
+// Somewhere for samples to go. Samples are mono at SAMPLE_RATE, between -1 and 1.
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    // Called once no more samples are coming
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Throws the sound away, for when nobody is listening
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Rings the terminal bell each time a tone starts. It can't hold a note, but it works everywhere.
+pub struct BellSink<W: Write> {
+    out: W,
+    ringing: bool,
+}
+
+impl BellSink<io::Stderr> {
+    // stderr, so the bell doesn't get mixed into the terminal UI's output on stdout
+    pub fn stderr() -> BellSink<io::Stderr> {
+        BellSink::new(io::stderr())
+    }
+}
+
+impl<W: Write> BellSink<W> {
+    pub fn new(out: W) -> BellSink<W> {
+        BellSink { out, ringing: false }
+    }
+}
+
+impl<W: Write> AudioSink for BellSink<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let on = samples.iter().any(|&sample| sample != 0.0);
+        if on && !self.ringing {
+            self.out.write_all(b"\x07")?;
+            self.out.flush()?;
+        }
+        self.ringing = on;
+        Ok(())
+    }
+}
+
+// The default output device. The emulator makes samples a frame at a time and the device takes
+// them whenever it likes, so they wait in a queue in between; the queue is kept short so the
+// sound can't fall behind the picture.
+#[cfg(feature = "sound")]
+pub struct DeviceSink {
+    queue: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<f32>>>,
+    _stream: cpal::Stream, // the sound stops when this is dropped
+}
+
+#[cfg(feature = "sound")]
+const MAX_QUEUED_SAMPLES: usize = SAMPLES_PER_FRAME * 6;
+
+#[cfg(feature = "sound")]
+impl DeviceSink {
+    pub fn open() -> Result<DeviceSink, String> {
+        use cpal::SampleFormat;
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host().default_output_device().ok_or("no sound output device")?;
+        let supported = device.default_output_config().map_err(|e| e.to_string())?;
+        let config = supported.config();
+
+        let queue = std::sync::Arc::new(std::sync::Mutex::new(std::collections::VecDeque::new()));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, queue.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, queue.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, queue.clone()),
+            other => return Err(format!("unsupported sample format {}", other)),
+        }
+        .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(DeviceSink { queue, _stream: stream })
+    }
+}
+
+// Plays the queue on every channel, stepping through it at SAMPLE_RATE whatever the device's
+// own rate is
+#[cfg(feature = "sound")]
+fn build_stream<T: cpal::SizedSample + cpal::FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: std::sync::Arc<std::sync::Mutex<std::collections::VecDeque<f32>>>,
+) -> Result<cpal::Stream, cpal::BuildStreamError> {
+    use cpal::traits::DeviceTrait;
+
+    let channels = config.channels as usize;
+    let step = SAMPLE_RATE as f64 / config.sample_rate.0 as f64;
+    let mut position = 0.0;
+    let mut current = 0.0;
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut queue = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for frame in data.chunks_mut(channels) {
+                position += step;
+                while position >= 1.0 {
+                    position -= 1.0;
+                    current = queue.pop_front().unwrap_or(0.0);
+                }
+                frame.fill(T::from_sample(current));
+            }
+        },
+        |e| eprintln!("audio: {}", e),
+        None,
+    )
+}
+
+#[cfg(feature = "sound")]
+impl AudioSink for DeviceSink {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queue.extend(samples);
+        let excess = queue.len().saturating_sub(MAX_QUEUED_SAMPLES);
+        queue.drain(..excess);
+        Ok(())
+    }
+}
+
+// 16-bit mono PCM. The header's sizes are filled in by finish().
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_bytes: u32,
+}
+
+const WAV_HEADER_BYTES: u32 = 44;
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<WavWriter<BufWriter<File>>> {
+        WavWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(out: W) -> io::Result<WavWriter<W>> {
+        let mut writer = WavWriter { out, data_bytes: 0 };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let channels: u16 = 1;
+        let bits: u16 = 16;
+        let block_align = channels * bits / 8;
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(b"RIFF")?;
+        self.out.write_all(&(WAV_HEADER_BYTES - 8 + self.data_bytes).to_le_bytes())?;
+        self.out.write_all(b"WAVEfmt ")?;
+        self.out.write_all(&16u32.to_le_bytes())?; // size of the fmt chunk
+        self.out.write_all(&1u16.to_le_bytes())?; // PCM
+        self.out.write_all(&channels.to_le_bytes())?;
+        self.out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.out.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        self.out.write_all(&block_align.to_le_bytes())?;
+        self.out.write_all(&bits.to_le_bytes())?;
+        self.out.write_all(b"data")?;
+        self.out.write_all(&self.data_bytes.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples {
+            bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+        }
+        self.out.write_all(&bytes)?;
+        self.data_bytes += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+pub struct Audio {
+    pub settings: AudioSettings,
+    sink: Box<dyn AudioSink>,
+    phase: u32,            // how far through its cycle the tone is, in 1/SAMPLE_RATE steps
+    error: Option<String>, // the first write that failed; the sound is dropped from then on
+}
+
+impl Audio {
+    pub fn new(settings: AudioSettings, sink: Box<dyn AudioSink>) -> Audio {
+        Audio { settings, sink, phase: 0, error: None }
+    }
+
+    // One 60 Hz frame: the tone while `on`, silence otherwise
+    pub fn frame(&mut self, on: bool) {
+        let mut samples = [0.0; SAMPLES_PER_FRAME];
+        if on {
+            let step = self.settings.frequency % SAMPLE_RATE;
+            for sample in samples.iter_mut() {
+                *sample = self.settings.volume * self.settings.waveform.sample(self.phase as f32 / SAMPLE_RATE as f32);
+                self.phase = (self.phase + step) % SAMPLE_RATE;
+            }
+        } else {
+            self.phase = 0;
+        }
+
+        if let Err(e) = self.sink.write(&samples) {
+            self.error = Some(e.to_string());
+            self.sink = Box::new(NullSink);
+        }
+    }
+
+    // Finishes the sink and reports the first error the sound ran into
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Some(e) = self.error.take() {
+            return Err(format!("audio: {}", e));
+        }
+        self.sink.finish().map_err(|e| format!("audio: {}", e))
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio::new(AudioSettings::default(), Box::new(NullSink))
+    }
+}
+
+// A WAV file still gets its header when the program exits without calling finish()
+impl Drop for Audio {
+    fn drop(&mut self) {
+        let _ = self.sink.finish();
+    }
+}
@@ -0,0 +1,387 @@
+// Breakpoints for the debugger. They are checked before each instruction executes, so a hit
+// leaves the machine just in front of the instruction that caused it.
+//
+// Command syntax understood by Breakpoint::parse:
+//   break 0x2A4               stop when PC reaches 0x2A4
+//   break 0x2A4 if V3 == 5    ... but only while the condition holds
+//   break if V3 >= 0x10       stop when the condition becomes true
+//   watch 0x300-0x30F w       stop before memory in the range is read (r), written (w) or either (rw)
+//   op DXYN                   stop before every instruction matching the pattern
+
+use std::fmt;
+
+use super::CPU;
+use super::instruction::Instruction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    Pc { address: u16, condition: Option<Condition> },
+    Register(Condition),
+    Watch { start: usize, end: usize, access: Access }, // end is inclusive
+    Opcode { mask: u16, value: u16 },
+}
+
+impl Operand {
+    pub fn value(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Operand::V(reg) => cpu.registers[reg as usize] as u16,
+            Operand::I => cpu.i_register,
+            Operand::Pc => cpu.program_counter,
+            Operand::DelayTimer => cpu.delay_timer as u16,
+            Operand::SoundTimer => cpu.sound_timer as u16,
+        }
+    }
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        let current = self.operand.value(cpu);
+
+        match self.comparison {
+            Comparison::Eq => current == self.value,
+            Comparison::Ne => current != self.value,
+            Comparison::Lt => current < self.value,
+            Comparison::Le => current <= self.value,
+            Comparison::Gt => current > self.value,
+            Comparison::Ge => current >= self.value,
+        }
+    }
+}
+
+pub fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => match text.strip_prefix('#').or_else(|| text.strip_prefix('$')) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => text.parse::<u32>(),
+        },
+    };
+    parsed.map_err(|_| format!("'{}' is not a number", text))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let value = parse_number(text)?;
+    u16::try_from(value).map_err(|_| format!("address {} is out of range", text))
+}
+
+// V0-VF, I, PC, DT or ST
+pub fn parse_operand(text: &str) -> Result<Operand, String> {
+    match text.to_uppercase().as_str() {
+        "I" => Ok(Operand::I),
+        "PC" => Ok(Operand::Pc),
+        "DT" => Ok(Operand::DelayTimer),
+        "ST" => Ok(Operand::SoundTimer),
+        reg => match reg.strip_prefix('V').map(|n| u8::from_str_radix(n, 16)) {
+            Some(Ok(n)) if n < 16 => Ok(Operand::V(n)),
+            _ => Err(format!("unknown register '{}'", text)),
+        },
+    }
+}
+
+fn parse_condition(words: &[&str]) -> Result<Condition, String> {
+    let [operand, comparison, value] = words else {
+        return Err("expected a condition like 'V3 == 5'".to_string());
+    };
+
+    let operand = parse_operand(operand)?;
+    let comparison = match *comparison {
+        "==" | "=" => Comparison::Eq,
+        "!=" => Comparison::Ne,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        other => return Err(format!("unknown comparison '{}'", other)),
+    };
+
+    let value = parse_number(value)?;
+    let value = u16::try_from(value).map_err(|_| format!("value {} is out of range", value))?;
+
+    Ok(Condition { operand, comparison, value })
+}
+
+// A pattern like DXYN or 8XY4: hex digits must match, X/Y/N/K are wildcards
+fn parse_opcode_pattern(pattern: &str) -> Result<(u16, u16), String> {
+    if pattern.len() != 4 {
+        return Err(format!("opcode pattern '{}' must be 4 characters", pattern));
+    }
+
+    let (mut mask, mut value) = (0u16, 0u16);
+    for c in pattern.chars() {
+        mask <<= 4;
+        value <<= 4;
+        match c.to_ascii_uppercase() {
+            'X' | 'Y' | 'N' | 'K' => {}
+            digit => match digit.to_digit(16) {
+                Some(d) => {
+                    mask |= 0xF;
+                    value |= d as u16;
+                }
+                None => return Err(format!("bad character '{}' in opcode pattern", c)),
+            },
+        }
+    }
+
+    Ok((mask, value))
+}
+
+impl Breakpoint {
+    pub fn parse(command: &str) -> Result<Breakpoint, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["break" | "b", "if", condition @ ..] => Ok(Breakpoint::Register(parse_condition(condition)?)),
+            ["break" | "b", address] => Ok(Breakpoint::Pc { address: parse_address(address)?, condition: None }),
+            ["break" | "b", address, "if", condition @ ..] => Ok(Breakpoint::Pc {
+                address: parse_address(address)?,
+                condition: Some(parse_condition(condition)?),
+            }),
+            ["watch" | "w", range, rest @ ..] => {
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+                    None => (parse_address(range)?, parse_address(range)?),
+                };
+                if end < start {
+                    return Err("watch range ends before it starts".to_string());
+                }
+                let access = match rest {
+                    [] | ["rw"] => Access::ReadWrite,
+                    ["r"] => Access::Read,
+                    ["w"] => Access::Write,
+                    _ => return Err("watch access must be r, w or rw".to_string()),
+                };
+                Ok(Breakpoint::Watch { start: start as usize, end: end as usize, access })
+            }
+            ["op", pattern] => {
+                let (mask, value) = parse_opcode_pattern(pattern)?;
+                Ok(Breakpoint::Opcode { mask, value })
+            }
+            _ => Err(format!("unknown breakpoint '{}'", command)),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::V(reg) => write!(f, "V{:X}", reg),
+            Operand::I => write!(f, "I"),
+            Operand::Pc => write!(f, "PC"),
+            Operand::DelayTimer => write!(f, "DT"),
+            Operand::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let comparison = match self.comparison {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, "{} {} {:#x}", self.operand, comparison, self.value)
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc { address, condition: None } => write!(f, "break {:#x}", address),
+            Breakpoint::Pc { address, condition: Some(condition) } => {
+                write!(f, "break {:#x} if {}", address, condition)
+            }
+            Breakpoint::Register(condition) => write!(f, "break if {}", condition),
+            Breakpoint::Watch { start, end, access } => {
+                let access = match access {
+                    Access::Read => "r",
+                    Access::Write => "w",
+                    Access::ReadWrite => "rw",
+                };
+                if start == end {
+                    write!(f, "watch {:#x} {}", start, access)
+                } else {
+                    write!(f, "watch {:#x}-{:#x} {}", start, end, access)
+                }
+            }
+            Breakpoint::Opcode { mask, value } => {
+                let pattern: String = [12, 8, 4, 0]
+                    .iter()
+                    .map(|&shift| {
+                        if (mask >> shift) & 0xF == 0xF {
+                            char::from_digit(((value >> shift) & 0xF) as u32, 16).unwrap_or('?').to_ascii_uppercase()
+                        } else {
+                            match shift {
+                                8 => 'X',
+                                4 => 'Y',
+                                _ => 'N',
+                            }
+                        }
+                    })
+                    .collect();
+                write!(f, "op {}", pattern)
+            }
+        }
+    }
+}
+
+// The memory the instruction at PC is about to touch, as (start, length, access).
+// Instruction fetches and F000 NNNN's operand don't count.
+fn memory_access(cpu: &CPU, opcode: u16) -> Option<(usize, usize, Access)> {
+    let i = cpu.i_register as usize;
+
+    match Instruction::decode(opcode).ok()? {
+        Instruction::StoreRange { x, y } => Some((i, x.abs_diff(y) as usize + 1, Access::Write)),
+        Instruction::LoadRange { x, y } => Some((i, x.abs_diff(y) as usize + 1, Access::Read)),
+        Instruction::Draw { n, .. } => {
+            let bytes = if n == 0 { 32 } else { n as usize };
+            let planes = ((cpu.plane_mask & 1) + ((cpu.plane_mask >> 1) & 1)) as usize;
+            Some((i, bytes * planes, Access::Read))
+        }
+        Instruction::Audio => Some((i, 16, Access::Read)),
+        Instruction::Bcd { .. } => Some((i, 3, Access::Write)),
+        Instruction::Store { x } => Some((i, x as usize + 1, Access::Write)),
+        Instruction::Load { x } => Some((i, x as usize + 1, Access::Read)),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    pub list: Vec<Breakpoint>,
+    was_true: Vec<bool>, // last value of each Register condition, so it only fires on becoming true
+    skip_next: bool,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints::default()
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        self.list.push(breakpoint);
+        self.was_true.push(false);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Breakpoint> {
+        if index >= self.list.len() {
+            return None;
+        }
+        self.was_true.remove(index);
+        Some(self.list.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.was_true.clear();
+    }
+
+    // Add an unconditional PC breakpoint, or remove it if it is already there
+    pub fn toggle_pc(&mut self, address: u16) {
+        let existing = self.list.iter().position(|breakpoint| {
+            matches!(breakpoint, Breakpoint::Pc { address: a, condition: None } if *a == address)
+        });
+        match existing {
+            Some(index) => {
+                self.remove(index);
+            }
+            None => self.add(Breakpoint::Pc { address, condition: None }),
+        }
+    }
+
+    pub fn has_pc(&self, address: u16) -> bool {
+        self.list.iter().any(|breakpoint| matches!(breakpoint, Breakpoint::Pc { address: a, .. } if *a == address))
+    }
+
+    // Let the next instruction run unchecked, so resuming from a hit doesn't stop on the same spot
+    pub fn resume(&mut self) {
+        self.skip_next = true;
+    }
+
+    // Returns why execution should stop before the instruction at PC, if it should
+    pub fn check(&mut self, cpu: &CPU) -> Option<String> {
+        if self.list.is_empty() || cpu.is_stalled() {
+            return None;
+        }
+
+        let skip = std::mem::take(&mut self.skip_next);
+
+        let pc = cpu.program_counter;
+        let pc_usize = pc as usize;
+        let opcode = if pc_usize + 1 < cpu.memory.len() {
+            (cpu.memory[pc_usize] as u16) << 8 | cpu.memory[pc_usize + 1] as u16
+        } else {
+            0
+        };
+
+        let mut hit = None;
+        for (index, breakpoint) in self.list.iter().enumerate() {
+            let reason = match breakpoint {
+                Breakpoint::Pc { address, condition } => {
+                    let matched = *address == pc && condition.is_none_or(|c| c.holds(cpu));
+                    matched.then(|| format!("#{} {} reached", index + 1, breakpoint))
+                }
+                Breakpoint::Register(condition) => {
+                    // Tracked even while skipping, so a condition that became true during the
+                    // skipped instruction still fires
+                    let holds = condition.holds(cpu);
+                    let rising = holds && !self.was_true[index];
+                    self.was_true[index] = holds;
+                    rising.then(|| format!("#{} {} at {:#x}", index + 1, condition, pc))
+                }
+                Breakpoint::Watch { start, end, access } => memory_access(cpu, opcode).and_then(|(addr, len, kind)| {
+                    let overlaps = addr <= *end && addr + len > *start;
+                    let wanted = *access == Access::ReadWrite || *access == kind;
+                    (overlaps && wanted).then(|| {
+                        let verb = if kind == Access::Write { "write" } else { "read" };
+                        format!("#{} {} of {:#x}-{:#x} by {:#06X} at {:#x}", index + 1, verb, addr, addr + len - 1, opcode, pc)
+                    })
+                }),
+                Breakpoint::Opcode { mask, value } => (opcode & mask == *value)
+                    .then(|| format!("#{} {}: {:#06X} at {:#x}", index + 1, breakpoint, opcode, pc)),
+            };
+
+            if hit.is_none() {
+                hit = reason;
+            }
+        }
+
+        if skip { None } else { hit }
+    }
+}
@@ -0,0 +1,55 @@
+// Instruction rate for the CPU. The delay and sound timers always tick at TIMER_HZ;
+// this only controls how many instructions run between two ticks.
+
+pub const TIMER_HZ: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockSpeed {
+    Hz(u32),             // instructions per second, spread as evenly as possible over the frames
+    CyclesPerFrame(u32), // a fixed number of instructions between timer ticks
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    pub speed: ClockSpeed,
+    remainder: u32,
+}
+
+impl Clock {
+    pub fn new(speed: ClockSpeed) -> Clock {
+        Clock { speed, remainder: 0 }
+    }
+
+    // How many instructions the next frame should run. 700 Hz alternates between 11 and 12.
+    pub fn cycles_for_next_frame(&mut self) -> u32 {
+        match self.speed {
+            ClockSpeed::CyclesPerFrame(cycles) => cycles.max(1),
+            ClockSpeed::Hz(hz) => {
+                self.remainder += hz.max(1);
+                let cycles = self.remainder / TIMER_HZ;
+                self.remainder %= TIMER_HZ;
+                cycles
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.remainder = 0;
+    }
+
+    // The fraction of an instruction carried over to the next frame, in 1/TIMER_HZ steps. It is
+    // part of a save state so a loaded machine runs the same number of instructions per frame.
+    pub fn leftover(&self) -> u32 {
+        self.remainder
+    }
+
+    pub fn resume(&mut self, leftover: u32) {
+        self.remainder = leftover % TIMER_HZ;
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new(ClockSpeed::Hz(700))
+    }
+}
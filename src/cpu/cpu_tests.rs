@@ -1,133 +1,146 @@
-use super::CPU;
+use super::assembler::{assemble, assemble_file};
+use super::audio::{self, Audio, AudioSettings, AudioSink, BellSink, WavWriter, Waveform};
+use super::breakpoints::{self, Access, Breakpoint, Breakpoints};
+use super::database::{self, Database, Overrides};
+use super::disassembler::{self, Labels, Syntax};
+use super::instruction::{Instruction, UnknownOpcode};
+use super::keymap::{self, Keymap, KeymapConfig};
+use super::octo::compile as compile_octo;
+use super::symbols::{self, SourceRef, Symbols};
+use super::rewind::{Rewind, RewindConfig};
+use super::settings::{self, RomSettings, SettingsStore};
+use super::state::StateError;
+use super::quirks::MemoryIncrement;
+use super::{Clock, ClockSpeed, CpuError, CPU, MEMORY_SIZE, Quirks, SCREEN_HEIGHT, SCREEN_WIDTH};
 
 #[test]
 fn op_00e0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x00E0;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     let mut has_unset_px = false;
 
     for y in 0..32 {
         for x in 0..64 {
-            has_unset_px &= cpu.gfx[x][y];
+            has_unset_px &= cpu.gfx[x][y] != 0;
         }
     }
 
-    assert_eq!(has_unset_px, false);
+    assert!(!has_unset_px);
 }
 
 #[test]
 fn op_00ee() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x00EE;
     cpu.stack.push(0x20ff);
     cpu.program_counter = 0x2022;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x20ff);
 }
 
 #[test]
 fn op_0nnn_basic() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x0123;
     cpu.program_counter = 0x202;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x202);
 }
 
 #[test]
 fn op_0nnn_different_address() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x0ABC;
     cpu.program_counter = 0x300;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x300);
 }
 
 #[test]
 fn op_bnnn_basic() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xB123;
     cpu.registers[0] = 0x50;
     cpu.program_counter = 0x200;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x50 + 0x123);
 }
 
 #[test]
 fn op_bnnn_with_zero_v0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xB456;
     cpu.registers[0] = 0x00;
     cpu.program_counter = 0x200;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x456);
 }
 
 #[test]
 fn op_bnnn_with_max_v0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xB100;
     cpu.registers[0] = 0xFF;
     cpu.program_counter = 0x200;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0xFF + 0x100);
 }
 
 #[test]
 fn op_bnnn_wraparound() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xBFFF;
     cpu.registers[0] = 0xFF;
     cpu.program_counter = 0x200;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0xFF + 0xFFF);
 }
 
 #[test]
 fn op_0nnn_zero_address() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x0000;
     cpu.program_counter = 0x400;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x400);
 }
 
 #[test]
 fn op_0nnn_max_address() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x0FFF;
     cpu.program_counter = 0x500;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.program_counter, 0x500);
 }
 
 #[test]
 fn op_1nnn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x1123;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x0123);
 }
 
 #[test]
 fn op_2nnn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x2220;
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x220);
     assert_eq!(cpu.stack.pop().unwrap(), 0x200);
@@ -135,195 +148,195 @@ fn op_2nnn() {
 
 #[test]
 fn op_3xnn_true() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x3200;
     cpu.registers[2] = 0;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_3xnn_false() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x3200;
     cpu.registers[2] = 1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_4xnn_true() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x4202;
     cpu.registers[2] = 0;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_4xnn_false() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x4202;
     cpu.registers[2] = 2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_5xy0_true() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x5200;
     cpu.registers[2] = 2;
     cpu.registers[0] = 2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_5xy0_false() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x5200;
     cpu.registers[2] = 2;
     cpu.registers[0] = 1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_6xnn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x6A02;
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[10], 2);
 }
 
 #[test]
 fn op_7xnn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x7407;
     cpu.registers[4] = 2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 9);
 }
 
 #[test]
 fn op_7xnn_overflow() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x74ff;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 1);
-    assert_eq!(cpu.registers[0xf as usize], 0);
+    assert_eq!(cpu.registers[0xf_usize], 0);
 }
 
 #[test]
 fn op_8xy0() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8430;
     cpu.registers[3] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x2);
 }
 
 #[test]
 fn op_8xy1() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8431;
     cpu.registers[3] = 0x1;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x3);
 }
 
 #[test]
 fn op_8xy1_same() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8431;
     cpu.registers[3] = 0x2;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x2);
 }
 
 #[test]
 fn op_8xy2_dont_flip() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8432;
     cpu.registers[3] = 0x2;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x2);
 }
 
 #[test]
 fn op_8xy2_flip() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8432;
     cpu.registers[3] = 0x2;
     cpu.registers[4] = 0x1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0);
 }
 
 #[test]
 fn op_8xy3() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8433;
     cpu.registers[3] = 0x1;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x3);
 }
 
 #[test]
 fn op_8xy3_unset() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8433;
     cpu.registers[3] = 0x2;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x0);
 }
 
 #[test]
 fn op_8xy4_nocarry() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8434;
     cpu.registers[3] = 0x2;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x4);
     assert_eq!(cpu.registers[0xF], 0);
@@ -331,12 +344,12 @@ fn op_8xy4_nocarry() {
 
 #[test]
 fn op_8xy4_carry() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8434;
     cpu.registers[3] = 0xFF;
     cpu.registers[4] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[4], 0x1);
     assert_eq!(cpu.registers[0xF], 1);
@@ -344,12 +357,12 @@ fn op_8xy4_carry() {
 
 #[test]
 fn op_8xy5_noborrow() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8985;
     cpu.registers[9] = 0x2;
     cpu.registers[8] = 0x1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[0x9], 1);
     assert_eq!(cpu.registers[0xf], 1); // VF=1 when NO borrow occurs
@@ -357,12 +370,12 @@ fn op_8xy5_noborrow() {
 
 #[test]
 fn op_8xy5_borrow() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8985;
     cpu.registers[9] = 0x2;
     cpu.registers[8] = 0xFF;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[0x9], 0x3);
     assert_eq!(cpu.registers[0xf], 0); // VF=0 when borrow occurs
@@ -370,34 +383,34 @@ fn op_8xy5_borrow() {
 
 #[test]
 fn op_8xy6() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8986;
     cpu.registers[8] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.registers[0x9], 0x1);
     assert_eq!(cpu.registers[0xF], 0x0);
 }
 
 #[test]
 fn op_8xy6_shift() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8986;
     cpu.registers[8] = 0x5;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.registers[0x9], 0x2);
     assert_eq!(cpu.registers[0xF], 0x1);
 }
 
 #[test]
 fn op_8xy7_noborrow() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8987;
     cpu.registers[9] = 0x1;
     cpu.registers[8] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[9], 0x1);
     assert_eq!(cpu.registers[0xf], 1); // VF=1 when NO borrow occurs
@@ -405,12 +418,12 @@ fn op_8xy7_noborrow() {
 
 #[test]
 fn op_8xy7_borrow() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x8987;
     cpu.registers[9] = 0x2;
     cpu.registers[8] = 0x1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[9], 0xFF);
     assert_eq!(cpu.registers[0xf], 0); // VF=0 when borrow occurs
@@ -418,179 +431,179 @@ fn op_8xy7_borrow() {
 
 #[test]
 fn op_8xye() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x898E;
     cpu.registers[8] = 0x2;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.registers[0x9], 0x4);
     assert_eq!(cpu.registers[0xF], 0x0);
 }
 
 #[test]
 fn op_8xye_shift() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x898E;
     cpu.registers[8] = 0xFF;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     assert_eq!(cpu.registers[0x9], 0xFE);
     assert_eq!(cpu.registers[0xF], 0x1);
 }
 
 #[test]
 fn op_9xy0_true() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x9230;
     cpu.registers[2] = 0;
     cpu.registers[3] = 1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_xy0_false() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0x3230;
     cpu.registers[2] = 1;
     cpu.registers[3] = 1;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_annn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xA2EA;
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.i_register, 0x2ea);
 }
 
 // #[test]
 // fn op_cxnn() {
-//     let mut cpu = CPU::new();
+//     let mut cpu = CPU::new(Quirks::default());
 //     cpu.opcode = 0xC122;
-//     cpu.execute_opcode();
+//     cpu.execute_opcode().unwrap();
 //     //how to test?
 //     //Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
 // }
 
 #[test]
 fn op_exa1_pressed() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xE1A1;
     cpu.registers[0x1] = 1;
-    cpu.press_key(Some(1));
+    cpu.key_down(1);
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_exa1_not_pressed() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xE1A1;
     cpu.registers[0x1] = 1;
-    cpu.press_key(Some(2));
+    cpu.key_down(2);
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_ex9e_pressed() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xE19E;
     cpu.registers[0x1] = 1;
-    cpu.press_key(Some(1));
+    cpu.key_down(1);
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200 + 2);
 }
 
 #[test]
 fn op_ex9e_not_pressed() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xE19E;
     cpu.registers[0x1] = 1;
-    cpu.press_key(Some(2));
+    cpu.key_down(2);
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.program_counter, 0x200);
 }
 
 #[test]
 fn op_fx07() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xFA07;
     cpu.delay_timer = 9;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[10], 9);
 }
 
 #[test]
 fn op_fx15() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xFA15;
     cpu.registers[10] = 9;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.delay_timer, 9);
 }
 
 #[test]
 fn op_fx18() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xFA18;
     cpu.registers[0xA] = 9;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.sound_timer, 9);
 }
 
 #[test]
 fn op_fx1e() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xFA1E;
     cpu.registers[0xA] = 9;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.i_register, 9);
 }
 
 #[test]
 fn op_fx29() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xFA29;
     cpu.registers[0xA] = 9;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.i_register, 9 * 5);
 }
 
 #[test]
 fn op_fx33() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF133;
     cpu.registers[1] = 123;
     cpu.i_register = 0x260;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.memory[0x260], 1);
     assert_eq!(cpu.memory[0x260 + 1], 2);
@@ -599,7 +612,7 @@ fn op_fx33() {
 
 #[test]
 fn op_fx55() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF955;
     cpu.i_register = 0x260;
 
@@ -614,7 +627,7 @@ fn op_fx55() {
     cpu.registers[8] = 9;
     cpu.registers[9] = 10;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.memory[0x260], 1);
     assert_eq!(cpu.memory[0x261], 2);
@@ -631,7 +644,7 @@ fn op_fx55() {
 
 #[test]
 fn op_fx65() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF965;
     cpu.i_register = 0x260;
 
@@ -646,7 +659,7 @@ fn op_fx65() {
     cpu.memory[0x268] = 9;
     cpu.memory[0x269] = 10;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
     assert_eq!(cpu.registers[0], 1);
     assert_eq!(cpu.registers[1], 2);
@@ -662,7 +675,7 @@ fn op_fx65() {
 
 #[test]
 fn op_dxyn() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xDAB6;
 
     cpu.registers[10] = 0x2;
@@ -678,72 +691,2054 @@ fn op_dxyn() {
     cpu.memory[0x2EE] = 0x80;
     cpu.memory[0x2EF] = 0x80;
 
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
 
-    assert_eq!(cpu.gfx[2][12], true)
+    assert_eq!(cpu.gfx[2][12], 1)
 }
 
 #[test]
 fn op_fx0a_no_key() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF50A;
     cpu.program_counter = 0x202;
     
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     
     assert_eq!(cpu.waiting_for_key, Some(5));
-    assert_eq!(cpu.program_counter, 0x200);
+    assert_eq!(cpu.program_counter, 0x202);
 }
 
 #[test]
 fn op_fx0a_with_key() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF50A;
     cpu.program_counter = 0x202;
-    cpu.press_key(Some(0x8));
     
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
+    cpu.key_down(0x8);
+    cpu.key_up(0x8);
     
     assert_eq!(cpu.registers[5], 0x8);
     assert_eq!(cpu.waiting_for_key, None);
     assert_eq!(cpu.program_counter, 0x202);
 }
 
+#[test]
+fn op_fx0a_waits_for_release() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xF50A;
+    
+    cpu.execute_opcode().unwrap();
+    cpu.key_down(0x8);
+    
+    assert_eq!(cpu.waiting_for_key, Some(5));
+    assert_eq!(cpu.registers[5], 0);
+}
+
+#[test]
+fn op_fx0a_ignores_key_held_before_wait() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xF50A;
+    cpu.key_down(0x8);
+    
+    cpu.execute_opcode().unwrap();
+    cpu.key_up(0x8);
+    
+    assert_eq!(cpu.waiting_for_key, Some(5));
+}
+
 #[test]
 fn op_fx0a_cycle_waiting() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     cpu.opcode = 0xF30A;
     cpu.program_counter = 0x202;
     
-    cpu.execute_opcode();
+    cpu.execute_opcode().unwrap();
     
     assert_eq!(cpu.waiting_for_key, Some(3));
-    assert_eq!(cpu.program_counter, 0x200);
+    assert_eq!(cpu.program_counter, 0x202);
     
-    cpu.do_cycle();
+    cpu.do_cycle().unwrap();
     
     assert_eq!(cpu.waiting_for_key, Some(3));
-    assert_eq!(cpu.program_counter, 0x200);
+    assert_eq!(cpu.program_counter, 0x202);
 }
 
 #[test]
 fn op_fx0a_cycle_key_pressed() {
-    let mut cpu = CPU::new();
+    let mut cpu = CPU::new(Quirks::default());
     
     cpu.memory[0x200] = 0xF3;
     cpu.memory[0x201] = 0x0A;
     cpu.memory[0x202] = 0x00;
     cpu.memory[0x203] = 0xE0;
     
-    cpu.do_cycle();
+    cpu.do_cycle().unwrap();
     
     assert_eq!(cpu.waiting_for_key, Some(3));
-    assert_eq!(cpu.program_counter, 0x200);
+    assert_eq!(cpu.program_counter, 0x202);
     
-    cpu.press_key(Some(0xF));
-    cpu.do_cycle();
+    cpu.key_down(0xF);
+    cpu.key_up(0xF);
+    cpu.do_cycle().unwrap();
     
     assert_eq!(cpu.registers[3], 0xF);
     assert_eq!(cpu.waiting_for_key, None);
+    assert_eq!(cpu.program_counter, 0x204);
+}
+
+#[test]
+fn keypad_multiple_keys() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xE19E;
+    cpu.registers[1] = 0xA;
+
+    cpu.key_down(0x2);
+    cpu.key_down(0xA);
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.program_counter, 0x202);
+    assert_eq!(cpu.keypad, 0b0000_0100_0000_0100);
+
+    cpu.key_up(0xA);
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.program_counter, 0x202);
+    assert!(cpu.is_key_down(0x2));
+}
+
+#[test]
+fn op_8xy6_shift_quirk() {
+    let mut cpu = CPU::new(Quirks::superchip());
+    cpu.opcode = 0x8126;
+    cpu.registers[1] = 0x5;
+    cpu.registers[2] = 0x8;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.registers[1], 0x2);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn op_8xye_shift_quirk() {
+    let mut cpu = CPU::new(Quirks::superchip());
+    cpu.opcode = 0x812E;
+    cpu.registers[1] = 0x81;
+    cpu.registers[2] = 0x1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.registers[1], 0x2);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn op_8xy1_vf_reset_quirk() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.opcode = 0x8121;
+    cpu.registers[1] = 0x1;
+    cpu.registers[2] = 0x2;
+    cpu.registers[0xF] = 0x7;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.registers[1], 0x3);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_bnnn_jump_quirk() {
+    let mut cpu = CPU::new(Quirks::superchip());
+    cpu.opcode = 0xB220;
+    cpu.registers[0] = 0x1;
+    cpu.registers[2] = 0x4;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.program_counter, 0x224);
+}
+
+#[test]
+fn op_fx55_memory_quirk_vip() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.opcode = 0xF255;
+    cpu.i_register = 0x260;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.i_register, 0x263);
+}
+
+#[test]
+fn op_fx65_memory_quirk_chip48() {
+    let mut cpu = CPU::new(Quirks::chip48());
+    cpu.opcode = 0xF265;
+    cpu.i_register = 0x260;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.i_register, 0x262);
+}
+
+#[test]
+fn op_dxyn_wraps_without_clipping() {
+    let mut cpu = CPU::new(Quirks::modern());
+    cpu.opcode = 0xD011;
+    cpu.registers[0] = 62;
+    cpu.registers[1] = 0;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0xF0;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[63][0], 1);
+    assert_eq!(cpu.gfx[0][0], 1);
+    assert_eq!(cpu.gfx[1][0], 1);
+}
+
+#[test]
+fn op_dxyn_clipping_quirk() {
+    let mut cpu = CPU::new(Quirks::superchip());
+    cpu.opcode = 0xD011;
+    cpu.registers[0] = 62;
+    cpu.registers[1] = 0;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0xF0;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[63][0], 1);
+    assert_eq!(cpu.gfx[0][0], 0);
+    assert_eq!(cpu.gfx[1][0], 0);
+}
+
+#[test]
+fn op_dxyn_display_wait_quirk() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.opcode = 0xD011;
+    cpu.program_counter = 0x202;
+
+    cpu.execute_opcode().unwrap();
+    assert_eq!(cpu.program_counter, 0x202);
+
+    cpu.program_counter = 0x204;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(cpu.program_counter, 0x202);
+
+    cpu.tick_timers();
+    cpu.program_counter = 0x204;
+    cpu.execute_opcode().unwrap();
+    assert_eq!(cpu.program_counter, 0x204);
+}
+
+#[test]
+fn op_00ff_hires() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0x00FF;
+    cpu.gfx[10][10] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert!(cpu.hires);
+    assert_eq!(cpu.width(), 128);
+    assert_eq!(cpu.height(), 64);
+    assert_eq!(cpu.gfx[10][10], 0);
+}
+
+#[test]
+fn op_00fe_lores() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0x00FE;
+    cpu.hires = true;
+
+    cpu.execute_opcode().unwrap();
+
+    assert!(!cpu.hires);
+    assert_eq!(cpu.width(), 64);
+    assert_eq!(cpu.height(), 32);
+}
+
+#[test]
+fn op_00cn() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0x00C3;
+    cpu.gfx[5][0] = 1;
+    cpu.gfx[5][31] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[5][0], 0);
+    assert_eq!(cpu.gfx[5][3], 1);
+    assert_eq!(cpu.gfx[5][31], 0);
+}
+
+#[test]
+fn op_00fb() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0x00FB;
+    cpu.hires = true;
+    cpu.gfx[0][7] = 1;
+    cpu.gfx[127][7] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[0][7], 0);
+    assert_eq!(cpu.gfx[4][7], 1);
+    assert_eq!(cpu.gfx[127][7], 0);
+}
+
+#[test]
+fn op_00fc() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0x00FC;
+    cpu.gfx[4][7] = 1;
+    cpu.gfx[0][8] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[0][7], 1);
+    assert_eq!(cpu.gfx[4][7], 0);
+    assert_eq!(cpu.gfx[0][8], 0);
+}
+
+#[test]
+fn op_00fd() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.memory[0x200] = 0x00;
+    cpu.memory[0x201] = 0xFD;
+
+    cpu.do_cycle().unwrap();
+    cpu.do_cycle().unwrap();
+
+    assert!(cpu.exited);
     assert_eq!(cpu.program_counter, 0x202);
 }
+
+#[test]
+fn op_dxy0_16x16_sprite() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xD010;
+    cpu.hires = true;
+    cpu.registers[0] = 100;
+    cpu.registers[1] = 40;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0x80;
+    cpu.memory[0x301] = 0x01;
+    cpu.memory[0x31E] = 0x80;
+    cpu.memory[0x31F] = 0x01;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[100][40], 1);
+    assert_eq!(cpu.gfx[115][40], 1);
+    assert_eq!(cpu.gfx[100][55], 1);
+    assert_eq!(cpu.gfx[115][55], 1);
+    assert_eq!(cpu.gfx[101][40], 0);
+    assert_eq!(cpu.registers[0xF], 0);
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[100][40], 0);
+    assert_eq!(cpu.registers[0xF], 1);
+}
+
+#[test]
+fn op_fx30() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xFA30;
+    cpu.registers[0xA] = 9;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.i_register, 0x50 + 9 * 10);
+    assert_eq!(cpu.memory[cpu.i_register as usize], 0x3C);
+}
+
+#[test]
+fn op_fx75_fx85() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xF275;
+    cpu.registers[0] = 1;
+    cpu.registers[1] = 2;
+    cpu.registers[2] = 3;
+    cpu.registers[3] = 4;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.rpl_flags[..4], [1, 2, 3, 0]);
+
+    cpu.registers = [0; 16];
+    cpu.opcode = 0xF185;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.registers[..3], [1, 2, 0]);
+}
+
+#[test]
+fn op_f000_long_i() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.memory[0x200] = 0xF0;
+    cpu.memory[0x201] = 0x00;
+    cpu.memory[0x202] = 0xAB;
+    cpu.memory[0x203] = 0xCD;
+
+    cpu.do_cycle().unwrap();
+
+    assert_eq!(cpu.i_register, 0xABCD);
+    assert_eq!(cpu.program_counter, 0x204);
+}
+
+#[test]
+fn op_3xnn_skips_long_instruction() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x3100;
+    cpu.program_counter = 0x202;
+    cpu.memory[0x202] = 0xF0;
+    cpu.memory[0x203] = 0x00;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.program_counter, 0x206);
+}
+
+#[test]
+fn op_5xy2() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x5242;
+    cpu.i_register = 0x300;
+    cpu.registers[2] = 1;
+    cpu.registers[3] = 2;
+    cpu.registers[4] = 3;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.memory[0x300..0x303], [1, 2, 3]);
+    assert_eq!(cpu.i_register, 0x300);
+}
+
+#[test]
+fn op_5xy2_reversed() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x5422;
+    cpu.i_register = 0x300;
+    cpu.registers[2] = 1;
+    cpu.registers[3] = 2;
+    cpu.registers[4] = 3;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.memory[0x300..0x303], [3, 2, 1]);
+}
+
+#[test]
+fn op_5xy3() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x5133;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 7;
+    cpu.memory[0x301] = 8;
+    cpu.memory[0x302] = 9;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.registers[1..4], [7, 8, 9]);
+    assert_eq!(cpu.i_register, 0x300);
+}
+
+#[test]
+fn op_fn01_plane_select() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0xF201;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.plane_mask, 2);
+}
+
+#[test]
+fn op_dxyn_both_planes() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0xD011;
+    cpu.plane_mask = 3;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0xC0;
+    cpu.memory[0x301] = 0x80;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[0][0], 3);
+    assert_eq!(cpu.gfx[1][0], 1);
+    assert_eq!(cpu.registers[0xF], 0);
+}
+
+#[test]
+fn op_00e0_clears_selected_plane_only() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x00E0;
+    cpu.plane_mask = 2;
+    cpu.gfx[0][0] = 3;
+    cpu.gfx[1][0] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[0][0], 1);
+    assert_eq!(cpu.gfx[1][0], 1);
+}
+
+#[test]
+fn op_00dn() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0x00D2;
+    cpu.gfx[3][5] = 1;
+    cpu.gfx[3][0] = 1;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.gfx[3][3], 1);
+    assert_eq!(cpu.gfx[3][5], 0);
+    assert_eq!(cpu.gfx[3][0], 0);
+}
+
+#[test]
+fn op_f002() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0xF002;
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0xAA;
+    cpu.memory[0x30F] = 0x55;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.audio_pattern[0], 0xAA);
+    assert_eq!(cpu.audio_pattern[15], 0x55);
+}
+
+#[test]
+fn op_fx3a() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.opcode = 0xF53A;
+    cpu.registers[5] = 100;
+
+    cpu.execute_opcode().unwrap();
+
+    assert_eq!(cpu.pitch, 100);
+}
+
+#[test]
+fn unknown_opcode_error() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.memory[0x200] = 0xE1;
+    cpu.memory[0x201] = 0x00;
+
+    let result = cpu.do_cycle();
+
+    assert_eq!(result, Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0xE100 }));
+}
+
+#[test]
+fn op_9xyn_requires_zero_low_nibble() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.memory[0x200] = 0x92;
+    cpu.memory[0x201] = 0x31;
+
+    let result = cpu.do_cycle();
+
+    assert_eq!(result, Err(CpuError::UnknownOpcode { pc: 0x200, opcode: 0x9231 }));
+}
+
+#[test]
+fn instruction_round_trip() {
+    let mut valid = 0;
+    for opcode in 0..=0xFFFF {
+        if let Ok(instruction) = Instruction::decode(opcode) {
+            assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+            valid += 1;
+        }
+    }
+
+    // 11 fully used groups, then 5XY0/2/3, nine 8XYN ops, 9XY0, two EXNN and the F page
+    assert_eq!(valid, 11 * 0x1000 + 3 * 0x100 + 9 * 0x100 + 0x100 + 2 * 0x10 + 14 * 0x10 + 2);
+}
+
+#[test]
+fn instruction_decode_fields() {
+    assert_eq!(Instruction::decode(0xD12F), Ok(Instruction::Draw { x: 1, y: 2, n: 0xF }));
+    assert_eq!(Instruction::decode(0x00C4), Ok(Instruction::ScrollDown { n: 4 }));
+    assert_eq!(Instruction::decode(0xF201), Ok(Instruction::Plane { n: 2 }));
+    assert_eq!(Instruction::decode(0x0123), Ok(Instruction::Sys { nnn: 0x123 }));
+    assert_eq!(Instruction::decode(0xF100), Err(UnknownOpcode(0xF100)));
+    assert_eq!(Instruction::decode(0x8AB8), Err(UnknownOpcode(0x8AB8)));
+}
+
+fn disassemble(opcode: u16, syntax: Syntax) -> String {
+    disassembler::decode(opcode, 0x200, syntax, &Labels::new()).assembly
+}
+
+#[test]
+fn disassemble_unknown_opcode() {
+    assert_eq!(disassemble(0x5AB1, Syntax::Classic), "unknown opcode 0x5AB1");
+    assert_eq!(disassemble(0x5AB1, Syntax::Octo), "0x5A 0xB1");
+    assert_eq!(disassemble(0x5AB1, Syntax::Cowgod), "DW 0x5AB1");
+    assert_eq!(disassemble(0x5AB1, Syntax::Chipper), "DW #5AB1");
+}
+
+#[test]
+fn disassemble_syntaxes() {
+    let cases: [(u16, [&str; 4]); 8] = [
+        (0x3102, ["SKF V1=0x2", "if v1 != 0x02 then", "SE V1, 0x02", "SE V1, #02"]),
+        (0xF333, ["MI=DEQ,V3", "bcd v3", "LD B, V3", "LD B, V3"]),
+        (0xD125, ["Draw 5 Rows @X1,Y2", "sprite v1 v2 5", "DRW V1, V2, 5", "DRW V1, V2, 5"]),
+        (0x2ABC, ["DO 0xABC", ":call 0xABC", "CALL 0xABC", "CALL #ABC"]),
+        (0x8AB7, ["V10=V11-V10", "va =- vb", "SUBN VA, VB", "SUBN VA, VB"]),
+        (0xEFA1, ["SKF V15≠KEY", "if vf key then", "SKNP VF", "SKNP VF"]),
+        (0xB300, ["GOTO V0+0x300", "jump0 0x300", "JP V0, 0x300", "JP V0, #300"]),
+        (0xF065, ["V0:V0=MI", "load v0", "LD V0, [I]", "LD V0, [I]"]),
+    ];
+    let syntaxes = [Syntax::Classic, Syntax::Octo, Syntax::Cowgod, Syntax::Chipper];
+
+    for (opcode, expected) in cases {
+        for (syntax, expected) in syntaxes.iter().zip(expected) {
+            assert_eq!(disassemble(opcode, *syntax), expected, "{:#06X} in {:?}", opcode, syntax);
+        }
+    }
+}
+
+#[test]
+fn disassemble_program_folds_long_load() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.load_program(&[0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]).unwrap();
+
+    let listing = cpu.disassemble_program(Syntax::Octo, &Labels::new());
+
+    assert_eq!(listing.len(), 2);
+    assert_eq!(listing[0].assembly, "i := long 0x1234");
+    assert_eq!(listing[1].memory_location, 0x204);
+    assert_eq!(cpu.disassemble_program(Syntax::Chipper, &Labels::new())[0].assembly, "LD I, LONG #1234");
+}
+
+#[test]
+fn disassemble_program_separates_code_and_data() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x22, 0x06, 0x12, 0x02, 0xF0, 0x90, 0xA2, 0x04, 0x00, 0xEE]).unwrap();
+
+    let listing = cpu.disassemble_program(Syntax::Octo, &Labels::new());
+
+    let lines: Vec<(u16, Option<&str>, &str, bool)> = listing
+        .iter()
+        .map(|item| (item.memory_location, item.label.as_deref(), item.assembly.as_str(), item.data))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (0x200, None, "sub_206", false),
+            (0x202, Some("loc_202"), "jump loc_202", false),
+            (0x204, None, "0xF0 # ████....", true),
+            (0x205, None, "0x90 # █..█....", true),
+            (0x206, Some("sub_206"), "i := 0x204", false),
+            (0x208, None, "return", false),
+        ]
+    );
+}
+
+#[test]
+fn disassemble_program_follows_odd_aligned_jumps() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x12, 0x03, 0xFF, 0x60, 0x05, 0x00, 0xFD, 0x12, 0x34]).unwrap();
+
+    let listing = cpu.disassemble_program(Syntax::Cowgod, &Labels::new());
+
+    let lines: Vec<(u16, &str)> = listing.iter().map(|item| (item.memory_location, item.assembly.as_str())).collect();
+    assert_eq!(
+        lines,
+        [
+            (0x200, "JP loc_203"),
+            (0x202, "DB 0xFF ; ████████"),
+            (0x203, "LD V0, 0x05"),
+            (0x205, "EXIT"),
+            // Nothing runs after EXIT, so this is data even though it decodes
+            (0x207, "DB 0x12 ; ...█..█."),
+            (0x208, "DB 0x34 ; ..██.█.."),
+        ]
+    );
+    assert_eq!(listing[2].label.as_deref(), Some("loc_203"));
+}
+
+#[test]
+fn disassemble_program_follows_both_sides_of_a_skip() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.load_program(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD]).unwrap();
+
+    let listing = cpu.disassemble_program(Syntax::Classic, &Labels::new());
+
+    let locations: Vec<u16> = listing.iter().map(|item| item.memory_location).collect();
+    assert_eq!(locations, [0x200, 0x202, 0x206]);
+    assert!(listing.iter().all(|item| !item.data));
+    assert_eq!(listing[1].assembly, "I=LONG 0x1234");
+}
+
+#[test]
+fn syntax_names_round_trip() {
+    let mut syntax = Syntax::default();
+    for name in disassembler::SYNTAX_NAMES {
+        assert_eq!(Syntax::from_name(name), Some(syntax));
+        assert_eq!(syntax.name(), name);
+        syntax = syntax.next();
+    }
+    assert_eq!(syntax, Syntax::default());
+}
+
+#[test]
+fn op_00ee_stack_underflow() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.memory[0x200] = 0x00;
+    cpu.memory[0x201] = 0xEE;
+
+    let result = cpu.do_cycle();
+
+    assert_eq!(result, Err(CpuError::StackUnderflow { pc: 0x200, opcode: 0x00EE }));
+}
+
+#[test]
+fn op_2nnn_stack_overflow() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.memory[0x200] = 0x22;
+    cpu.memory[0x201] = 0x00;
+
+    for _ in 0..16 {
+        cpu.do_cycle().unwrap();
+    }
+    let result = cpu.do_cycle();
+
+    assert_eq!(result, Err(CpuError::StackOverflow { pc: 0x200, opcode: 0x2200 }));
+}
+
+#[test]
+fn op_fx33_memory_out_of_range() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xF133;
+    cpu.program_counter = 0x202;
+    cpu.i_register = 0xFFFE;
+
+    let result = cpu.execute_opcode();
+
+    assert_eq!(result, Err(CpuError::MemoryOutOfRange { pc: 0x200, opcode: 0xF133, address: 0x10000 }));
+}
+
+#[test]
+fn op_fx55_memory_out_of_range() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.opcode = 0xFF55;
+    cpu.program_counter = 0x202;
+    cpu.i_register = 0xFFF8;
+
+    let result = cpu.execute_opcode();
+
+    assert!(matches!(result, Err(CpuError::MemoryOutOfRange { .. })));
+}
+
+#[test]
+fn pc_out_of_range() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.program_counter = 0xFFFF;
+
+    let result = cpu.do_cycle();
+
+    assert!(matches!(result, Err(CpuError::PcOutOfRange { pc: 0xFFFF, .. })));
+}
+
+#[test]
+fn load_program_too_large() {
+    let mut cpu = CPU::new(Quirks::default());
+    let rom = vec![0; 0x10000];
+
+    let result = cpu.load_program(&rom);
+
+    assert_eq!(result, Err(CpuError::RomTooLarge { size: 0x10000, max: 0xFE00 }));
+}
+
+#[test]
+fn clock_hz_spreads_over_frames() {
+    let mut clock = Clock::new(ClockSpeed::Hz(700));
+
+    let cycles: Vec<u32> = (0..60).map(|_| clock.cycles_for_next_frame()).collect();
+
+    assert_eq!(cycles.iter().sum::<u32>(), 700);
+    assert!(cycles.iter().all(|&c| c == 11 || c == 12));
+}
+
+#[test]
+fn run_frame_ticks_timers_once() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(10));
+    cpu.delay_timer = 5;
+    cpu.sound_timer = 5;
+    for addr in (0x200..0x220).step_by(2) {
+        cpu.memory[addr] = 0x71; // V1 += 1
+        cpu.memory[addr + 1] = 0x01;
+    }
+
+    cpu.run_frame().unwrap();
+
+    assert_eq!(cpu.registers[1], 10);
+    assert_eq!(cpu.delay_timer, 4);
+    assert_eq!(cpu.sound_timer, 4);
+}
+
+#[test]
+fn do_cycle_leaves_timers_alone() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.delay_timer = 5;
+    cpu.memory[0x200] = 0x60;
+
+    cpu.do_cycle().unwrap();
+
+    assert_eq!(cpu.delay_timer, 5);
+}
+
+#[test]
+fn run_frame_display_wait_draws_once() {
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(10));
+    cpu.i_register = 0x300;
+    cpu.memory[0x300] = 0x80;
+    for addr in (0x200..0x220).step_by(2) {
+        cpu.memory[addr] = 0xD0; // D001
+        cpu.memory[addr + 1] = 0x01;
+    }
+
+    cpu.run_frame().unwrap();
+
+    assert_eq!(cpu.program_counter, 0x202);
+    assert_eq!(cpu.gfx[0][0], 1);
+}
+
+#[test]
+fn save_state_round_trip() {
+    let mut cpu = CPU::new(Quirks::superchip());
+    cpu.load_program(&[0x60, 0x05, 0x22, 0x06, 0x12, 0x04, 0xF3, 0x0A, 0x00, 0xEE]).unwrap();
+    cpu.run_frame().unwrap();
+    cpu.gfx[100][50] = 3;
+    cpu.hires = true;
+    cpu.delay_timer = 7;
+    cpu.key_down(0xA);
+
+    let state = cpu.save_state();
+
+    let mut restored = CPU::new(Quirks::default());
+    restored.load_state(&state).unwrap();
+
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.registers[0], 5);
+    assert_eq!(restored.program_counter, cpu.program_counter);
+    assert_eq!(restored.stack, cpu.stack);
+    assert_eq!(restored.gfx[100][50], 3);
+    assert!(restored.hires);
+    assert_eq!(restored.delay_timer, 7);
+    assert!(restored.is_key_down(0xA));
+    assert_eq!(restored.waiting_for_key, cpu.waiting_for_key);
+    assert_eq!(restored.quirks, Quirks::superchip());
+}
+
+#[test]
+fn load_state_resumes_execution() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    cpu.do_cycle().unwrap();
+    let state = cpu.save_state();
+
+    cpu.do_cycle().unwrap();
+    cpu.do_cycle().unwrap();
+    assert_eq!(cpu.registers[0], 2);
+
+    cpu.load_state(&state).unwrap();
+    assert_eq!(cpu.registers[0], 1);
+    cpu.do_cycle().unwrap();
+    cpu.do_cycle().unwrap();
+    assert_eq!(cpu.registers[0], 2);
+}
+
+#[test]
+fn load_state_rejects_bad_input() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.registers[0] = 0x42;
+    let mut state = cpu.save_state();
+
+    assert_eq!(cpu.load_state(b"nope"), Err(StateError::BadMagic));
+    assert_eq!(cpu.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+    state[4] = 0xFF;
+    assert_eq!(cpu.load_state(&state), Err(StateError::UnsupportedVersion(0x00FF)));
+
+    // A failed load leaves the machine untouched
+    assert_eq!(cpu.registers[0], 0x42);
+}
+
+// Where the display starts in a save state: magic, version, opcode, memory, registers, I and PC
+const STATE_GFX_OFFSET: usize = 4 + 2 + 2 + MEMORY_SIZE + 16 + 2 + 2;
+
+#[test]
+fn load_state_rejects_bad_pixels() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.gfx[5][7] = 0x3;
+    let mut state = cpu.save_state();
+
+    let offset = STATE_GFX_OFFSET + 5 * SCREEN_HEIGHT + 7;
+    assert_eq!(state[offset], 0x3);
+    state[offset] = 0x4;
+    assert_eq!(cpu.load_state(&state), Err(StateError::Invalid("pixel")));
+}
+
+#[test]
+fn load_state_rejects_bad_plane_mask() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.plane_mask = 0x2;
+    let mut state = cpu.save_state();
+
+    let offset = STATE_GFX_OFFSET + SCREEN_WIDTH * SCREEN_HEIGHT;
+    assert_eq!(state[offset], 0x2);
+    state[offset] = 0x4;
+    assert_eq!(cpu.load_state(&state), Err(StateError::Invalid("plane mask")));
+}
+
+#[test]
+fn load_state_rejects_stack_pointer_off_the_stack() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.stack.push(0x204);
+    cpu.sp = 1;
+    let mut state = cpu.save_state();
+
+    // plane mask, display mode, both timers and the stack depth come before the stack
+    let offset = STATE_GFX_OFFSET + SCREEN_WIDTH * SCREEN_HEIGHT + 5 + 2;
+    assert_eq!(state[offset], 1);
+    state[offset] = 0;
+    assert_eq!(cpu.load_state(&state), Err(StateError::Invalid("stack pointer")));
+}
+
+#[test]
+fn load_state_keeps_the_clock_in_step() {
+    // 700 Hz runs 11 or 12 instructions a frame depending on the leftover fraction
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x12, 0x00]).unwrap();
+    cpu.run_frame().unwrap();
+    let state = cpu.save_state();
+
+    let mut restored = CPU::new(Quirks::default());
+    restored.load_state(&state).unwrap();
+
+    for _ in 0..10 {
+        assert_eq!(restored.clock.cycles_for_next_frame(), cpu.clock.cycles_for_next_frame());
+    }
+}
+
+// Draws, stores BCD, saves registers and calls a subroutine in a loop so every kind of
+// state a step can touch gets exercised
+fn rewind_test_cpu() -> CPU {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(4));
+    cpu.load_program(&[
+        0xA3, 0x00, // 200: I = 0x300
+        0x70, 0x07, // 202: V0 += 7
+        0xF0, 0x33, // 204: BCD V0
+        0xF2, 0x55, // 206: save V0-V2
+        0xD0, 0x15, // 208: draw 8x5 at V0,V1
+        0x22, 0x10, // 20A: call 0x210
+        0x12, 0x02, // 20C: jump 0x202
+        0x00, 0x00,
+        0x71, 0x01, // 210: V1 += 1
+        0x00, 0xEE, // 212: return
+    ]).unwrap();
+    cpu
+}
+
+fn run_recorded_frame(rewind: &mut Rewind, cpu: &mut CPU) {
+    while !rewind.step(cpu).unwrap() {}
+}
+
+#[test]
+fn rewind_step_back_restores_every_state() {
+    let mut cpu = rewind_test_cpu();
+    let mut rewind = Rewind::new(RewindConfig::default());
+
+    let mut history = vec![];
+    for _ in 0..200 {
+        history.push(cpu.save_state());
+        rewind.step(&mut cpu).unwrap();
+    }
+
+    while let Some(expected) = history.pop() {
+        assert!(rewind.step_back(&mut cpu));
+        assert!(cpu.save_state() == expected, "mismatch {} steps in", history.len());
+    }
+    assert!(!rewind.step_back(&mut cpu));
+    assert_eq!(rewind.bytes_used(), 0);
+}
+
+#[test]
+fn rewind_replays_identically_after_stepping_back() {
+    let mut cpu = rewind_test_cpu();
+    let mut rewind = Rewind::new(RewindConfig { max_bytes: 1 << 20, snapshot_interval: 2 });
+
+    for _ in 0..30 {
+        rewind.step(&mut cpu).unwrap();
+    }
+    let checkpoint = cpu.save_state();
+    for _ in 0..30 {
+        rewind.step(&mut cpu).unwrap();
+    }
+    let end = cpu.save_state();
+
+    for _ in 0..30 {
+        assert!(rewind.step_back(&mut cpu));
+    }
+    assert!(cpu.save_state() == checkpoint);
+
+    for _ in 0..30 {
+        rewind.step(&mut cpu).unwrap();
+    }
+    assert!(cpu.save_state() == end);
+}
+
+#[test]
+fn rewind_step_back_frame_stops_at_frame_boundary() {
+    let mut cpu = rewind_test_cpu();
+    let mut rewind = Rewind::new(RewindConfig::default());
+
+    let mut frames = vec![];
+    for _ in 0..5 {
+        frames.push(cpu.save_state());
+        run_recorded_frame(&mut rewind, &mut cpu);
+    }
+
+    while let Some(expected) = frames.pop() {
+        assert!(rewind.step_back_frame(&mut cpu));
+        assert!(cpu.save_state() == expected);
+    }
+    assert!(!rewind.step_back_frame(&mut cpu));
+}
+
+#[test]
+fn rewind_stays_within_budget() {
+    let mut cpu = rewind_test_cpu();
+    let budget = 300 * 1024;
+    let mut rewind = Rewind::new(RewindConfig { max_bytes: budget, snapshot_interval: 10 });
+
+    let mut frames = vec![];
+    for _ in 0..200 {
+        frames.push(cpu.save_state());
+        run_recorded_frame(&mut rewind, &mut cpu);
+        assert!(rewind.bytes_used() <= budget);
+    }
+
+    // Older history is coarser but still lands on real frame boundaries
+    let mut rewound = 0;
+    while rewind.step_back_frame(&mut cpu) {
+        rewound += 1;
+        let state = cpu.save_state();
+        assert!(frames.contains(&state));
+    }
+    assert!(rewound > 0);
+    assert!(rewound < 200);
+}
+
+#[test]
+fn rewind_disabled_records_nothing() {
+    let mut cpu = rewind_test_cpu();
+    let mut rewind = Rewind::new(RewindConfig { max_bytes: 0, snapshot_interval: 60 });
+
+    run_recorded_frame(&mut rewind, &mut cpu);
+
+    assert_eq!(rewind.bytes_used(), 0);
+    assert!(!rewind.step_back(&mut cpu));
+}
+
+#[test]
+fn rewind_steps_back_out_of_a_fault() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x00, 0xEE]).unwrap();
+    let mut rewind = Rewind::new(RewindConfig::default());
+
+    assert!(rewind.step(&mut cpu).is_err());
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.program_counter, 0x200);
+}
+
+#[test]
+fn breakpoint_parse_round_trip() {
+    for command in [
+        "break 0x2a4",
+        "break 0x2a4 if V3 == 0x5",
+        "break if VA >= 0x10",
+        "break if I != 0x300",
+        "break if PC < 0x300",
+        "watch 0x300-0x30f w",
+        "watch 0x300 rw",
+        "op DXYN",
+        "op 8XY4",
+        "op 00E0",
+    ] {
+        let breakpoint = Breakpoint::parse(command).unwrap();
+        assert_eq!(breakpoint.to_string(), command);
+    }
+
+    assert_eq!(
+        Breakpoint::parse("watch 0x300-0x30F r"),
+        Ok(Breakpoint::Watch { start: 0x300, end: 0x30F, access: Access::Read })
+    );
+    assert!(Breakpoint::parse("break").is_err());
+    assert!(Breakpoint::parse("break if VG == 1").is_err());
+    assert!(Breakpoint::parse("watch 0x30F-0x300").is_err());
+    assert!(Breakpoint::parse("op DXY").is_err());
+}
+
+#[test]
+fn breakpoint_operands_and_numbers() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.program_counter = 0x2A0;
+    cpu.registers[0xF] = 1;
+
+    assert_eq!(breakpoints::parse_operand("pc").unwrap().value(&cpu), 0x2A0);
+    assert_eq!(breakpoints::parse_operand("VF").unwrap().value(&cpu), 1);
+    assert!(breakpoints::parse_operand("VG").is_err());
+
+    assert_eq!(breakpoints::parse_number("0x1F"), Ok(0x1F));
+    assert_eq!(breakpoints::parse_number("#1F"), Ok(0x1F));
+    assert_eq!(breakpoints::parse_number("31"), Ok(31));
+    assert!(breakpoints::parse_number("1F").is_err());
+}
+
+#[test]
+fn breakpoint_pc_and_resume() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x60, 0x01, 0x12, 0x00]).unwrap();
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::parse("break 0x202").unwrap());
+
+    assert_eq!(breakpoints.check(&cpu), None);
+    cpu.do_cycle().unwrap();
+    assert!(breakpoints.check(&cpu).is_some());
+
+    // Resuming runs the instruction under the breakpoint instead of stopping again
+    breakpoints.resume();
+    assert_eq!(breakpoints.check(&cpu), None);
+    cpu.do_cycle().unwrap();
+    assert_eq!(breakpoints.check(&cpu), None);
+    cpu.do_cycle().unwrap();
+    assert!(breakpoints.check(&cpu).is_some());
+}
+
+#[test]
+fn breakpoint_conditions() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::parse("break 0x200 if V0 == 3").unwrap());
+
+    let mut hits = 0;
+    for _ in 0..20 {
+        if breakpoints.check(&cpu).is_some() {
+            hits += 1;
+            assert_eq!(cpu.registers[0], 3);
+            breakpoints.resume();
+        }
+        cpu.do_cycle().unwrap();
+    }
+    assert_eq!(hits, 1);
+
+    // A bare register condition fires once when it becomes true, not while it stays true
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::parse("break if V0 >= 2").unwrap());
+
+    let mut hits = vec![];
+    for _ in 0..20 {
+        if breakpoints.check(&cpu).is_some() {
+            hits.push(cpu.registers[0]);
+        }
+        cpu.do_cycle().unwrap();
+    }
+    assert_eq!(hits, vec![2]);
+}
+
+#[test]
+fn breakpoint_watchpoints() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[
+        0xA3, 0x00, // I = 0x300
+        0xF2, 0x65, // load V0-V2
+        0xF2, 0x55, // save V0-V2
+    ]).unwrap();
+    let mut reads = Breakpoints::new();
+    reads.add(Breakpoint::parse("watch 0x302 r").unwrap());
+    let mut writes = Breakpoints::new();
+    writes.add(Breakpoint::parse("watch 0x302-0x310 w").unwrap());
+    let mut elsewhere = Breakpoints::new();
+    elsewhere.add(Breakpoint::parse("watch 0x303-0x310").unwrap());
+
+    cpu.do_cycle().unwrap();
+    assert!(reads.check(&cpu).is_some());
+    assert_eq!(writes.check(&cpu), None);
+    assert_eq!(elsewhere.check(&cpu), None);
+
+    cpu.do_cycle().unwrap();
+    assert_eq!(reads.check(&cpu), None);
+    assert!(writes.check(&cpu).is_some());
+    assert_eq!(elsewhere.check(&cpu), None);
+}
+
+#[test]
+fn breakpoint_watchpoints_follow_the_decoder() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.load_program(&[
+        0xA3, 0x00, // I = 0x300
+        0xF0, 0x33, // BCD V0
+        0xD0, 0x05, // draw 8x5
+        0x53, 0x12, // save V3-V1 (XO-CHIP)
+        0xF0, 0x02, // audio pattern (XO-CHIP)
+    ]).unwrap();
+    let mut watch = Breakpoints::new();
+    watch.add(Breakpoint::parse("watch 0x302").unwrap());
+    let mut beyond = Breakpoints::new();
+    beyond.add(Breakpoint::parse("watch 0x303-0x30F").unwrap());
+
+    // BCD and the 3-register save stop short of 0x303, the sprite and the pattern don't
+    cpu.do_cycle().unwrap();
+    for reaches_beyond in [false, true, false, true] {
+        assert!(watch.check(&cpu).is_some(), "at {:#X}", cpu.program_counter);
+        assert_eq!(beyond.check(&cpu).is_some(), reaches_beyond, "at {:#X}", cpu.program_counter);
+        cpu.do_cycle().unwrap();
+    }
+}
+
+#[test]
+fn breakpoint_opcode_class() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x60, 0x01, 0xD0, 0x15, 0x80, 0x14, 0xD1, 0x1A]).unwrap();
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::parse("op DXYN").unwrap());
+
+    let mut hits = vec![];
+    for _ in 0..4 {
+        if breakpoints.check(&cpu).is_some() {
+            hits.push(cpu.program_counter);
+        }
+        cpu.do_cycle().unwrap();
+    }
+    assert_eq!(hits, vec![0x202, 0x206]);
+}
+
+#[test]
+fn breakpoint_ignores_stalled_cpu() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0xF0, 0x0A, 0x12, 0x02]).unwrap();
+    let mut breakpoints = Breakpoints::new();
+    breakpoints.add(Breakpoint::parse("break 0x202").unwrap());
+
+    cpu.do_cycle().unwrap();
+    assert!(cpu.is_stalled());
+    assert_eq!(breakpoints.check(&cpu), None);
+
+    cpu.key_down(1);
+    cpu.key_up(1);
+    assert!(breakpoints.check(&cpu).is_some());
+}
+
+#[test]
+fn run_for_stops_at_first_limit() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(10));
+    cpu.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+    assert_eq!(cpu.run_for(Some(25), None).unwrap(), (25, 2));
+    // The first frame finishes the one already under way
+    assert_eq!(cpu.run_for(Some(100), Some(3)).unwrap(), (25, 3));
+}
+
+#[test]
+fn run_for_stops_on_exit() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.load_program(&[0x60, 0x01, 0x00, 0xFD]).unwrap();
+
+    assert_eq!(cpu.run_for(None, Some(60)).unwrap(), (2, 0));
+    assert!(cpu.exited);
+}
+
+#[test]
+fn screen_dumps() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.gfx[0][0] = 1;
+    cpu.gfx[63][31] = 3;
+
+    let ascii = cpu.screen_to_ascii();
+    let lines: Vec<&str> = ascii.lines().collect();
+    assert_eq!(lines.len(), 32);
+    assert!(lines.iter().all(|line| line.len() == 64));
+    assert!(lines[0].starts_with("#."));
+    assert!(lines[31].ends_with(".@"));
+
+    let pbm = cpu.screen_to_pbm();
+    assert!(pbm.starts_with("P1\n64 32\n1 0 0"));
+    assert!(pbm.ends_with("0 1\n"));
+
+    cpu.hires = true;
+    assert_eq!(cpu.screen_to_ascii().lines().count(), 64);
+}
+
+
+#[test]
+fn assemble_instructions_and_labels() {
+    let source = "
+        SPEED EQU 3
+        start:  LD V0, SPEED      ; forward and backward references
+                CALL draw
+                JP start
+        draw:   LD I, sprite
+                DRW V0, V1, 2
+                LD [I], V2-V5
+                LD I, LONG sprite + 1
+                RET
+        sprite: DB 0xF0, #90
+                DW draw
+    ";
+
+    let rom = assemble(source).unwrap();
+
+    assert_eq!(
+        rom,
+        [
+            0x60, 0x03, 0x22, 0x06, 0x12, 0x00, 0xA2, 0x12, 0xD0, 0x12, 0x52, 0x52, 0xF0, 0x00, 0x02, 0x13, 0x00,
+            0xEE, 0xF0, 0x90, 0x02, 0x06,
+        ]
+    );
+}
+
+#[test]
+fn assemble_number_formats() {
+    assert_eq!(assemble("DB 10, 0x0A, #0a, $1010, 0b1010").unwrap(), [10; 5]);
+    assert_eq!(assemble("LOW\nhigh\nScD 4").unwrap(), [0x00, 0xFE, 0x00, 0xFF, 0x00, 0xC4]);
+}
+
+#[test]
+fn assemble_errors_have_line_numbers() {
+    let error = |source: &str| assemble(source).unwrap_err();
+
+    assert_eq!(error("CLS\nJP nowhere").to_string(), "<source>:2: undefined symbol 'nowhere'");
+    assert_eq!(error("CLS\n\nFOO V1").line, 3);
+    assert_eq!(error("LD V0, 0x100").message, "byte 0x100 is out of range (0-0xFF)");
+    assert_eq!(error("DRW V0, V1").message, "invalid operands for DRW");
+    assert_eq!(error("a: CLS\na: RET").message, "'a' is defined more than once");
+}
+
+#[test]
+fn assemble_include_is_relative_to_including_file() {
+    let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("main.asm"), "CALL sub\nINCLUDE \"lib/sub.asm\"\n").unwrap();
+    std::fs::write(dir.join("lib/sub.asm"), "sub: INCLUDE \"data.asm\"\n").unwrap();
+    std::fs::write(dir.join("lib/data.asm"), "RET\nBAD\n").unwrap();
+
+    let error = assemble_file(&dir.join("main.asm")).unwrap_err();
+    assert!(error.file.ends_with("data.asm"), "{}", error);
+    assert_eq!(error.line, 2);
+
+    std::fs::write(dir.join("lib/data.asm"), "RET\n").unwrap();
+    assert_eq!(assemble_file(&dir.join("main.asm")).unwrap().0, [0x22, 0x02, 0x00, 0xEE]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disassembly_reassembles_to_the_same_rom() {
+    for entry in std::fs::read_dir("roms").unwrap() {
+        let path = entry.unwrap().path();
+        let rom = std::fs::read(&path).unwrap();
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load_program(&rom).unwrap();
+
+        for syntax in [Syntax::Cowgod, Syntax::Chipper] {
+            let source = disassembler::to_source(&cpu.disassemble_program(syntax, &Labels::new()), syntax);
+            assert_eq!(assemble(&source).unwrap(), rom, "{} in {:?}", path.display(), syntax);
+        }
+    }
+}
+
+#[test]
+fn disassembly_reassembles_f000_without_its_operand() {
+    // JP 0x202; F000 with the program ending where its address word would be
+    let rom = [0x12, 0x02, 0xF0, 0x00];
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.load_program(&rom).unwrap();
+
+    for syntax in [Syntax::Cowgod, Syntax::Chipper] {
+        let source = disassembler::to_source(&cpu.disassemble_program(syntax, &Labels::new()), syntax);
+        assert_eq!(assemble(&source).unwrap(), rom, "{:?}", syntax);
+    }
+    let source = disassembler::to_source(&cpu.disassemble_program(Syntax::Octo, &Labels::new()), Syntax::Octo);
+    assert_eq!(octo(&format!(": main\n{}", source)), rom);
+}
+
+fn octo(source: &str) -> Vec<u8> {
+    compile_octo(source, "test.8o").unwrap_or_else(|e| panic!("{}", e)).0
+}
+
+#[test]
+fn octo_basic_statements() {
+    let rom = octo(
+        ": main
+            clear
+            v0 := 5  v1 := v0  v2 += 3  v2 -= 1  v3 := random 0xF
+            i := hex v0  bcd v2  save v3  load v3  delay := v0  v4 := key
+            sprite v0 v1 8
+            0xAB",
+    );
+
+    assert_eq!(
+        rom,
+        [
+            0x00, 0xE0, 0x60, 0x05, 0x81, 0x00, 0x72, 0x03, 0x72, 0xFF, 0xC3, 0x0F, 0xF0, 0x29, 0xF2, 0x33, 0xF3, 0x55,
+            0xF3, 0x65, 0xF0, 0x15, 0xF4, 0x0A, 0xD0, 0x18, 0xAB,
+        ]
+    );
+}
+
+#[test]
+fn octo_jumps_to_main_unless_it_comes_first() {
+    assert_eq!(octo(": sub return\n: main sub"), [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    assert_eq!(octo(":const A 1\n: main v0 := A"), [0x60, 0x01]);
+}
+
+#[test]
+fn octo_forward_references_and_labels() {
+    let rom = octo(
+        ": main
+            i := data
+            jump end
+            :call data
+         : end
+         : data 1 2",
+    );
+
+    assert_eq!(rom, [0xA2, 0x06, 0x12, 0x06, 0x22, 0x06, 0x01, 0x02]);
+}
+
+#[test]
+fn octo_structured_control_flow() {
+    let rom = octo(
+        ": main
+            if v0 == 1 then v1 := 2
+            if v0 != v1 begin
+                v2 := 3
+            else
+                v2 := 4
+            end
+            loop
+                v3 += 1
+                while v3 != 10
+                if v4 key then v5 := 0
+            again",
+    );
+
+    assert_eq!(
+        rom,
+        [
+            0x40, 0x01, 0x61, 0x02, // if then
+            0x90, 0x10, 0x12, 0x0C, 0x62, 0x03, 0x12, 0x0E, 0x62, 0x04, // if begin else end
+            0x73, 0x01, 0x43, 0x0A, 0x12, 0x1A, 0xE4, 0xA1, 0x65, 0x00, 0x12, 0x0E, // loop while again
+        ]
+    );
+}
+
+#[test]
+fn octo_macros_calc_and_directives() {
+    let rom = octo(
+        ":macro set reg value { reg := value }
+         :calc DOUBLE { 2 * 3 + 1 }
+         :alias counter v7
+         : main
+            set counter DOUBLE
+            :unpack 0xA data
+            :next target v0 := 0
+            :byte { target - 0x200 }
+            :org 0x210
+         : data 0xFF",
+    );
+
+    assert_eq!(
+        rom,
+        [
+            0x67, 0x08, 0x60, 0xA2, 0x61, 0x10, 0x60, 0x00, 0x07, 0, 0, 0, 0, 0, 0, 0, 0xFF,
+        ]
+    );
+}
+
+#[test]
+fn octo_xo_chip_extensions() {
+    let rom = octo(
+        ": main
+            i := long far
+            save v1 - v3  load v2 - v4
+            plane 3  audio  pitch := v0  scroll-up 2
+            saveflags v2  loadflags v2  i := bighex v1  hires  exit
+         :org 0x1000
+         : far",
+    );
+
+    assert_eq!(
+        rom,
+        [
+            0xF0, 0x00, 0x10, 0x00, 0x51, 0x32, 0x52, 0x43, 0xF3, 0x01, 0xF0, 0x02, 0xF0, 0x3A, 0x00, 0xD2, 0xF2, 0x75,
+            0xF2, 0x85, 0xF1, 0x30, 0x00, 0xFF, 0x00, 0xFD,
+        ]
+    );
+}
+
+#[test]
+fn octo_errors_have_line_numbers() {
+    let error = |source: &str| compile_octo(source, "game.8o").unwrap_err();
+
+    assert_eq!(error(": main\n  jump nowhere").to_string(), "game.8o:2: undefined name 'nowhere'");
+    assert_eq!(error(": main\n\n  v0 := 300").message, "300 doesn't fit in a byte");
+    assert_eq!(error(": main loop\n v0 += 1").message, "'loop' without 'again'");
+    assert_eq!(error(": main\nelse").line, 2);
+    assert_eq!(error("v0 := 1").message, "the program has no 'main' label");
+    assert_eq!(error(": main\n if v0 =~ v1 then").message, "unsupported comparison '=~'");
+    assert_eq!(error(": main\n if vf < 3 then").message, "vf can't be compared with '<', the comparison works in vf");
+}
+
+#[test]
+fn octo_ordered_comparisons() {
+    assert_eq!(
+        octo(": main if v3 < v4 then v0 := 1 if v3 >= 5 begin v0 := 2 end"),
+        [
+            0x8F, 0x40, 0x8F, 0x37, 0x4F, 0x00, 0x60, 0x01, // vf := v4  vf =- v3  skip unless vf == 0
+            0x6F, 0x05, 0x8F, 0x37, 0x4F, 0x00, 0x12, 0x12, 0x60, 0x02, // vf := 5  vf =- v3  jump to end if vf == 0
+        ]
+    );
+
+    // Every operator against a register and a byte, each side of the value and on it. The loop
+    // ends by setting v0 to a value the comparison is false for.
+    for (op, holds, end) in [
+        ("<", (|a, b| a < b) as fn(u8, u8) -> bool, 6),
+        (">", |a, b| a > b, 4),
+        ("<=", |a, b| a <= b, 6),
+        (">=", |a, b| a >= b, 4),
+    ] {
+        for a in [4, 5, 6] {
+            let rom = octo(&format!(
+                ": main
+                    v0 := {a}
+                    v1 := 5
+                    if v0 {op} v1 then v2 := 1
+                    if v0 {op} 5 begin v3 := 1 else v3 := 2 end
+                    loop
+                        while v0 {op} 5
+                        v4 := 1
+                        while v0 {op} v1
+                        v5 := 1
+                        v0 := {end}
+                    again
+                    i := 0x300
+                    save v5"
+            ));
+            let mut cpu = CPU::new(Quirks::xo_chip());
+            cpu.load_program(&rom).unwrap();
+            cpu.run_for(Some(100), None).unwrap();
+
+            let expected = holds(a, 5) as u8;
+            let result = &cpu.memory[0x300..0x306];
+            assert_eq!(result[2], expected, "if v0 {} v1 with v0 = {}", op, a);
+            assert_eq!(result[3], 2 - expected, "if v0 {} 5 with v0 = {}", op, a);
+            assert_eq!(result[4], expected, "while v0 {} 5 with v0 = {}", op, a);
+            assert_eq!(result[5], expected, "while v0 {} v1 with v0 = {}", op, a);
+        }
+    }
+}
+
+#[test]
+fn octo_program_runs() {
+    let rom = octo(
+        ": main
+            v0 := 0
+            loop
+                v0 += 1
+                while v0 != 5
+            again
+            i := 0x300
+            save v0",
+    );
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    cpu.load_program(&rom).unwrap();
+
+    cpu.run_for(Some(100), None).unwrap();
+
+    assert_eq!(cpu.memory[0x300], 5);
+}
+
+#[test]
+fn symbols_round_trip_through_text() {
+    let mut symbols = Symbols::default();
+    symbols.labels.insert(0x200, "main".to_string());
+    symbols.lines.insert(0x200, SourceRef { file: "game.asm".to_string(), line: 3, text: "CLS ; start".to_string() });
+
+    assert_eq!(Symbols::parse(&symbols.to_text()).unwrap(), symbols);
+    assert_eq!(Symbols::parse("# other tools\n0x0208 draw\n$20A next").unwrap().labels[&0x208], "draw");
+    assert_eq!(Symbols::parse("label\tnowhere\tx").unwrap_err(), "line 1: bad address");
+}
+
+#[test]
+fn assembler_and_octo_report_symbols() {
+    let dir = std::env::temp_dir().join(format!("chip8-symbols-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("game.asm"), "start: CLS\n\nloop:  JP loop\nDB 1, 2").unwrap();
+
+    let (_, symbols) = assemble_file(&dir.join("game.asm")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(symbols.labels.values().collect::<Vec<_>>(), ["start", "loop"]);
+    assert_eq!(symbols.source_at(0x202).unwrap().text, "loop:  JP loop");
+    assert_eq!(symbols.source_at(0x205).unwrap().line, 4);
+
+    let (_, symbols) = compile_octo(": helper return\n: main\n  v0 := 1 helper", "game.8o").unwrap();
+    assert_eq!(symbols.labels[&0x202], "helper");
+    assert_eq!(symbols.labels[&0x204], "main");
+    // The jump to main at 0x200 belongs to no line
+    assert_eq!(symbols.source_at(0x200), None);
+    assert_eq!(symbols.source_at(0x206).unwrap(), &SourceRef { file: "game.8o".to_string(), line: 3, text: "v0 := 1 helper".to_string() });
+}
+
+#[test]
+fn disassemble_program_uses_symbol_names() {
+    let mut cpu = CPU::new(Quirks::xo_chip());
+    // LD I, 0x208; JP 0x20A; F000 0x0208; data at 0x208
+    cpu.load_program(&[0xA2, 0x08, 0x12, 0x04, 0xF0, 0x00, 0x02, 0x08, 0xFF, 0x00]).unwrap();
+    let symbols = Labels::from([(0x204, "next".to_string()), (0x208, "sprite".to_string()), (0x300, "gone".to_string())]);
+
+    let listing = cpu.disassemble_program(Syntax::Cowgod, &symbols);
+
+    assert_eq!(listing[0].assembly, "LD I, sprite");
+    assert_eq!(listing[1].assembly, "JP next");
+    assert_eq!(listing[2].label.as_deref(), Some("next"));
+    assert_eq!(listing[2].assembly, "LD I, LONG sprite");
+    assert_eq!(listing[3].label.as_deref(), Some("sprite"));
+    assert!(listing.iter().all(|item| item.label.as_deref() != Some("gone")));
+
+    let source = disassembler::to_source(&listing, Syntax::Cowgod);
+    assert_eq!(assemble(&source).unwrap(), &cpu.memory[0x200..0x20A]);
+}
+
+#[test]
+fn annotate_shows_operand_registers() {
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.registers[3] = 0x0A;
+    cpu.registers[4] = 0xFF;
+    cpu.i_register = 0x2A0;
+
+    assert_eq!(cpu.annotate(0x8344), "V3=0x0A V4=0xFF");
+    assert_eq!(cpu.annotate(0xD345), "V3=0x0A V4=0xFF I=0x2A0");
+    assert_eq!(cpu.annotate(0x8334), "V3=0x0A");
+    assert_eq!(cpu.annotate(0xF333), "V3=0x0A I=0x2A0");
+    assert_eq!(cpu.annotate(0x00E0), "");
+}
+
+#[test]
+fn call_and_return_keep_sp_in_step() {
+    let mut cpu = CPU::new(Quirks::default());
+    // 0x200 CALL 0x204, 0x204 CALL 0x208, 0x206 RET, 0x208 RET
+    cpu.load_program(&[0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE]).unwrap();
+
+    cpu.run_for(Some(2), None).unwrap();
+    assert_eq!((cpu.sp, cpu.stack.clone()), (2, vec![0x202, 0x206]));
+
+    cpu.run_for(Some(1), None).unwrap();
+    assert_eq!((cpu.sp, cpu.program_counter), (1, 0x206));
+}
+
+#[test]
+fn symbolize_uses_the_nearest_label_before() {
+    let labels = Labels::from([(0x200, "main".to_string()), (0x2A0, "draw".to_string())]);
+
+    assert_eq!(symbols::symbolize(&labels, 0x200).as_deref(), Some("main"));
+    assert_eq!(symbols::symbolize(&labels, 0x29E).as_deref(), Some("main+0x9E"));
+    assert_eq!(symbols::symbolize(&labels, 0x2A6).as_deref(), Some("draw+0x6"));
+    assert_eq!(symbols::symbolize(&labels, 0x1FE), None);
+}
+
+// A two-program database in the community format; the ROMs are [0x00, 0xE0] and [0x12, 0x00]
+fn sample_database() -> Database {
+    let cls = database::sha1_hex(&[0x00, 0xE0]);
+    let jump = database::sha1_hex(&[0x12, 0x00]);
+    let programs = format!(
+        r##"[
+            {{
+                "title": "Clear",
+                "description": "Clears the screen",
+                "release": "1978",
+                "authors": ["A", "B"],
+                "roms": {{
+                    "{cls}": {{
+                        "platforms": ["megachip8", "superchip"],
+                        "quirkyPlatforms": {{ "superchip": {{ "wrap": true, "memoryIncrementByX": true }} }},
+                        "tickrate": 30,
+                        "keys": {{ "left": 4, "right": 6, "a": 5 }},
+                        "colors": {{ "pixels": ["#000000", "#ffcc00"], "buzzer": "#990000" }}
+                    }}
+                }}
+            }},
+            {{ "title": "Loop", "images": [] }}
+        ]"##
+    );
+    let hashes = format!(r#"{{ "{}": 0, "{}": 1 }}"#, cls.to_uppercase(), jump);
+    Database::parse(&programs, &hashes).unwrap()
+}
+
+#[test]
+fn database_hashes_roms_with_sha1() {
+    assert_eq!(database::sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn database_lookup_finds_metadata_and_settings() {
+    let info = sample_database().lookup(&[0x00, 0xE0]).unwrap();
+
+    assert_eq!(info.title, "Clear");
+    assert_eq!(info.authors, vec!["A", "B"]);
+    assert_eq!(info.description.as_deref(), Some("Clears the screen"));
+    assert_eq!(info.release.as_deref(), Some("1978"));
+    // MEGA-CHIP isn't supported, so the next platform is used, with the ROM's overrides
+    assert_eq!(info.platform.as_deref(), Some("superchip"));
+    assert_eq!(info.platform_name(), Some("SUPER-CHIP 1.1"));
+    let quirks = info.quirks.unwrap();
+    assert!(!quirks.clipping);
+    assert_eq!(quirks.memory_increment, MemoryIncrement::X);
+    assert_eq!(Quirks { clipping: true, memory_increment: MemoryIncrement::None, ..quirks }, Quirks::superchip());
+    assert_eq!(info.speed(), Some(ClockSpeed::CyclesPerFrame(30)));
+    assert_eq!(info.keys.get("a"), Some(&5));
+    assert_eq!(info.colors, [0x000000, 0xFFCC00]);
+}
+
+#[test]
+fn database_lookup_without_rom_details() {
+    let database = sample_database();
+    let info = database.lookup(&[0x12, 0x00]).unwrap();
+
+    assert_eq!(info.title, "Loop");
+    assert!(info.authors.is_empty());
+    assert_eq!((info.platform, info.quirks, info.tickrate), (None, None, None));
+    assert!(info.colors.is_empty());
+
+    assert_eq!(database.lookup(&[0x12, 0x02]), None);
+}
+
+#[test]
+fn rom_info_applies_quirks_and_speed() {
+    let info = sample_database().lookup(&[0x00, 0xE0]).unwrap();
+    let mut cpu = CPU::new(Quirks::default());
+
+    info.apply(&mut cpu, Overrides::default());
+    assert_eq!(Some(cpu.quirks), info.quirks);
+    assert_eq!(cpu.clock.speed, ClockSpeed::CyclesPerFrame(30));
+
+    // A ROM without recommendations leaves the CPU as it was
+    let mut cpu = CPU::new(Quirks::cosmac_vip());
+    sample_database().lookup(&[0x12, 0x00]).unwrap().apply(&mut cpu, Overrides::default());
+    assert_eq!((cpu.quirks, cpu.clock), (Quirks::cosmac_vip(), Clock::default()));
+}
+
+#[test]
+fn rom_info_leaves_command_line_choices_alone() {
+    let info = sample_database().lookup(&[0x00, 0xE0]).unwrap();
+
+    // Only --speed given: the database still picks the quirks
+    let overrides = Overrides { quirks: None, speed: Some(ClockSpeed::Hz(1000)) };
+    let mut cpu = CPU::new(overrides.quirks());
+    info.apply(&mut cpu, overrides);
+    assert_eq!(Some(cpu.quirks), info.quirks);
+    assert_eq!(cpu.clock.speed, ClockSpeed::Hz(1000));
+
+    // Only --quirks given: the database still picks the speed
+    let overrides = Overrides { quirks: Some(Quirks::superchip()), speed: None };
+    let mut cpu = CPU::new(overrides.quirks());
+    info.apply(&mut cpu, overrides);
+    assert_eq!(cpu.quirks, Quirks::superchip());
+    assert_eq!(cpu.clock.speed, ClockSpeed::CyclesPerFrame(30));
+
+    assert_eq!((Overrides::default().quirks(), Overrides::default().clock()), (Quirks::modern(), Clock::default()));
+}
+
+#[test]
+fn database_rejects_bad_files() {
+    assert!(Database::parse("[]", "{ \"00\": 0 }").err().unwrap().contains("only 0"));
+    assert!(Database::parse("{", "{}").err().unwrap().starts_with(database::PROGRAMS_FILE));
+    assert!(Database::parse("[]", "[]").err().unwrap().starts_with(database::HASHES_FILE));
+}
+
+#[test]
+fn bundled_database_covers_the_bundled_roms() {
+    assert!(Database::load(std::path::Path::new("no-such-database")).unwrap().lookup(&[0x00, 0xE0]).is_none());
+
+    let database = Database::load(std::path::Path::new("database")).unwrap();
+    let ibm = std::fs::read("roms/2-ibm-logo.ch8").unwrap();
+    assert_eq!(database.lookup(&ibm).unwrap().title, "IBM Logo");
+}
+
+#[test]
+fn rom_settings_are_stored_by_hash() {
+    let dir = std::env::temp_dir().join(format!("chip8-settings-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rom-settings.json");
+
+    let mut store = SettingsStore::load(&path).unwrap();
+    assert_eq!(store.get("abc"), RomSettings::default());
+
+    let settings = RomSettings {
+        cycles_per_frame: Some(20),
+        quirks: Some(Quirks::chip48()),
+        palette: Some([0x000000, 0xFFFFFF, 0xFF6600, 0x662200]),
+        keys: [("up".to_string(), 5)].into(),
+        breakpoints: vec!["break 0x22a".to_string()],
+        last_slot: Some(2),
+    };
+    store.set("abc", settings.clone()).unwrap();
+    store.set("def", RomSettings { last_slot: Some(1), ..RomSettings::default() }).unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    assert!(text.contains("\"#FF6600\""));
+    let mut store = SettingsStore::load(&path).unwrap();
+    assert_eq!(store.get("abc"), settings);
+
+    // Settings back at their defaults aren't kept
+    store.set("def", RomSettings::default()).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let store = SettingsStore::load(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!text.contains("def"));
+    assert_eq!(store.get("abc"), settings);
+}
+
+#[test]
+fn rom_settings_apply_speed_and_quirks() {
+    let mut cpu = CPU::new(Quirks::default());
+    RomSettings::default().apply(&mut cpu);
+    assert_eq!((cpu.quirks, cpu.clock), (Quirks::default(), Clock::default()));
+
+    RomSettings { cycles_per_frame: Some(9), quirks: Some(Quirks::superchip()), ..RomSettings::default() }.apply(&mut cpu);
+    assert_eq!((cpu.quirks, cpu.clock.speed), (Quirks::superchip(), ClockSpeed::CyclesPerFrame(9)));
+}
+
+#[test]
+fn rom_settings_reject_bad_colours() {
+    assert_eq!(settings::parse_color("#1a2B3c"), Some(0x1A2B3C));
+    assert_eq!(settings::parse_color("1A2B3C"), Some(0x1A2B3C));
+    assert_eq!(settings::parse_color("#+12345"), None);
+    assert_eq!(settings::parse_color("#FFF"), None);
+
+    let dir = std::env::temp_dir().join(format!("chip8-bad-settings-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rom-settings.json");
+    std::fs::write(&path, r##"{ "abc": { "palette": ["#000000", "white", "#FF0000", "#00FF00"] } }"##).unwrap();
+    let error = SettingsStore::load(&path).err().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(error.contains("bad colour 'white'"), "{}", error);
+}
+
+#[test]
+fn keymap_layouts_cover_the_whole_keypad() {
+    for name in keymap::LAYOUT_NAMES {
+        let keymap = Keymap::layout(name).unwrap();
+        let mut keys: Vec<u8> = keymap.bindings().map(|(_, key)| key).collect();
+        keys.sort();
+        assert_eq!(keys, (0..16).collect::<Vec<u8>>(), "{}", name);
+    }
+
+    assert_eq!(Keymap::layout("QWERTY").unwrap().key("Q"), Some(0x4));
+    assert_eq!(Keymap::layout("azerty").unwrap().key("a"), Some(0x4));
+    assert_eq!(Keymap::layout("azerty").unwrap().key("w"), Some(0xA));
+    assert_eq!(Keymap::layout("numpad").unwrap().key("num7"), Some(0x7));
+    assert_eq!(Keymap::layout("numpad").unwrap().key("numenter"), Some(0xE));
+    assert_eq!(Keymap::layout("dvorak"), None);
+}
+
+#[test]
+fn keymap_binds_any_host_key() {
+    let mut keymap = Keymap::layout("qwerty").unwrap();
+
+    keymap.bind("Up", 0x5).unwrap();
+    keymap.bind("num+", 0xD).unwrap();
+    keymap.bind(";", 0x0).unwrap();
+    assert_eq!((keymap.key("up"), keymap.key("num+"), keymap.key(";")), (Some(0x5), Some(0xD), Some(0x0)));
+
+    assert!(keymap.bind("space", 0x1).is_err());
+    assert!(keymap.bind("num10", 0x1).is_err());
+    assert!(keymap.bind("k", 0x10).unwrap_err().contains("0xF"));
+
+    keymap.apply(&[("q".to_string(), None), ("k".to_string(), Some(0x4))].into()).unwrap();
+    assert_eq!((keymap.key("q"), keymap.key("k")), (None, Some(0x4)));
+}
+
+#[test]
+fn keymap_config_layers_rom_overrides() {
+    let config = KeymapConfig::parse(
+        r#"{
+            "layout": "azerty",
+            "keys": { "up": 5, "v": null }
+        }"#,
+    )
+    .unwrap();
+    let controls = keymap::controls(&[("up".to_string(), 2), ("left".to_string(), 7), ("a".to_string(), 6), ("b".to_string(), 3)].into());
+    assert_eq!(controls.len(), 3);
+    let no_keys = std::collections::BTreeMap::new();
+
+    let (keymap, errors) = config.keymap(&controls, &no_keys);
+    assert!(errors.is_empty());
+    assert_eq!(keymap.key("a"), Some(0x4));
+    assert_eq!(keymap.key("v"), None);
+    // The config's keys win over the database's controls
+    assert_eq!((keymap.key("up"), keymap.key("left"), keymap.key("enter")), (Some(0x5), Some(0x7), Some(0x6)));
+
+    // and the ROM's own bindings win over both
+    let (pong, errors) = config.keymap(&controls, &[("up".to_string(), 1), ("k".to_string(), 12)].into());
+    assert!(errors.is_empty());
+    assert_eq!((pong.key("up"), pong.key("k")), (Some(0x1), Some(0xC)));
+
+    // Bad bindings from a hand-edited settings file are reported, and the good ones still made
+    let (keymap, errors) = config.keymap(&Default::default(), &[("tab".to_string(), 1), ("k".to_string(), 16), ("j".to_string(), 2)].into());
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().any(|e| e.contains("unknown host key 'tab'")), "{:?}", errors);
+    assert_eq!((keymap.key("k"), keymap.key("j")), (None, Some(0x2)));
+}
+
+#[test]
+fn keymap_config_is_checked_when_loaded() {
+    assert_eq!(KeymapConfig::load(std::path::Path::new("no-such-keymap.json")).unwrap(), KeymapConfig::default());
+    assert_eq!(KeymapConfig::parse("{}").unwrap().keymap(&Default::default(), &Default::default()).0, Keymap::layout("qwerty").unwrap());
+
+    assert!(KeymapConfig::parse(r#"{ "layout": "dvorak" }"#).unwrap_err().contains("unknown layout"));
+    assert!(KeymapConfig::parse(r#"{ "keys": { "tab": 1 } }"#).unwrap_err().contains("unknown host key 'tab'"));
+    assert!(KeymapConfig::parse(r#"{ "layuot": "azerty" }"#).is_err());
+    // Per-ROM keys live in the settings file now
+    assert!(KeymapConfig::parse(r#"{ "roms": { "a.ch8": { "k": 1 } } }"#).is_err());
+}
+
+// Keeps what it's given where the test can still see it once Audio owns the sink
+#[derive(Clone, Default)]
+struct SharedSink(std::rc::Rc<std::cell::RefCell<Vec<f32>>>);
+
+impl AudioSink for SharedSink {
+    fn write(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        self.0.borrow_mut().extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+struct FailingSink;
+
+impl AudioSink for FailingSink {
+    fn write(&mut self, _samples: &[f32]) -> std::io::Result<()> {
+        Err(std::io::Error::other("disk full"))
+    }
+}
+
+#[test]
+fn audio_plays_a_tone_while_on() {
+    let sink = SharedSink::default();
+    // 441 Hz is exactly 100 samples per cycle
+    let settings = AudioSettings { volume: 0.5, frequency: 441, waveform: Waveform::Square };
+    let mut audio = Audio::new(settings, Box::new(sink.clone()));
+
+    audio.frame(true);
+    audio.frame(false);
+
+    let samples = sink.0.borrow();
+    assert_eq!(samples.len(), 2 * audio::SAMPLES_PER_FRAME);
+    assert_eq!(audio::SAMPLES_PER_FRAME, 735);
+    assert!(samples[..50].iter().all(|&sample| sample == 0.5));
+    assert!(samples[50..100].iter().all(|&sample| sample == -0.5));
+    assert_eq!(samples[100], 0.5);
+    assert!(samples[audio::SAMPLES_PER_FRAME..].iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn audio_bell_rings_once_per_tone() {
+    let mut bells = vec![];
+    let mut sink = BellSink::new(&mut bells);
+    let (tone, silence) = ([0.0, 0.25, -0.25], [0.0; 3]);
+
+    for frame in [&tone, &tone, &silence, &tone, &silence, &silence] {
+        sink.write(frame).unwrap();
+    }
+
+    assert_eq!(bells, b"\x07\x07");
+}
+
+#[test]
+fn audio_waveforms() {
+    let mut peaks = vec![];
+    for name in audio::WAVEFORM_NAMES {
+        let sink = SharedSink::default();
+        let waveform = Waveform::from_name(name).unwrap();
+        let mut audio = Audio::new(AudioSettings { volume: 1.0, frequency: 441, waveform }, Box::new(sink.clone()));
+        audio.frame(true);
+
+        let samples = sink.0.borrow();
+        assert!(samples.iter().all(|sample| (-1.0..=1.0).contains(sample)), "{}", name);
+        peaks.push((samples[0], samples[25], samples[50]));
+    }
+
+    assert_eq!(peaks[0], (1.0, 1.0, -1.0));
+    assert_eq!(peaks[1], (-1.0, 0.0, 1.0));
+    assert_eq!(peaks[2], (-1.0, -0.5, 0.0));
+    assert_eq!(peaks[3].0, 0.0);
+    assert!((peaks[3].1 - 1.0).abs() < 1e-4 && peaks[3].2.abs() < 1e-4);
+    assert_eq!(Waveform::from_name("noise"), None);
+}
+
+#[test]
+fn sound_timer_drives_audio() {
+    let sink = SharedSink::default();
+    let mut cpu = CPU::new(Quirks::default());
+    cpu.audio = Audio::new(AudioSettings::default(), Box::new(sink.clone()));
+    // LD V0, 2; LD ST, V0; JP 0x204
+    cpu.load_program(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+    cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(2));
+
+    cpu.run_for(None, Some(4)).unwrap();
+
+    let samples = sink.0.borrow();
+    let loud: Vec<bool> = samples.chunks(audio::SAMPLES_PER_FRAME).map(|frame| frame.iter().any(|&sample| sample != 0.0)).collect();
+    assert_eq!(loud, vec![true, true, false, false]);
+}
+
+#[test]
+fn wav_writer_writes_16_bit_pcm() {
+    let path = std::env::temp_dir().join(format!("chip8-audio-{}.wav", std::process::id()));
+    let mut wav = WavWriter::create(&path).unwrap();
+    wav.write(&[0.0, 1.0, -1.0]).unwrap();
+    wav.write(&[0.5]).unwrap();
+    wav.finish().unwrap();
+    drop(wav);
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(bytes.len(), 44 + 8);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 36 + 8);
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), audio::SAMPLE_RATE);
+    assert_eq!(&bytes[36..40], b"data");
+    assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 8);
+
+    let samples: Vec<i16> = bytes[44..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect();
+    assert_eq!(samples, vec![0, i16::MAX, -i16::MAX, i16::MAX / 2]);
+}
+
+#[test]
+fn audio_reports_a_failing_sink_once() {
+    let mut audio = Audio::new(AudioSettings::default(), Box::new(FailingSink));
+    audio.frame(true);
+    audio.frame(true);
+
+    assert_eq!(audio.finish(), Err("audio: disk full".to_string()));
+    assert_eq!(audio.finish(), Ok(()));
+}
@@ -0,0 +1,241 @@
+// Metadata from the CHIP-8 community database (https://github.com/chip-8/chip-8-database): what
+// a ROM is, who wrote it and how it wants to be run, found by the SHA-1 of its bytes. A local
+// copy lives in database/ in the same layout as upstream; of its files only programs.json and
+// sha1-hashes.json are read.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use super::quirks::MemoryIncrement;
+use super::settings::parse_color;
+use super::{Clock, ClockSpeed, CPU, Quirks};
+
+pub const PROGRAMS_FILE: &str = "programs.json";
+pub const HASHES_FILE: &str = "sha1-hashes.json";
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    description: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    #[serde(default)]
+    colors: Colors,
+}
+
+// Only the pixel colours are used; the buzzer and silence colours are for emulators that flash
+// the screen border while the sound plays
+#[derive(Default, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+// Where a ROM differs from its platform's usual behaviour. The database words some flags the
+// other way round from Quirks: wrap is !clipping and logic is vf_reset.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift {
+            quirks.shift = shift;
+        }
+        if self.memory_leave_i_unchanged == Some(true) {
+            quirks.memory_increment = MemoryIncrement::None;
+        } else if self.memory_increment_by_x == Some(true) {
+            quirks.memory_increment = MemoryIncrement::X;
+        } else if self.memory_leave_i_unchanged.is_some() || self.memory_increment_by_x.is_some() {
+            quirks.memory_increment = MemoryIncrement::XPlusOne;
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.clipping = !wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jump_vx = jump;
+        }
+        if let Some(vblank) = self.vblank {
+            quirks.display_wait = vblank;
+        }
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+}
+
+// The quirks profile for a database platform id, or None for platforms this emulator can't run
+pub fn platform_quirks(platform: &str) -> Option<Quirks> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(Quirks::cosmac_vip()),
+        "modernChip8" => Some(Quirks::modern()),
+        "chip48" => Some(Quirks::chip48()),
+        "superchip1" | "superchip" => Some(Quirks::superchip()),
+        "xochip" => Some(Quirks::xo_chip()),
+        _ => None,
+    }
+}
+
+pub fn platform_name(platform: &str) -> &str {
+    match platform {
+        "originalChip8" => "COSMAC VIP",
+        "hybridVIP" => "COSMAC VIP (hybrid)",
+        "modernChip8" => "Modern CHIP-8",
+        "chip8x" => "CHIP-8X",
+        "chip48" => "CHIP-48",
+        "superchip1" => "SUPER-CHIP 1.0",
+        "superchip" => "SUPER-CHIP 1.1",
+        "megachip8" => "MEGA-CHIP",
+        "xochip" => "XO-CHIP",
+        other => other,
+    }
+}
+
+// Everything the database knows about one ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    pub platform: Option<String>, // the first listed platform this emulator can run, by id
+    pub quirks: Option<Quirks>,   // the platform's profile with this ROM's overrides
+    pub tickrate: Option<u32>,    // instructions per frame
+    pub keys: BTreeMap<String, u8>, // "up", "left", "a"... to the CHIP-8 key the game uses for it
+    pub colors: Vec<u32>,           // pixel colours by plane bits, two for CHIP-8 and four for XO-CHIP
+}
+
+impl RomInfo {
+    pub fn speed(&self) -> Option<ClockSpeed> {
+        self.tickrate.map(ClockSpeed::CyclesPerFrame)
+    }
+
+    pub fn platform_name(&self) -> Option<&str> {
+        self.platform.as_deref().map(platform_name)
+    }
+
+    // Run a CPU with the recommended quirks and speed, where the database has them and the
+    // command line didn't pick its own
+    pub fn apply(&self, cpu: &mut CPU, overrides: Overrides) {
+        if let Some(quirks) = overrides.quirks.or(self.quirks) {
+            cpu.quirks = quirks;
+        }
+        if let Some(speed) = overrides.speed.or(self.speed()) {
+            cpu.clock = Clock::new(speed);
+        }
+    }
+}
+
+// The quirks and speed given on the command line, which win over the database's
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub quirks: Option<Quirks>,
+    pub speed: Option<ClockSpeed>,
+}
+
+impl Overrides {
+    // For ROMs the database doesn't know: the command line's choice, or the built-in default
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or_default()
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.speed.map(Clock::new).unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>, // SHA-1 of a ROM to its index in programs
+}
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Database {
+    pub fn parse(programs: &str, hashes: &str) -> Result<Database, String> {
+        let programs: Vec<Program> =
+            serde_json::from_str(programs).map_err(|e| format!("{}: {}", PROGRAMS_FILE, e))?;
+        let hashes: HashMap<String, usize> =
+            serde_json::from_str(hashes).map_err(|e| format!("{}: {}", HASHES_FILE, e))?;
+
+        if let Some((hash, index)) = hashes.iter().find(|&(_, &index)| index >= programs.len()) {
+            return Err(format!("{}: {} points at program {}, but there are only {}", HASHES_FILE, hash, index, programs.len()));
+        }
+
+        let hashes = hashes.into_iter().map(|(hash, index)| (hash.to_lowercase(), index)).collect();
+        Ok(Database { programs, hashes })
+    }
+
+    // A directory that doesn't exist is an empty database, so ROMs still run without one
+    pub fn load(dir: &Path) -> Result<Database, String> {
+        if !dir.exists() {
+            return Ok(Database::default());
+        }
+
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        Database::parse(&read(PROGRAMS_FILE)?, &read(HASHES_FILE)?).map_err(|e| format!("{}: {}", dir.display(), e))
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1_hex(rom);
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let entry = program.roms.get(&hash);
+
+        let platform = entry.and_then(|entry| entry.platforms.iter().find(|platform| platform_quirks(platform).is_some()));
+        let quirks = platform.and_then(|platform| {
+            let mut quirks = platform_quirks(platform)?;
+            if let Some(overrides) = entry.and_then(|entry| entry.quirky_platforms.get(platform)) {
+                overrides.apply(&mut quirks);
+            }
+            Some(quirks)
+        });
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            platform: platform.cloned(),
+            quirks,
+            tickrate: entry.and_then(|entry| entry.tickrate),
+            keys: entry.map(|entry| entry.keys.clone()).unwrap_or_default(),
+            // A palette with a colour that can't be read isn't used at all
+            colors: entry
+                .and_then(|entry| entry.colors.pixels.iter().take(4).map(|color| parse_color(color)).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
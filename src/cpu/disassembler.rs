@@ -1,97 +1,497 @@
+use std::collections::{BTreeMap, HashSet};
+
 use super::CPU;
-use std::fmt::Write;
+use super::instruction::Instruction;
+
+// Where programs are loaded, and so where the control-flow walk starts
+const ENTRY_POINT: usize = 0x200;
+
+// Label names for branch targets, by address
+pub type Labels = BTreeMap<u16, String>;
+
+// Output dialects for the listing. Classic is this emulator's own notation; the others follow
+// the assemblers of the same name so a listing can be fed back into them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Classic,
+    Octo,
+    Cowgod,
+    Chipper,
+}
+
+pub const SYNTAX_NAMES: [&str; 4] = ["classic", "octo", "cowgod", "chipper"];
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name.to_lowercase().as_str() {
+            "classic" => Some(Syntax::Classic),
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            "chipper" => Some(Syntax::Chipper),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syntax::Classic => "classic",
+            Syntax::Octo => "octo",
+            Syntax::Cowgod => "cowgod",
+            Syntax::Chipper => "chipper",
+        }
+    }
+
+    // Cycles through every dialect, for a key that switches between them
+    pub fn next(&self) -> Syntax {
+        match self {
+            Syntax::Classic => Syntax::Octo,
+            Syntax::Octo => Syntax::Cowgod,
+            Syntax::Cowgod => Syntax::Chipper,
+            Syntax::Chipper => Syntax::Classic,
+        }
+    }
+
+    // Chipper writes hex as #1F, everything else as 0x1F
+    fn hex(&self, value: u16, digits: usize) -> String {
+        match self {
+            Syntax::Chipper => format!("#{:0digits$X}", value),
+            _ => format!("0x{:0digits$X}", value),
+        }
+    }
+}
 
 pub struct Dissemble {
     pub memory_location: u16,
-    pub opcode: u16,
+    pub opcode: u16, // a single byte for data
     pub assembly: String,
+    pub label: Option<String>,
+    pub data: bool,
 }
 
 impl CPU {
-    pub fn disassemble_program(&mut self) -> Vec<Dissemble> {
-        let mut vec = Vec::<Dissemble>::new();
+    // Only bytes reachable from the entry point are decoded as code; everything else is listed
+    // a byte at a time as data, which also keeps the listing in step across odd-aligned code.
+    // `symbols` are names from a symbol file; they win over the made-up loc_/sub_ ones and can
+    // name data as well as code.
+    pub fn disassemble_program(&self, syntax: Syntax, symbols: &Labels) -> Vec<Dissemble> {
+        let (starts, targets) = self.trace_code();
+
+        // Lay out the listing first, so labels are only made for addresses that get a line
+        let mut layout = vec![];
+        let mut addr = ENTRY_POINT;
+        while addr < self.program_size {
+            let size = if starts[addr] { self.instruction_size_at(addr) } else { 1 };
+            layout.push((addr, size));
+            addr += size;
+        }
+
+        let lines: HashSet<u16> = layout.iter().map(|&(addr, _)| addr as u16).collect();
+        let instructions: HashSet<u16> =
+            layout.iter().filter(|&&(_, size)| size > 1).map(|&(addr, _)| addr as u16).collect();
 
-        let mut counter: u16 = 0x200;
+        let mut labels: Labels = targets.into_iter().filter(|(target, _)| instructions.contains(target)).collect();
+        labels.extend(
+            symbols
+                .iter()
+                .filter(|(target, _)| lines.contains(target))
+                .map(|(target, name)| (*target, name.clone())),
+        );
 
-        while counter < self.program_size {
-            self.fetch_opcode(counter as usize);
+        layout
+            .into_iter()
+            .map(|(addr, size)| {
+                let label = labels.get(&(addr as u16)).cloned();
+                if size == 1 {
+                    let byte = self.memory[addr];
+                    return Dissemble {
+                        memory_location: addr as u16,
+                        opcode: byte as u16,
+                        assembly: data_byte(syntax, byte),
+                        label,
+                        data: true,
+                    };
+                }
+
+                let opcode = self.word_at(addr);
+                let mut diss = decode(opcode, addr as u16, syntax, &labels);
+                // F000 NNNN is the only 4-byte instruction; fold its operand into the listing. When
+                // the program ends before the operand, the word can only be written back as data.
+                match size {
+                    4 => diss.assembly = long_mnemonic(syntax, Some(self.word_at(addr + 2)), &labels),
+                    _ if syntax != Syntax::Classic && matches!(Instruction::decode(opcode), Ok(Instruction::LoadILong)) => {
+                        diss.assembly = data_word(syntax, opcode)
+                    }
+                    _ => {}
+                }
+                diss.label = label;
+                diss
+            })
+            .collect()
+    }
 
-            let diss = decode(self.opcode, counter);
-            vec.push(diss);
-            counter += 2;
+    fn word_at(&self, addr: usize) -> u16 {
+        (self.memory[addr] as u16) << 8 | self.memory[addr + 1] as u16
+    }
+
+    // F000 only takes its address word along when the word is part of the program
+    fn instruction_size_at(&self, addr: usize) -> usize {
+        match Instruction::decode(self.word_at(addr)) {
+            Ok(instruction) if addr + 3 < self.program_size => instruction.size() as usize,
+            _ => 2,
         }
+    }
+
+    // The current values of the registers an instruction works on, e.g. "V3=0x0A I=0x2A0", for
+    // annotating the line the debugger is stopped on
+    pub fn annotate(&self, opcode: u16) -> String {
+        let Ok(instruction) = Instruction::decode(opcode) else {
+            return String::new();
+        };
 
-        self.program_counter = 0x200;
+        let (registers, uses_i): (Vec<u8>, bool) = match instruction {
+            Instruction::SkipEqReg { x, y }
+            | Instruction::Move { x, y }
+            | Instruction::Or { x, y }
+            | Instruction::And { x, y }
+            | Instruction::Xor { x, y }
+            | Instruction::Add { x, y }
+            | Instruction::Sub { x, y }
+            | Instruction::ShiftRight { x, y }
+            | Instruction::SubReverse { x, y }
+            | Instruction::ShiftLeft { x, y }
+            | Instruction::SkipNeReg { x, y } => (vec![x, y], false),
+            Instruction::StoreRange { x, y } | Instruction::LoadRange { x, y } | Instruction::Draw { x, y, .. } => {
+                (vec![x, y], true)
+            }
+            Instruction::SkipEqImm { x, .. }
+            | Instruction::SkipNeImm { x, .. }
+            | Instruction::LoadImm { x, .. }
+            | Instruction::AddImm { x, .. }
+            | Instruction::Random { x, .. }
+            | Instruction::SkipKey { x }
+            | Instruction::SkipNotKey { x }
+            | Instruction::GetDelay { x }
+            | Instruction::WaitKey { x }
+            | Instruction::SetDelay { x }
+            | Instruction::SetSound { x }
+            | Instruction::Font { x }
+            | Instruction::BigFont { x }
+            | Instruction::Pitch { x }
+            | Instruction::SaveFlags { x }
+            | Instruction::LoadFlags { x } => (vec![x], false),
+            Instruction::AddI { x } | Instruction::Bcd { x } | Instruction::Store { x } | Instruction::Load { x } => {
+                (vec![x], true)
+            }
+            Instruction::JumpOffset { .. } => (vec![0], false),
+            Instruction::LoadI { .. } | Instruction::LoadILong | Instruction::Audio => (vec![], true),
+            _ => (vec![], false),
+        };
 
-        return vec;
+        let mut values: Vec<String> = vec![];
+        for register in registers {
+            let value = format!("V{:X}={:#04X}", register, self.registers[register as usize]);
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        if uses_i {
+            values.push(format!("I={:#05X}", self.i_register));
+        }
+        values.join(" ")
+    }
+
+    // Recursive descent from the entry point along every jump, call and skip. Returns which
+    // addresses start an instruction, and a label for each branch target.
+    fn trace_code(&self) -> (Vec<bool>, Labels) {
+        let end = self.program_size;
+        let mut starts = vec![false; end.max(ENTRY_POINT)];
+        let mut labels = Labels::new();
+        let mut pending = vec![ENTRY_POINT];
+
+        while let Some(addr) = pending.pop() {
+            if addr < ENTRY_POINT || addr + 1 >= end || starts[addr] {
+                continue;
+            }
+            let Ok(instruction) = Instruction::decode(self.word_at(addr)) else {
+                continue;
+            };
+            starts[addr] = true;
+
+            let next = addr + self.instruction_size_at(addr);
+            match instruction {
+                Instruction::Jump { nnn } | Instruction::JumpOffset { nnn } => {
+                    labels.entry(nnn).or_insert_with(|| format!("loc_{:03X}", nnn));
+                    pending.push(nnn as usize);
+                }
+                Instruction::Call { nnn } => {
+                    labels.insert(nnn, format!("sub_{:03X}", nnn));
+                    pending.push(nnn as usize);
+                    pending.push(next);
+                }
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEqReg { .. }
+                | Instruction::SkipNeReg { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNotKey { .. } => {
+                    pending.push(next);
+                    if next + 1 < end {
+                        pending.push(next + self.instruction_size_at(next));
+                    }
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        (starts, labels)
+    }
+}
+
+// The listing as source text, each label on a line of its own. Cowgod and Chipper listings
+// assemble back to the same bytes with cpu::assembler.
+pub fn to_source(listing: &[Dissemble], syntax: Syntax) -> String {
+    let mut out = String::new();
+    for item in listing {
+        if let Some(label) = &item.label {
+            match syntax {
+                Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+                _ => out.push_str(&format!("{}:\n", label)),
+            }
+        }
+        out.push_str(&format!("    {}\n", item.assembly));
+    }
+    out
+}
+
+// One byte of data, with its bits drawn as a sprite row
+fn data_byte(syntax: Syntax, byte: u8) -> String {
+    let bitmap: String = (0..8).rev().map(|bit| if byte >> bit & 1 == 1 { '█' } else { '.' }).collect();
+    match syntax {
+        Syntax::Classic => format!("DATA {:#04X} {}", byte, bitmap),
+        Syntax::Octo => format!("0x{:02X} # {}", byte, bitmap),
+        Syntax::Cowgod | Syntax::Chipper => format!("DB {} ; {}", syntax.hex(byte as u16, 2), bitmap),
+    }
+}
+
+// A whole word of data, for the assemblers that take one back
+fn data_word(syntax: Syntax, word: u16) -> String {
+    match syntax {
+        Syntax::Octo => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+        _ => format!("DW {}", syntax.hex(word, 4)),
     }
 }
 
-pub fn decode(opcode: u16, memory_location: u16) -> Dissemble {
-    let mut diss = Dissemble {
-        memory_location: memory_location.clone(),
-        opcode: opcode.clone(),
-        assembly: "".to_string(),
+// Branch targets that have a label are written with it instead of the address
+pub fn decode(opcode: u16, memory_location: u16, syntax: Syntax, labels: &Labels) -> Dissemble {
+    let assembly = match (Instruction::decode(opcode), syntax) {
+        (Ok(instruction), Syntax::Classic) => classic(instruction, labels),
+        (Ok(instruction), Syntax::Octo) => octo(instruction, labels),
+        (Ok(instruction), Syntax::Cowgod | Syntax::Chipper) => cowgod(instruction, syntax, labels),
+        (Err(unknown), Syntax::Classic) => unknown.to_string(),
+        // Emit the word as data so the listing still assembles
+        (Err(_), _) => data_word(syntax, opcode),
     };
 
-    let nibble = opcode & 0xF000;
+    Dissemble { memory_location, opcode, assembly, label: None, data: false }
+}
+
+// F000 NNNN, with the address word when it's available
+fn long_mnemonic(syntax: Syntax, operand: Option<u16>, labels: &Labels) -> String {
+    let mnemonic = match syntax {
+        Syntax::Classic => "I=LONG",
+        Syntax::Octo => "i := long",
+        Syntax::Cowgod | Syntax::Chipper => "LD I, LONG",
+    };
+    match (syntax, operand) {
+        (_, Some(address)) if labels.contains_key(&address) => format!("{} {}", mnemonic, labels[&address]),
+        (Syntax::Classic, Some(address)) => format!("{} {:#X}", mnemonic, address),
+        (_, Some(address)) => format!("{} {}", mnemonic, syntax.hex(address, 4)),
+        (_, None) => mnemonic.to_string(),
+    }
+}
 
-    let x: u8 = ((opcode & 0x0F00) >> 8) as u8;
-    let y: u8 = ((opcode & 0x00F0) >> 4) as u8;
-    let nn: u8 = (opcode & 0x00FF) as u8;
-    let nnn: u16 = opcode & 0x0FFF;
-    let n: u8 = (opcode & 0x000F) as u8;
+fn classic(instruction: Instruction, labels: &Labels) -> String {
+    let addr = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| format!("{:#X}", nnn));
 
-    match nibble {
-        0x0000 => match opcode & 0x0FFF {
-            0x00E0 => diss.assembly = "ERASE".to_string(),
-            0x00EE => diss.assembly = "Return".to_string(),
-            _ if nnn != 0x00E0 && nnn != 0x00EE => write!(diss.assembly, "NOP {:#X}", nnn).unwrap(),
-            _ => diss.assembly = "Not implemented yet".to_string(),
-        },
-        0x1000 => write!(diss.assembly, "GOTO {:#X}", nnn).unwrap(),
-        0x2000 => write!(diss.assembly, "DO {:#X}", nnn).unwrap(),
-        0x3000 => write!(diss.assembly, "SKF V{}={:#X}", x, nn).unwrap(),
-        0x4000 => write!(diss.assembly, "SKF V{}≠{:#X}", x, nn).unwrap(),
-        0x5000 => write!(diss.assembly, "SKF V{}=V{}", x, y).unwrap(),
-        0x6000 => write!(diss.assembly, "V{}={:#X}", x, nn).unwrap(),
-        0x7000 => write!(diss.assembly, "V{}+={:#X}", x, nn).unwrap(),
-        0x8000 => match opcode & 0xF00F {
-            0x8000 => write!(diss.assembly, "V{}=V{}", x, y).unwrap(),
-            0x8001 => write!(diss.assembly, "V{}|=V{}", x, y).unwrap(),
-            0x8002 => write!(diss.assembly, "V{}&=V{}", x, y).unwrap(),
-            0x8003 => write!(diss.assembly, "V{}^=V{}", x, y).unwrap(),
-            0x8004 => write!(diss.assembly, "V{}+=V{}", x, y).unwrap(),
-            0x8005 => write!(diss.assembly, "V{}-=V{}", x, y).unwrap(),
-            0x8006 => write!(diss.assembly, "V{}=V{}>>1", x, y).unwrap(),
-            0x8007 => write!(diss.assembly, "V{}=V{}-V{}", x, y, x).unwrap(),
-            0x800E => write!(diss.assembly, "V{}=V{}<<1", x, y).unwrap(),
-            _ => write!(diss.assembly, "{:#X} not handled yet", opcode).unwrap(),
-        },
-        0x9000 => write!(diss.assembly, "SKF V{}≠V{}", x, y).unwrap(),
-        0xA000 => write!(diss.assembly, "I={:#X}", nnn).unwrap(),
-        0xB000 => write!(diss.assembly, "GOTO V0+{:#X}", nnn).unwrap(),
-        0xC000 => write!(diss.assembly, "V{}=RND.{:#X}", x, nn).unwrap(),
-        0xD000 => write!(diss.assembly, "Draw {} Rows @X{},Y{}", n, x, y).unwrap(),
-        0xe000 => match opcode & 0xF0FF {
-            0xE09E => write!(diss.assembly, "SKF V{}=KEY", x).unwrap(),
-            0xE0A1 => write!(diss.assembly, "SKF V{}≠KEY", x).unwrap(),
-            _ => diss.assembly = "Not implemented yet".to_string(),
-        },
-        0xf000 => match opcode & 0xF0FF {
-            0xF007 => write!(diss.assembly, "V{}=TIME", x).unwrap(),
-            0xF00A => write!(diss.assembly, "V{}=KEY", x).unwrap(),
-            0xF015 => write!(diss.assembly, "TIME=V{}", x).unwrap(),
-            0xF018 => write!(diss.assembly, "TONE=V{}", x).unwrap(),
-            0xF01E => write!(diss.assembly, "I=I+V{}", x).unwrap(),
-            0xF029 => write!(diss.assembly, "I=DSP,V{}", x).unwrap(),
-            0xF033 => write!(diss.assembly, "MI=DEQ,V{}", x).unwrap(),
-            0xF055 => write!(diss.assembly, "MI=V0:V{}", x).unwrap(),
-            0xF065 => write!(diss.assembly, "V0:V{}=MI", x).unwrap(),
-            _ => diss.assembly = "Not implemented yet".to_string(),
+    match instruction {
+        Instruction::Sys { nnn } => format!("NOP {:#X}", nnn),
+        Instruction::ScrollDown { n } => format!("SCROLL DOWN {}", n),
+        Instruction::ScrollUp { n } => format!("SCROLL UP {}", n),
+        Instruction::Clear => "ERASE".to_string(),
+        Instruction::Return => "Return".to_string(),
+        Instruction::ScrollRight => "SCROLL RIGHT".to_string(),
+        Instruction::ScrollLeft => "SCROLL LEFT".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::LoRes => "LORES".to_string(),
+        Instruction::HiRes => "HIRES".to_string(),
+        Instruction::Jump { nnn } => format!("GOTO {}", addr(nnn)),
+        Instruction::Call { nnn } => format!("DO {}", addr(nnn)),
+        Instruction::SkipEqImm { x, nn } => format!("SKF V{}={:#X}", x, nn),
+        Instruction::SkipNeImm { x, nn } => format!("SKF V{}≠{:#X}", x, nn),
+        Instruction::SkipEqReg { x, y } => format!("SKF V{}=V{}", x, y),
+        Instruction::StoreRange { x, y } => format!("MI=V{}:V{}", x, y),
+        Instruction::LoadRange { x, y } => format!("V{}:V{}=MI", x, y),
+        Instruction::LoadImm { x, nn } => format!("V{}={:#X}", x, nn),
+        Instruction::AddImm { x, nn } => format!("V{}+={:#X}", x, nn),
+        Instruction::Move { x, y } => format!("V{}=V{}", x, y),
+        Instruction::Or { x, y } => format!("V{}|=V{}", x, y),
+        Instruction::And { x, y } => format!("V{}&=V{}", x, y),
+        Instruction::Xor { x, y } => format!("V{}^=V{}", x, y),
+        Instruction::Add { x, y } => format!("V{}+=V{}", x, y),
+        Instruction::Sub { x, y } => format!("V{}-=V{}", x, y),
+        Instruction::ShiftRight { x, y } => format!("V{}=V{}>>1", x, y),
+        Instruction::SubReverse { x, y } => format!("V{}=V{}-V{}", x, y, x),
+        Instruction::ShiftLeft { x, y } => format!("V{}=V{}<<1", x, y),
+        Instruction::SkipNeReg { x, y } => format!("SKF V{}≠V{}", x, y),
+        Instruction::LoadI { nnn } => format!("I={}", addr(nnn)),
+        Instruction::JumpOffset { nnn } => format!("GOTO V0+{}", addr(nnn)),
+        Instruction::Random { x, nn } => format!("V{}=RND.{:#X}", x, nn),
+        Instruction::Draw { x, y, n: 0 } => format!("Draw 16x16 @X{},Y{}", x, y),
+        Instruction::Draw { x, y, n } => format!("Draw {} Rows @X{},Y{}", n, x, y),
+        Instruction::SkipKey { x } => format!("SKF V{}=KEY", x),
+        Instruction::SkipNotKey { x } => format!("SKF V{}≠KEY", x),
+        Instruction::LoadILong => long_mnemonic(Syntax::Classic, None, labels),
+        Instruction::Plane { n } => format!("PLANE {}", n),
+        Instruction::Audio => "AUDIO=MI".to_string(),
+        Instruction::GetDelay { x } => format!("V{}=TIME", x),
+        Instruction::WaitKey { x } => format!("V{}=KEY", x),
+        Instruction::SetDelay { x } => format!("TIME=V{}", x),
+        Instruction::SetSound { x } => format!("TONE=V{}", x),
+        Instruction::AddI { x } => format!("I=I+V{}", x),
+        Instruction::Font { x } => format!("I=DSP,V{}", x),
+        Instruction::BigFont { x } => format!("I=BIGDSP,V{}", x),
+        Instruction::Bcd { x } => format!("MI=DEQ,V{}", x),
+        Instruction::Pitch { x } => format!("PITCH=V{}", x),
+        Instruction::Store { x } => format!("MI=V0:V{}", x),
+        Instruction::Load { x } => format!("V0:V{}=MI", x),
+        Instruction::SaveFlags { x } => format!("RPL=V0:V{}", x),
+        Instruction::LoadFlags { x } => format!("V0:V{}=RPL", x),
+    }
+}
+
+fn octo(instruction: Instruction, labels: &Labels) -> String {
+    let addr = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| format!("0x{:03X}", nnn));
+
+    match instruction {
+        // Octo has no mnemonic for machine code calls, so emit the raw bytes
+        Instruction::Sys { nnn } => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+        Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+        Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+        Instruction::Clear => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LoRes => "lores".to_string(),
+        Instruction::HiRes => "hires".to_string(),
+        Instruction::Jump { nnn } => format!("jump {}", addr(nnn)),
+        // A bare label is a call in Octo, a plain address needs :call
+        Instruction::Call { nnn } => match labels.get(&nnn) {
+            Some(label) => label.clone(),
+            None => format!(":call 0x{:03X}", nnn),
         },
-        _ => diss.assembly = "Not implemented yet".to_string(),
+        // Octo conditions say when the next instruction runs, i.e. the opposite of the skip
+        Instruction::SkipEqImm { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+        Instruction::SkipNeImm { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+        Instruction::SkipEqReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::StoreRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LoadImm { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+        Instruction::AddImm { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+        Instruction::Move { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::Add { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShiftRight { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubReverse { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SkipNeReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::LoadI { nnn } => format!("i := {}", addr(nnn)),
+        Instruction::JumpOffset { nnn } => format!("jump0 {}", addr(nnn)),
+        Instruction::Random { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+        Instruction::Draw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::SkipKey { x } => format!("if v{:x} -key then", x),
+        Instruction::SkipNotKey { x } => format!("if v{:x} key then", x),
+        Instruction::LoadILong => long_mnemonic(Syntax::Octo, None, labels),
+        Instruction::Plane { n } => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::GetDelay { x } => format!("v{:x} := delay", x),
+        Instruction::WaitKey { x } => format!("v{:x} := key", x),
+        Instruction::SetDelay { x } => format!("delay := v{:x}", x),
+        Instruction::SetSound { x } => format!("buzzer := v{:x}", x),
+        Instruction::AddI { x } => format!("i += v{:x}", x),
+        Instruction::Font { x } => format!("i := hex v{:x}", x),
+        Instruction::BigFont { x } => format!("i := bighex v{:x}", x),
+        Instruction::Bcd { x } => format!("bcd v{:x}", x),
+        Instruction::Pitch { x } => format!("pitch := v{:x}", x),
+        Instruction::Store { x } => format!("save v{:x}", x),
+        Instruction::Load { x } => format!("load v{:x}", x),
+        Instruction::SaveFlags { x } => format!("saveflags v{:x}", x),
+        Instruction::LoadFlags { x } => format!("loadflags v{:x}", x),
     }
+}
+
+// Cowgod's reference mnemonics, which Chipper shares apart from how it writes hex. Neither
+// covers XO-CHIP, so those opcodes get LD-style names in the same spirit.
+fn cowgod(instruction: Instruction, syntax: Syntax, labels: &Labels) -> String {
+    let addr = |nnn: u16| labels.get(&nnn).cloned().unwrap_or_else(|| syntax.hex(nnn, 3));
+    let byte = |nn: u8| syntax.hex(nn as u16, 2);
 
-    return diss;
+    match instruction {
+        Instruction::Sys { nnn } => format!("SYS {}", syntax.hex(nnn, 3)),
+        Instruction::ScrollDown { n } => format!("SCD {}", n),
+        Instruction::ScrollUp { n } => format!("SCU {}", n),
+        Instruction::Clear => "CLS".to_string(),
+        Instruction::Return => "RET".to_string(),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::LoRes => "LOW".to_string(),
+        Instruction::HiRes => "HIGH".to_string(),
+        Instruction::Jump { nnn } => format!("JP {}", addr(nnn)),
+        Instruction::Call { nnn } => format!("CALL {}", addr(nnn)),
+        Instruction::SkipEqImm { x, nn } => format!("SE V{:X}, {}", x, byte(nn)),
+        Instruction::SkipNeImm { x, nn } => format!("SNE V{:X}, {}", x, byte(nn)),
+        Instruction::SkipEqReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::StoreRange { x, y } => format!("LD [I], V{:X}-V{:X}", x, y),
+        Instruction::LoadRange { x, y } => format!("LD V{:X}-V{:X}, [I]", x, y),
+        Instruction::LoadImm { x, nn } => format!("LD V{:X}, {}", x, byte(nn)),
+        Instruction::AddImm { x, nn } => format!("ADD V{:X}, {}", x, byte(nn)),
+        Instruction::Move { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubReverse { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipNeReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LoadI { nnn } => format!("LD I, {}", addr(nnn)),
+        Instruction::JumpOffset { nnn } => format!("JP V0, {}", addr(nnn)),
+        Instruction::Random { x, nn } => format!("RND V{:X}, {}", x, byte(nn)),
+        Instruction::Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipKey { x } => format!("SKP V{:X}", x),
+        Instruction::SkipNotKey { x } => format!("SKNP V{:X}", x),
+        Instruction::LoadILong => long_mnemonic(syntax, None, labels),
+        Instruction::Plane { n } => format!("PLANE {}", n),
+        Instruction::Audio => "LD AUDIO, [I]".to_string(),
+        Instruction::GetDelay { x } => format!("LD V{:X}, DT", x),
+        Instruction::WaitKey { x } => format!("LD V{:X}, K", x),
+        Instruction::SetDelay { x } => format!("LD DT, V{:X}", x),
+        Instruction::SetSound { x } => format!("LD ST, V{:X}", x),
+        Instruction::AddI { x } => format!("ADD I, V{:X}", x),
+        Instruction::Font { x } => format!("LD F, V{:X}", x),
+        Instruction::BigFont { x } => format!("LD HF, V{:X}", x),
+        Instruction::Bcd { x } => format!("LD B, V{:X}", x),
+        Instruction::Pitch { x } => format!("LD PITCH, V{:X}", x),
+        Instruction::Store { x } => format!("LD [I], V{:X}", x),
+        Instruction::Load { x } => format!("LD V{:X}, [I]", x),
+        Instruction::SaveFlags { x } => format!("LD R, V{:X}", x),
+        Instruction::LoadFlags { x } => format!("LD V{:X}, R", x),
+    }
 }
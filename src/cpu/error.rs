@@ -0,0 +1,40 @@
+use std::fmt;
+
+// Everything that can stop the CPU mid-program. Each variant records where it happened
+// so the front ends can point at the faulting instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    MemoryOutOfRange { pc: u16, opcode: u16, address: usize },
+    PcOutOfRange { pc: u16, opcode: u16 },
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06X} at {:#x}", opcode, pc)
+            }
+            CpuError::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow: {:#06X} at {:#x} returned with an empty stack", opcode, pc)
+            }
+            CpuError::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow: {:#06X} at {:#x} nested too many calls", opcode, pc)
+            }
+            CpuError::MemoryOutOfRange { pc, opcode, address } => {
+                write!(f, "{:#06X} at {:#x} accessed memory out of range at {:#x}", opcode, pc, address)
+            }
+            CpuError::PcOutOfRange { pc, opcode } => {
+                write!(f, "program counter out of range at {:#x} (last opcode {:#06X})", pc, opcode)
+            }
+            CpuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but only {} fit in memory", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
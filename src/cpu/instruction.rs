@@ -0,0 +1,221 @@
+// Every opcode the CPU understands, decoded once and shared by the executor and the disassembler.
+//
+// Field names follow the usual opcode notation: x and y are register numbers, n is a 4-bit
+// immediate, nn an 8-bit immediate and nnn a 12-bit address.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys { nnn: u16 },                // 0NNN: machine code routine, ignored
+    ScrollDown { n: u8 },            // 00CN (SUPER-CHIP)
+    ScrollUp { n: u8 },              // 00DN (XO-CHIP)
+    Clear,                           // 00E0
+    Return,                          // 00EE
+    ScrollRight,                     // 00FB (SUPER-CHIP)
+    ScrollLeft,                      // 00FC (SUPER-CHIP)
+    Exit,                            // 00FD (SUPER-CHIP)
+    LoRes,                           // 00FE (SUPER-CHIP)
+    HiRes,                           // 00FF (SUPER-CHIP)
+    Jump { nnn: u16 },               // 1NNN
+    Call { nnn: u16 },               // 2NNN
+    SkipEqImm { x: u8, nn: u8 },     // 3XNN
+    SkipNeImm { x: u8, nn: u8 },     // 4XNN
+    SkipEqReg { x: u8, y: u8 },      // 5XY0
+    StoreRange { x: u8, y: u8 },     // 5XY2 (XO-CHIP)
+    LoadRange { x: u8, y: u8 },      // 5XY3 (XO-CHIP)
+    LoadImm { x: u8, nn: u8 },       // 6XNN
+    AddImm { x: u8, nn: u8 },        // 7XNN
+    Move { x: u8, y: u8 },           // 8XY0
+    Or { x: u8, y: u8 },             // 8XY1
+    And { x: u8, y: u8 },            // 8XY2
+    Xor { x: u8, y: u8 },            // 8XY3
+    Add { x: u8, y: u8 },            // 8XY4
+    Sub { x: u8, y: u8 },            // 8XY5
+    ShiftRight { x: u8, y: u8 },     // 8XY6
+    SubReverse { x: u8, y: u8 },     // 8XY7
+    ShiftLeft { x: u8, y: u8 },      // 8XYE
+    SkipNeReg { x: u8, y: u8 },      // 9XY0
+    LoadI { nnn: u16 },              // ANNN
+    JumpOffset { nnn: u16 },         // BNNN
+    Random { x: u8, nn: u8 },        // CXNN
+    Draw { x: u8, y: u8, n: u8 },    // DXYN
+    SkipKey { x: u8 },               // EX9E
+    SkipNotKey { x: u8 },            // EXA1
+    LoadILong,                       // F000 NNNN (XO-CHIP), the address is the following word
+    Plane { n: u8 },                 // FN01 (XO-CHIP)
+    Audio,                           // F002 (XO-CHIP)
+    GetDelay { x: u8 },              // FX07
+    WaitKey { x: u8 },               // FX0A
+    SetDelay { x: u8 },              // FX15
+    SetSound { x: u8 },              // FX18
+    AddI { x: u8 },                  // FX1E
+    Font { x: u8 },                  // FX29
+    BigFont { x: u8 },               // FX30 (SUPER-CHIP)
+    Bcd { x: u8 },                   // FX33
+    Pitch { x: u8 },                 // FX3A (XO-CHIP)
+    Store { x: u8 },                 // FX55
+    Load { x: u8 },                  // FX65
+    SaveFlags { x: u8 },             // FX75 (SUPER-CHIP)
+    LoadFlags { x: u8 },             // FX85 (SUPER-CHIP)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownOpcode(pub u16);
+
+impl fmt::Display for UnknownOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:#06X}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownOpcode {}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Result<Instruction, UnknownOpcode> {
+        let x: u8 = ((opcode & 0x0F00) >> 8) as u8;
+        let y: u8 = ((opcode & 0x00F0) >> 4) as u8;
+        let nn: u8 = (opcode & 0x00FF) as u8;
+        let nnn: u16 = opcode & 0x0FFF;
+        let n: u8 = (opcode & 0x000F) as u8;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match nnn {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::LoRes,
+                0x00FF => Instruction::HiRes,
+                _ if nnn & 0xFF0 == 0x0C0 => Instruction::ScrollDown { n },
+                _ if nnn & 0xFF0 == 0x0D0 => Instruction::ScrollUp { n },
+                _ => Instruction::Sys { nnn },
+            },
+            0x1000 => Instruction::Jump { nnn },
+            0x2000 => Instruction::Call { nnn },
+            0x3000 => Instruction::SkipEqImm { x, nn },
+            0x4000 => Instruction::SkipNeImm { x, nn },
+            0x5000 => match n {
+                0x0 => Instruction::SkipEqReg { x, y },
+                0x2 => Instruction::StoreRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return Err(UnknownOpcode(opcode)),
+            },
+            0x6000 => Instruction::LoadImm { x, nn },
+            0x7000 => Instruction::AddImm { x, nn },
+            0x8000 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::Add { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubReverse { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => return Err(UnknownOpcode(opcode)),
+            },
+            0x9000 if n == 0 => Instruction::SkipNeReg { x, y },
+            0xA000 => Instruction::LoadI { nnn },
+            0xB000 => Instruction::JumpOffset { nnn },
+            0xC000 => Instruction::Random { x, nn },
+            0xD000 => Instruction::Draw { x, y, n },
+            0xE000 => match nn {
+                0x9E => Instruction::SkipKey { x },
+                0xA1 => Instruction::SkipNotKey { x },
+                _ => return Err(UnknownOpcode(opcode)),
+            },
+            0xF000 => match nn {
+                0x00 if x == 0 => Instruction::LoadILong,
+                0x01 => Instruction::Plane { n: x },
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::GetDelay { x },
+                0x0A => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1E => Instruction::AddI { x },
+                0x29 => Instruction::Font { x },
+                0x30 => Instruction::BigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3A => Instruction::Pitch { x },
+                0x55 => Instruction::Store { x },
+                0x65 => Instruction::Load { x },
+                0x75 => Instruction::SaveFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => return Err(UnknownOpcode(opcode)),
+            },
+            _ => return Err(UnknownOpcode(opcode)),
+        };
+
+        Ok(instruction)
+    }
+
+    pub fn encode(&self) -> u16 {
+        fn xy(high: u16, x: u8, y: u8, low: u16) -> u16 {
+            high | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | low
+        }
+        fn xnn(high: u16, x: u8, nn: u8) -> u16 {
+            high | (x as u16 & 0xF) << 8 | nn as u16
+        }
+
+        match *self {
+            Instruction::Sys { nnn } => nnn & 0x0FFF,
+            Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LoRes => 0x00FE,
+            Instruction::HiRes => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call { nnn } => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipEqImm { x, nn } => xnn(0x3000, x, nn),
+            Instruction::SkipNeImm { x, nn } => xnn(0x4000, x, nn),
+            Instruction::SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::StoreRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadImm { x, nn } => xnn(0x6000, x, nn),
+            Instruction::AddImm { x, nn } => xnn(0x7000, x, nn),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::Add { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubReverse { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI { nnn } => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpOffset { nnn } => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random { x, nn } => xnn(0xC000, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::SkipKey { x } => xnn(0xE000, x, 0x9E),
+            Instruction::SkipNotKey { x } => xnn(0xE000, x, 0xA1),
+            Instruction::LoadILong => 0xF000,
+            Instruction::Plane { n } => xnn(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::GetDelay { x } => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey { x } => xnn(0xF000, x, 0x0A),
+            Instruction::SetDelay { x } => xnn(0xF000, x, 0x15),
+            Instruction::SetSound { x } => xnn(0xF000, x, 0x18),
+            Instruction::AddI { x } => xnn(0xF000, x, 0x1E),
+            Instruction::Font { x } => xnn(0xF000, x, 0x29),
+            Instruction::BigFont { x } => xnn(0xF000, x, 0x30),
+            Instruction::Bcd { x } => xnn(0xF000, x, 0x33),
+            Instruction::Pitch { x } => xnn(0xF000, x, 0x3A),
+            Instruction::Store { x } => xnn(0xF000, x, 0x55),
+            Instruction::Load { x } => xnn(0xF000, x, 0x65),
+            Instruction::SaveFlags { x } => xnn(0xF000, x, 0x75),
+            Instruction::LoadFlags { x } => xnn(0xF000, x, 0x85),
+        }
+    }
+
+    // Size in memory, counting the address word that follows F000
+    pub fn size(&self) -> u16 {
+        if *self == Instruction::LoadILong { 4 } else { 2 }
+    }
+}
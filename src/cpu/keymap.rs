@@ -0,0 +1,182 @@
+// Which host keys press which of the 16 CHIP-8 keys. Host keys go by name so the terminal and the
+// window can share one keymap:
+//
+//   a character        "q", "7", ";"
+//   a named key        "up", "down", "left", "right", "enter"
+//   the numeric keypad "num0"-"num9", "num/", "num*", "num-", "num+", "num.", "numenter"
+//
+// A keymap starts from one of the built-in layouts, and keymap.json can change it for every ROM:
+//
+//   {
+//     "layout": "azerty",
+//     "keys": { "up": 5, "down": 8, "v": null }
+//   }
+//
+// A null removes the host key's binding. Keys for a single ROM are bound from the debugger and
+// kept with its other settings, by SHA-1 (see settings.rs).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+pub const LAYOUT_NAMES: [&str; 3] = ["qwerty", "azerty", "numpad"];
+
+// The COSMAC VIP hex keypad laid over the left of the keyboard
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D      q w e r
+//   7 8 9 E  ->  a s d f
+//   A 0 B F      z x c v
+const QWERTY: [(&str, u8); 16] = [
+    ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xC),
+    ("q", 0x4), ("w", 0x5), ("e", 0x6), ("r", 0xD),
+    ("a", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xE),
+    ("z", 0xA), ("x", 0x0), ("c", 0xB), ("v", 0xF),
+];
+
+// The same keys on a French keyboard
+const AZERTY: [(&str, u8); 16] = [
+    ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xC),
+    ("a", 0x4), ("z", 0x5), ("e", 0x6), ("r", 0xD),
+    ("q", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xE),
+    ("w", 0xA), ("x", 0x0), ("c", 0xB), ("v", 0xF),
+];
+
+// Digits press the same key, and the operators around them A-F
+const NUMPAD: [(&str, u8); 16] = [
+    ("num0", 0x0), ("num1", 0x1), ("num2", 0x2), ("num3", 0x3),
+    ("num4", 0x4), ("num5", 0x5), ("num6", 0x6), ("num7", 0x7),
+    ("num8", 0x8), ("num9", 0x9), ("num/", 0xA), ("num*", 0xB),
+    ("num-", 0xC), ("num+", 0xD), ("numenter", 0xE), ("num.", 0xF),
+];
+
+// Space and Tab are left out: the terminal needs them for the debugger and the disassembly
+pub fn is_host_key(name: &str) -> bool {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c.is_ascii_graphic(),
+        _ => matches!(name, "up" | "down" | "left" | "right" | "enter" | "numenter")
+            || name.strip_prefix("num").is_some_and(|key| key.len() == 1 && "0123456789/*-+.".contains(key)),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: BTreeMap<String, u8>, // host key name to CHIP-8 key
+}
+
+impl Keymap {
+    pub fn layout(name: &str) -> Option<Keymap> {
+        let layout = match name.to_lowercase().as_str() {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "numpad" => NUMPAD,
+            _ => return None,
+        };
+        Some(Keymap { bindings: layout.iter().map(|&(host, key)| (host.to_string(), key)).collect() })
+    }
+
+    pub fn key(&self, host: &str) -> Option<u8> {
+        self.bindings.get(&host.to_lowercase()).copied()
+    }
+
+    pub fn bind(&mut self, host: &str, key: u8) -> Result<(), String> {
+        let host = host.to_lowercase();
+        if !is_host_key(&host) {
+            return Err(format!("unknown host key '{}'", host));
+        }
+        if key > 0xF {
+            return Err(format!("{} can't press key {:#X}, CHIP-8 keys go up to 0xF", host, key));
+        }
+        self.bindings.insert(host, key);
+        Ok(())
+    }
+
+    pub fn unbind(&mut self, host: &str) {
+        self.bindings.remove(&host.to_lowercase());
+    }
+
+    // Binds and unbinds (for None) several keys at once
+    pub fn apply(&mut self, changes: &BTreeMap<String, Option<u8>>) -> Result<(), String> {
+        for (host, key) in changes {
+            match key {
+                Some(key) => self.bind(host, *key)?,
+                None => self.unbind(host),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.bindings.iter().map(|(host, &key)| (host.as_str(), key))
+    }
+}
+
+// The ROM database names a game's controls rather than host keys; its "a" button goes on Enter
+pub fn controls(keys: &BTreeMap<String, u8>) -> BTreeMap<String, Option<u8>> {
+    keys.iter()
+        .filter_map(|(control, &key)| {
+            let host = match control.as_str() {
+                "up" | "down" | "left" | "right" => control.as_str(),
+                "a" => "enter",
+                _ => return None,
+            };
+            (key <= 0xF).then(|| (host.to_string(), Some(key)))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub layout: String,
+    pub keys: BTreeMap<String, Option<u8>>,
+}
+
+impl Default for KeymapConfig {
+    fn default() -> KeymapConfig {
+        KeymapConfig { layout: LAYOUT_NAMES[0].to_string(), keys: BTreeMap::new() }
+    }
+}
+
+impl KeymapConfig {
+    pub fn parse(text: &str) -> Result<KeymapConfig, String> {
+        let config: KeymapConfig = serde_json::from_str(text).map_err(|e| e.to_string())?;
+
+        // Check everything up front rather than when a ROM happens to need it
+        let mut keymap = Keymap::layout(&config.layout)
+            .ok_or_else(|| format!("unknown layout '{}' ({})", config.layout, LAYOUT_NAMES.join(", ")))?;
+        keymap.apply(&config.keys)?;
+        Ok(config)
+    }
+
+    // A file that doesn't exist is the QWERTY layout
+    pub fn load(path: &Path) -> Result<KeymapConfig, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => KeymapConfig::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KeymapConfig::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    // The keymap for a ROM: the layout, then the game's controls from the database, the config's
+    // own keys and the keys bound to the ROM, each over the last. The ROM's bindings come from a
+    // settings file that may have been edited by hand, so any that can't be made are left out and
+    // returned with the keymap.
+    pub fn keymap(&self, controls: &BTreeMap<String, Option<u8>>, rom_keys: &BTreeMap<String, u8>) -> (Keymap, Vec<String>) {
+        let mut keymap = Keymap::layout(&self.layout).unwrap_or_default();
+        let rom_keys = rom_keys.iter().map(|(host, &key)| (host.clone(), Some(key))).collect();
+
+        let mut errors = vec![];
+        for changes in [controls, &self.keys, &rom_keys] {
+            for (host, &key) in changes {
+                match key {
+                    Some(key) => errors.extend(keymap.bind(host, key).err()),
+                    None => keymap.unbind(host),
+                }
+            }
+        }
+        (keymap, errors)
+    }
+}
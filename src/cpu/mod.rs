@@ -1,77 +1,221 @@
-use rand;
-
+pub mod assembler;
+pub mod audio;
+pub mod breakpoints;
+pub mod clock;
 #[cfg(test)]
 mod cpu_tests;
+pub mod database;
 pub mod disassembler;
-
+pub mod error;
+pub mod instruction;
+pub mod keymap;
+pub mod octo;
+pub mod quirks;
+pub mod rewind;
+pub mod screen;
+pub mod settings;
+pub mod state;
+pub mod symbols;
+
+pub use clock::{Clock, ClockSpeed};
+pub use error::CpuError;
+use instruction::Instruction;
+pub use quirks::Quirks;
+use quirks::MemoryIncrement;
+
+// The framebuffer is sized for SUPER-CHIP hi-res; lo-res programs only use the top-left 64x32
+pub const SCREEN_WIDTH: usize = 128;
+pub const SCREEN_HEIGHT: usize = 64;
+
+// XO-CHIP extends the address space to 64K
+pub const MEMORY_SIZE: usize = 0x10000;
+
+// Nesting limit for 2NNN calls, matching SUPER-CHIP
+pub const STACK_SIZE: usize = 16;
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub opcode: u16,
-    pub memory: [u8; 4096],
+    pub memory: [u8; MEMORY_SIZE],
     pub registers: [u8; 16],
     pub i_register: u16,
     pub program_counter: u16,
-    pub gfx: [[bool; 32]; 64], //   [bool; 64 * 32],
+    pub gfx: [[u8; SCREEN_HEIGHT]; SCREEN_WIDTH], // each pixel is a colour index, one bit per plane
+    pub plane_mask: u8,
+    pub hires: bool,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub stack: Vec<u16>,
     pub sp: u8,
-    pub key_press: Option<u8>,
+    pub keypad: u16, // bit N is set while key N is held
     pub waiting_for_key: Option<u8>,
-    pub program_size: u16,
+    pub keys_pressed_while_waiting: u16,
+    pub program_size: usize,
+    pub quirks: Quirks,
+    pub vblank_wait: bool,
+    pub rpl_flags: [u8; 16],
+    pub exited: bool,
+    pub audio_pattern: [u8; 16],
+    pub pitch: u8,
+    pub clock: Clock,
+    pub frame_cycles_left: u32,
+    pub audio: audio::Audio, // where the sound timer's tone goes; silent unless a sink is set up
 }
 
 impl CPU {
-    pub fn new() -> CPU {
+    pub fn new(quirks: Quirks) -> CPU {
         let mut cpu = CPU {
             opcode: 0,
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             registers: [0; 16], //Registers V0-VF
             i_register: 0, 
             program_counter: 0x200,
-            gfx: [[false; 32]; 64], // [false; 64 * 32],
+            gfx: [[0; SCREEN_HEIGHT]; SCREEN_WIDTH],
+            plane_mask: 1,
+            hires: false,
             delay_timer: 0,
             sound_timer: 0,
             stack: vec![],
             sp: 0,
-            key_press: None,
+            keypad: 0,
             waiting_for_key: None,
+            keys_pressed_while_waiting: 0,
             program_size: 0,
+            quirks,
+            vblank_wait: false,
+            rpl_flags: [0; 16],
+            exited: false,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            clock: Clock::default(),
+            frame_cycles_left: 0,
+            audio: audio::Audio::default(),
         };
 
         cpu.load_fonts();
 
-        return cpu;
+        cpu
     }
 
-    pub fn load_program(&mut self, bytes: &[u8]) {
+    pub fn load_program(&mut self, bytes: &[u8]) -> Result<(), CpuError> {
+        let max = MEMORY_SIZE - 0x200;
+        if bytes.len() > max {
+            return Err(CpuError::RomTooLarge { size: bytes.len(), max });
+        }
+
         let mut addr = 0x200;
         for byte in bytes {
             self.memory[addr] = *byte;
             addr += 1;
         }
-        self.program_size = addr as u16;
+        self.program_size = addr;
         self.program_counter = 0x200;
+
+        Ok(())
     }
 
     pub fn reset(&mut self) {
         self.opcode = 0;
-        self.memory = [0; 4096];
+        self.memory = [0; MEMORY_SIZE];
         self.registers = [0; 16];
         self.i_register = 0;
         self.program_counter = 0x200;
-        self.gfx = [[false; 32]; 64];
+        self.gfx = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+        self.plane_mask = 1;
+        self.hires = false;
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.stack.clear();
         self.sp = 0;
-        self.key_press = None;
+        self.keypad = 0;
         self.waiting_for_key = None;
+        self.keys_pressed_while_waiting = 0;
         self.program_size = 0;
-        
+        self.vblank_wait = false;
+        self.exited = false;
+        self.audio_pattern = [0; 16];
+        self.pitch = 64;
+        self.clock.reset();
+        self.frame_cycles_left = 0;
+        // rpl_flags are deliberately kept: on the HP-48 they survive between programs
+
         self.load_fonts();
     }
 
-    pub fn do_cycle(&mut self) {
+    // Run one 60 Hz frame: the clock's share of instructions followed by a timer tick
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        while !self.step()? {}
+
+        Ok(())
+    }
+
+    // Run until either limit is reached or the program exits (00FD). Returns the instructions
+    // and frames actually run.
+    pub fn run_for(&mut self, max_cycles: Option<u64>, max_frames: Option<u64>) -> Result<(u64, u64), CpuError> {
+        let (mut cycles, mut frames) = (0, 0);
+
+        while !self.exited
+            && max_cycles.is_none_or(|max| cycles < max)
+            && max_frames.is_none_or(|max| frames < max)
+        {
+            if self.step()? {
+                frames += 1;
+            }
+            cycles += 1;
+        }
+
+        Ok((cycles, frames))
+    }
+
+    // Execute the next instruction of the current frame, ticking the timers once the frame's
+    // instructions are used up. Returns true when that tick happened.
+    pub fn step(&mut self) -> Result<bool, CpuError> {
+        if self.frame_cycles_left == 0 {
+            self.frame_cycles_left = self.clock.cycles_for_next_frame();
+        }
+
+        if self.frame_cycles_left > 0 {
+            self.do_cycle()?;
+            self.frame_cycles_left -= 1;
+        }
+
+        if self.frame_cycles_left == 0 {
+            self.tick_timers();
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    // True while the next cycle won't execute a new instruction: the program has exited, FX0A is
+    // waiting for a key, or a DXYN is waiting for the display refresh
+    pub fn is_stalled(&self) -> bool {
+        let pc = self.program_counter as usize;
+        let next_is_draw = pc < MEMORY_SIZE && self.memory[pc] >> 4 == 0xD;
+
+        self.exited || self.waiting_for_key.is_some() || (self.quirks.display_wait && self.vblank_wait && next_is_draw)
+    }
+
+    // Execute a single instruction without touching the timers
+    pub fn do_cycle(&mut self) -> Result<(), CpuError> {
+        if self.exited {
+            return Ok(());
+        }
+
+        // FX0A halts execution until key_up completes the wait
+        if self.waiting_for_key.is_some() {
+            return Ok(());
+        }
+
+        self.fetch_opcode(self.program_counter as usize)?;
+        self.execute_opcode()
+    }
+
+    // Called at 60 Hz: plays the frame's sound, counts the timers down and ends any pending
+    // display wait
+    pub fn tick_timers(&mut self) {
+        self.audio.frame(self.sound_timer > 0);
+
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
@@ -80,98 +224,126 @@ impl CPU {
             self.delay_timer -= 1;
         }
 
-        if let Some(reg) = self.waiting_for_key {
-            if let Some(key) = self.key_press {
-                self.registers[reg as usize] = key;
-                self.waiting_for_key = None;
-            } else {
-                return;
-            }
-        }
-
-        self.fetch_opcode(self.program_counter as usize);
-        self.execute_opcode();
+        self.vblank_wait = false;
     }
 
-    fn fetch_opcode(&mut self, memory_location: usize) {
+    fn fetch_opcode(&mut self, memory_location: usize) -> Result<(), CpuError> {
+        if memory_location + 1 >= MEMORY_SIZE {
+            return Err(CpuError::PcOutOfRange { pc: self.program_counter, opcode: self.opcode });
+        }
+
         let a = self.memory[memory_location] as u16;
         let b = self.memory[memory_location + 1] as u16;
         self.opcode = a << 8 | b;
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        Ok(())
+    }
+
+    // Address of the instruction currently executing, for error reports
+    fn current_pc(&self) -> u16 {
+        self.program_counter.wrapping_sub(2)
+    }
+
+    // Fail unless `len` bytes starting at `start` are inside memory
+    fn check_memory(&self, start: usize, len: usize) -> Result<(), CpuError> {
+        if start + len > MEMORY_SIZE {
+            return Err(CpuError::MemoryOutOfRange {
+                pc: self.current_pc(),
+                opcode: self.opcode,
+                address: start.max(MEMORY_SIZE), // first byte past the end that was touched
+            });
+        }
+
+        Ok(())
     }
 
     fn load_fonts(&mut self) {
-        for i in 0..FONT_SET.len() {
-            self.memory[i] = FONT_SET[i];
-        }
-    }
-
-    pub fn execute_opcode(&mut self) {
-        let nibble = self.opcode & 0xF000;
-
-        let x: u8 = ((self.opcode & 0x0F00) >> 8) as u8;
-        let y: u8 = ((self.opcode & 0x00F0) >> 4) as u8;
-        let nn: u8 = (self.opcode & 0x00FF) as u8;
-        let nnn: u16 = self.opcode & 0x0FFF;
-        let n: u8 = (self.opcode & 0x000F) as u8;
-
-        match nibble {
-            0x0000 => match self.opcode & 0x0FFF {
-                0x00E0 => self.op_00e0(),
-                0x00EE => self.op_00ee(),
-                _ if nnn != 0x00E0 && nnn != 0x00EE => self.op_0nnn(nnn),
-                _ => self.op_ni(),
-            },
-            0x1000 => self.op_1nnn(nnn),
-            0x2000 => self.op_2nnn(nnn),
-            0x3000 => self.op_3xnn(x, nn),
-            0x4000 => self.op_4xnn(x, nn),
-            0x5000 => self.op_5xy0(x, y),
-            0x6000 => self.op_6xnn(x, nn),
-            0x7000 => self.op_7xnn(x, nn),
-            0x8000 => match self.opcode & 0xF00F {
-                0x8000 => self.op_8xy0(x, y),
-                0x8001 => self.op_8xy1(x, y),
-                0x8002 => self.op_8xy2(x, y),
-                0x8003 => self.op_8xy3(x, y),
-                0x8004 => self.op_8xy4(x, y),
-                0x8005 => self.op_8xy5(x, y),
-                0x8006 => self.op_8xy6(x, y),
-                0x8007 => self.op_8xy7(x, y),
-                0x800E => self.op_8xye(x, y),
-                _ => self.op_ni(),
-            },
-            0x9000 => self.op_9xy0(x, y),
-            0xA000 => self.op_annn(nnn),
-            0xB000 => self.op_bnnn(nnn),
-            0xC000 => self.op_cxnn(x, nn),
-            0xD000 => self.op_dxyn(x as usize, y as usize, n as usize),
-            0xE000 => match self.opcode & 0xF0FF {
-                0xE09E => self.op_ex9e(x),
-                0xE0A1 => self.op_exa1(x),
-                _ => self.op_ni(),
-            },
-            0xF000 => match self.opcode & 0xF0FF {
-                0xF007 => self.op_fx07(x),
-                0xF00A => self.op_fx0a(x),
-                0xF018 => self.op_fx18(x),
-                0xF01E => self.op_fx1e(x),
-                0xF015 => self.op_fx15(x),
-                0xF029 => self.op_fx29(x),
-                0xF033 => self.op_fx33(x),
-                0xF055 => self.op_fx55(x),
-                0xF065 => self.op_fx65(x),
-                _ => self.op_ni(),
-            },
-            _ => self.op_ni(),
-        }
-    }
-
-    fn op_ni(&mut self) {
-        panic!(
-            "{:#x} {:#06X} not implemented yet",
-            self.program_counter, self.opcode
-        );
+        self.memory[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+        self.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT_SET.len()].copy_from_slice(&BIG_FONT_SET);
+    }
+
+    // Skip the next instruction, stepping over both words of an XO-CHIP F000 NNNN
+    fn skip_instruction(&mut self) {
+        let pc = self.program_counter as usize;
+        let is_long = pc + 1 < MEMORY_SIZE && self.memory[pc] == 0xF0 && self.memory[pc + 1] == 0x00;
+
+        self.program_counter = self.program_counter.wrapping_add(if is_long { 4 } else { 2 });
+    }
+
+    // Visible resolution for the current display mode
+    pub fn width(&self) -> usize {
+        if self.hires { SCREEN_WIDTH } else { SCREEN_WIDTH / 2 }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { SCREEN_HEIGHT } else { SCREEN_HEIGHT / 2 }
+    }
+
+    pub fn execute_opcode(&mut self) -> Result<(), CpuError> {
+        let Ok(instruction) = Instruction::decode(self.opcode) else {
+            return Err(self.op_ni());
+        };
+
+        match instruction {
+            Instruction::Sys { nnn } => self.op_0nnn(nnn),
+            Instruction::ScrollDown { n } => self.op_00cn(n),
+            Instruction::ScrollUp { n } => self.op_00dn(n),
+            Instruction::Clear => self.op_00e0(),
+            Instruction::Return => self.op_00ee()?,
+            Instruction::ScrollRight => self.op_00fb(),
+            Instruction::ScrollLeft => self.op_00fc(),
+            Instruction::Exit => self.op_00fd(),
+            Instruction::LoRes => self.op_00fe(),
+            Instruction::HiRes => self.op_00ff(),
+            Instruction::Jump { nnn } => self.op_1nnn(nnn),
+            Instruction::Call { nnn } => self.op_2nnn(nnn)?,
+            Instruction::SkipEqImm { x, nn } => self.op_3xnn(x, nn),
+            Instruction::SkipNeImm { x, nn } => self.op_4xnn(x, nn),
+            Instruction::SkipEqReg { x, y } => self.op_5xy0(x, y),
+            Instruction::StoreRange { x, y } => self.op_5xy2(x, y)?,
+            Instruction::LoadRange { x, y } => self.op_5xy3(x, y)?,
+            Instruction::LoadImm { x, nn } => self.op_6xnn(x, nn),
+            Instruction::AddImm { x, nn } => self.op_7xnn(x, nn),
+            Instruction::Move { x, y } => self.op_8xy0(x, y),
+            Instruction::Or { x, y } => self.op_8xy1(x, y),
+            Instruction::And { x, y } => self.op_8xy2(x, y),
+            Instruction::Xor { x, y } => self.op_8xy3(x, y),
+            Instruction::Add { x, y } => self.op_8xy4(x, y),
+            Instruction::Sub { x, y } => self.op_8xy5(x, y),
+            Instruction::ShiftRight { x, y } => self.op_8xy6(x, y),
+            Instruction::SubReverse { x, y } => self.op_8xy7(x, y),
+            Instruction::ShiftLeft { x, y } => self.op_8xye(x, y),
+            Instruction::SkipNeReg { x, y } => self.op_9xy0(x, y),
+            Instruction::LoadI { nnn } => self.op_annn(nnn),
+            Instruction::JumpOffset { nnn } => self.op_bnnn(nnn),
+            Instruction::Random { x, nn } => self.op_cxnn(x, nn),
+            Instruction::Draw { x, y, n } => self.op_dxyn(x as usize, y as usize, n as usize)?,
+            Instruction::SkipKey { x } => self.op_ex9e(x),
+            Instruction::SkipNotKey { x } => self.op_exa1(x),
+            Instruction::LoadILong => self.op_f000()?,
+            Instruction::Plane { n } => self.op_fn01(n),
+            Instruction::Audio => self.op_f002()?,
+            Instruction::GetDelay { x } => self.op_fx07(x),
+            Instruction::WaitKey { x } => self.op_fx0a(x),
+            Instruction::SetDelay { x } => self.op_fx15(x),
+            Instruction::SetSound { x } => self.op_fx18(x),
+            Instruction::AddI { x } => self.op_fx1e(x),
+            Instruction::Font { x } => self.op_fx29(x),
+            Instruction::BigFont { x } => self.op_fx30(x),
+            Instruction::Bcd { x } => self.op_fx33(x)?,
+            Instruction::Pitch { x } => self.op_fx3a(x),
+            Instruction::Store { x } => self.op_fx55(x)?,
+            Instruction::Load { x } => self.op_fx65(x)?,
+            Instruction::SaveFlags { x } => self.op_fx75(x),
+            Instruction::LoadFlags { x } => self.op_fx85(x),
+        }
+
+        Ok(())
+    }
+
+    fn op_ni(&self) -> CpuError {
+        CpuError::UnknownOpcode { pc: self.current_pc(), opcode: self.opcode }
     }
 
     // 0NNN: No operation (historically called machine language subroutine, but modern implementations treat as no-op)
@@ -179,19 +351,79 @@ impl CPU {
         // No operation - do nothing
     }
 
-    // 00E0: Clear the display
+    // 00E0: Clear the selected planes of the display
     fn op_00e0(&mut self) {
-        for y in 0..32 {
-            for x in 0..64 {
-                self.gfx[x][y] = false;
+        for column in self.gfx.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= !self.plane_mask;
+            }
+        }
+    }
+
+    // 00CN: Scroll the display down N rows (SUPER-CHIP)
+    fn op_00cn(&mut self, n: u8) {
+        self.scroll(0, n as isize);
+    }
+
+    // 00DN: Scroll the display up N rows (XO-CHIP)
+    fn op_00dn(&mut self, n: u8) {
+        self.scroll(0, -(n as isize));
+    }
+
+    // 00FB: Scroll the display right 4 pixels (SUPER-CHIP)
+    fn op_00fb(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    // 00FC: Scroll the display left 4 pixels (SUPER-CHIP)
+    fn op_00fc(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    // Shift the selected planes by (dx, dy) within the visible area, filling the gap with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.gfx;
+
+        for x in 0..width {
+            for y in 0..height {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    old[src_x as usize][src_y as usize] & self.plane_mask
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.gfx[x as usize][y as usize];
+                *pixel = (*pixel & !self.plane_mask) | moved;
             }
         }
     }
 
+    // 00FD: Exit the interpreter (SUPER-CHIP)
+    fn op_00fd(&mut self) {
+        self.exited = true;
+    }
+
+    // 00FE: Switch to 64x32 lo-res mode and clear the display (SUPER-CHIP)
+    fn op_00fe(&mut self) {
+        self.hires = false;
+        self.gfx = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+    }
+
+    // 00FF: Switch to 128x64 hi-res mode and clear the display (SUPER-CHIP)
+    fn op_00ff(&mut self) {
+        self.hires = true;
+        self.gfx = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+    }
+
     // 00EE: Return from subroutine
-    fn op_00ee(&mut self) {
-        let pc = self.stack.pop().unwrap();
+    fn op_00ee(&mut self) -> Result<(), CpuError> {
+        let pc = self.stack.pop().ok_or(CpuError::StackUnderflow { pc: self.current_pc(), opcode: self.opcode })?;
         self.program_counter = pc;
+        self.sp = self.stack.len() as u8;
+
+        Ok(())
     }
 
     // 1NNN: Jump to address NNN
@@ -200,9 +432,16 @@ impl CPU {
     }
 
     // 2NNN: Call subroutine at NNN
-    fn op_2nnn(&mut self, nnn: u16) {
+    fn op_2nnn(&mut self, nnn: u16) -> Result<(), CpuError> {
+        if self.stack.len() >= STACK_SIZE {
+            return Err(CpuError::StackOverflow { pc: self.current_pc(), opcode: self.opcode });
+        }
+
         self.stack.push(self.program_counter);
+        self.sp = self.stack.len() as u8;
         self.program_counter = nnn;
+
+        Ok(())
     }
 
     // 3XNN: Skip next instruction if VX equals NN
@@ -210,7 +449,7 @@ impl CPU {
         let vx = self.registers[x as usize];
 
         if vx == nn {
-            self.program_counter += 2;
+            self.skip_instruction();
         }
     }
 
@@ -219,7 +458,7 @@ impl CPU {
         let vx = self.registers[x as usize];
 
         if vx != nn {
-            self.program_counter += 2;
+            self.skip_instruction();
         }
     }
 
@@ -229,7 +468,7 @@ impl CPU {
         let vy = self.registers[y as usize];
 
         if vx == vy {
-            self.program_counter += 2;
+            self.skip_instruction();
         }
     }
 
@@ -257,6 +496,9 @@ impl CPU {
         let vy = self.registers[y as usize];
 
         self.registers[x as usize] = vx | vy;
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     // 8XY2: Set VX to VX AND VY
@@ -265,6 +507,9 @@ impl CPU {
         let vy = self.registers[y as usize];
 
         self.registers[x as usize] = vx & vy;
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     // 8XY3: Set VX to VX XOR VY
@@ -273,6 +518,9 @@ impl CPU {
         let vy = self.registers[y as usize];
 
         self.registers[x as usize] = vx ^ vy;
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
     }
 
     // 8XY4: Add VY to VX. VF is set to 1 when there's a carry, 0 otherwise
@@ -283,7 +531,7 @@ impl CPU {
         let (val, overflow) = vx.overflowing_add(vy);
 
         self.registers[x as usize] = val;
-        self.registers[0xF as usize] = overflow as u8;
+        self.registers[0xF] = overflow as u8;
     }
 
     // 8XY5: Subtract VY from VX. VF is set to 0 when there's a borrow, 1 otherwise
@@ -294,15 +542,17 @@ impl CPU {
         let (val, borrow) = vx.overflowing_sub(vy);
 
         self.registers[x as usize] = val;
-        self.registers[0xF as usize] = (!borrow) as u8;
+        self.registers[0xF] = (!borrow) as u8;
     }
 
     // 8XY6: Store the least significant bit of VY in VF and shift VY right by 1, store result in VX
+    //       With the shift quirk VX is shifted in place and VY is ignored
     fn op_8xy6(&mut self, x: u8, y: u8) {
-        let vy = self.registers[y as usize];
+        let src = if self.quirks.shift { x } else { y };
+        let value = self.registers[src as usize];
 
-        self.registers[x as usize] = vy >> 1;
-        self.registers[0xF as usize] = vy & 0x1;
+        self.registers[x as usize] = value >> 1;
+        self.registers[0xF] = value & 0x1;
     }
 
     // 8XY7: Set VX to VY minus VX. VF is set to 0 when there's a borrow, 1 otherwise
@@ -313,15 +563,41 @@ impl CPU {
         let (val, borrow) = vy.overflowing_sub(vx);
 
         self.registers[x as usize] = val;
-        self.registers[0xF as usize] = (!borrow) as u8;
+        self.registers[0xF] = (!borrow) as u8;
     }
 
     // 8XYE: Store the most significant bit of VY in VF and shift VY left by 1, store result in VX
+    //       With the shift quirk VX is shifted in place and VY is ignored
     fn op_8xye(&mut self, x: u8, y: u8) {
-        let vy = self.registers[y as usize];
+        let src = if self.quirks.shift { x } else { y };
+        let value = self.registers[src as usize];
+
+        self.registers[x as usize] = value << 1;
+        self.registers[0xF] = (value & 0x80) >> 7;
+    }
+
+    // 5XY2: Store VX to VY (in either order) in memory starting at address I, leaving I unchanged (XO-CHIP)
+    fn op_5xy2(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        self.check_memory(self.i_register as usize, x.abs_diff(y) as usize + 1)?;
+
+        for offset in 0..=x.abs_diff(y) as usize {
+            let reg = register_in_range(x, y, offset);
+            self.memory[self.i_register as usize + offset] = self.registers[reg];
+        }
 
-        self.registers[x as usize] = vy << 1;
-        self.registers[0xF as usize] = (vy & 0x80) >> 7;
+        Ok(())
+    }
+
+    // 5XY3: Fill VX to VY (in either order) from memory starting at address I, leaving I unchanged (XO-CHIP)
+    fn op_5xy3(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        self.check_memory(self.i_register as usize, x.abs_diff(y) as usize + 1)?;
+
+        for offset in 0..=x.abs_diff(y) as usize {
+            let reg = register_in_range(x, y, offset);
+            self.registers[reg] = self.memory[self.i_register as usize + offset];
+        }
+
+        Ok(())
     }
 
     // 9XY0: Skip next instruction if VX does not equal VY
@@ -330,7 +606,7 @@ impl CPU {
         let vy = self.registers[y as usize];
 
         if vx != vy {
-            self.program_counter += 2;
+            self.skip_instruction();
         }
     }
 
@@ -340,8 +616,10 @@ impl CPU {
     }
 
     // BNNN: Jump to address NNN plus V0
+    //       With the jump quirk this is BXNN: jump to XNN plus VX
     fn op_bnnn(&mut self, nnn: u16) {
-        self.program_counter = self.registers[0] as u16 + nnn;
+        let reg = if self.quirks.jump_vx { (nnn >> 8) as usize } else { 0 };
+        self.program_counter = self.registers[reg] as u16 + nnn;
     }
 
     // CXNN: Set VX to the result of a bitwise AND operation on a random number and NN
@@ -351,73 +629,142 @@ impl CPU {
     }
 
     // DXYN: Draw a sprite at coordinate (VX, VY) with N bytes of sprite data starting at address I
-    fn op_dxyn(&mut self, x: usize, y: usize, rows: usize) {
-        let vx = self.registers[x];
-        let vy = self.registers[y];
+    //       The starting coordinate always wraps; with the clipping quirk pixels past the edge are dropped
+    //       DXY0 draws a 16x16 sprite from 32 bytes at I (SUPER-CHIP)
+    //       Each selected plane takes the next sprite's worth of bytes from I (XO-CHIP)
+    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> Result<(), CpuError> {
+        if self.quirks.display_wait {
+            if self.vblank_wait {
+                self.program_counter -= 2;
+                return Ok(());
+            }
+            self.vblank_wait = true;
+        }
+
+        let (width, height) = (self.width(), self.height());
+        let (columns, rows) = if n == 0 { (16, 16) } else { (8, n) };
+        let bytes_per_row = columns / 8;
+        let planes = (self.plane_mask & 1) + ((self.plane_mask >> 1) & 1);
+        self.check_memory(self.i_register as usize, rows * bytes_per_row * planes as usize)?;
+
+        let vx = self.registers[x] as usize % width;
+        let vy = self.registers[y] as usize % height;
         self.registers[0xF] = 0;
 
-        for row in 0..rows {
-            let font = self.memory[self.i_register as usize + row];
-            let y_pos = ((vy as u16 + row as u16) % 32) as usize;
+        let mut addr = self.i_register as usize;
 
-            for column in 0..8 {
-                //sprites are 8px wide
-                let x_pos = ((vx as u16 + column as u16) % 64) as usize;
-                let pixel = (font >> (7 - column)) & 1 != 0;
-                self.registers[0xF] |= (pixel & self.gfx[x_pos][y_pos]) as u8; //check for collision
-                self.gfx[x_pos][y_pos] ^= pixel;
+        for plane in [1, 2] {
+            if self.plane_mask & plane == 0 {
+                continue;
             }
+
+            for row in 0..rows {
+                let row_addr = addr + row * bytes_per_row;
+                if self.quirks.clipping && vy + row >= height {
+                    break;
+                }
+
+                let sprite_row = if bytes_per_row == 2 {
+                    (self.memory[row_addr] as u16) << 8 | self.memory[row_addr + 1] as u16
+                } else {
+                    (self.memory[row_addr] as u16) << 8
+                };
+                let y_pos = (vy + row) % height;
+
+                for column in 0..columns {
+                    if self.quirks.clipping && vx + column >= width {
+                        break;
+                    }
+
+                    let x_pos = (vx + column) % width;
+                    if (sprite_row >> (15 - column)) & 1 == 0 {
+                        continue;
+                    }
+
+                    if self.gfx[x_pos][y_pos] & plane != 0 {
+                        self.registers[0xF] = 1; //check for collision
+                    }
+                    self.gfx[x_pos][y_pos] ^= plane;
+                }
+            }
+
+            addr += rows * bytes_per_row;
         }
+
+        Ok(())
     }
 
     // EX9E: Skip next instruction if key stored in VX is pressed
     fn op_ex9e(&mut self, x: u8) {
         let vx = self.registers[x as usize];
-        if self.is_key_press(vx) {
-            self.program_counter += 2;
+        if self.is_key_down(vx) {
+            self.skip_instruction();
         }
     }
 
     // EXA1: Skip next instruction if key stored in VX is not pressed
     fn op_exa1(&mut self, x: u8) {
         let vx = self.registers[x as usize];
-        if !self.is_key_press(vx) {
-            self.program_counter += 2;
+        if !self.is_key_down(vx) {
+            self.skip_instruction();
         }
     }
 
+    // F000 NNNN: Set I to the 16-bit address in the following word (XO-CHIP)
+    fn op_f000(&mut self) -> Result<(), CpuError> {
+        let pc = self.program_counter as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(CpuError::PcOutOfRange { pc: self.current_pc(), opcode: self.opcode });
+        }
+
+        self.i_register = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        Ok(())
+    }
+
+    // FN01: Select the drawing planes given by the bitmask N (XO-CHIP)
+    fn op_fn01(&mut self, n: u8) {
+        self.plane_mask = n & 0x3;
+    }
+
+    // F002: Load the 16-byte audio pattern buffer from memory starting at address I (XO-CHIP)
+    fn op_f002(&mut self) -> Result<(), CpuError> {
+        let start = self.i_register as usize;
+        self.check_memory(start, 16)?;
+        self.audio_pattern.copy_from_slice(&self.memory[start..start + 16]);
+
+        Ok(())
+    }
+
     // FX07: Set VX to the value of the delay timer
     fn op_fx07(&mut self, x: u8) {
         self.registers[x as usize] = self.delay_timer;
     }
 
-    // FX0A: Wait for a keypress and store the result in register VX
+    // FX0A: Wait for a key to be pressed and released, then store it in register VX
+    //       Keys already held when the wait starts don't count until they are pressed again
     fn op_fx0a(&mut self, x: u8) {
-        if let Some(key) = self.key_press {
-            self.registers[x as usize] = key;
-            self.waiting_for_key = None;
-        } else {
-            self.waiting_for_key = Some(x);
-            self.program_counter -= 2;
-        }
+        self.waiting_for_key = Some(x);
+        self.keys_pressed_while_waiting = 0;
     }
 
     // FX18: Set the sound timer to VX
     fn op_fx18(&mut self, x: u8) {
-        let vx = self.registers[x as usize] as u8;
+        let vx = self.registers[x as usize];
         self.sound_timer = vx;
     }
 
     // FX15: Set the delay timer to VX
     fn op_fx15(&mut self, x: u8) {
-        let vx = self.registers[x as usize] as u8;
+        let vx = self.registers[x as usize];
         self.delay_timer = vx;
     }
 
     // FX1E: Add VX to I
     fn op_fx1e(&mut self, x: u8) {
         let vx = self.registers[x as usize] as u16;
-        self.i_register += vx;
+        self.i_register = self.i_register.wrapping_add(vx);
     }
 
     // FX29: Set I to the location of the sprite for the character in VX
@@ -427,48 +774,121 @@ impl CPU {
         self.i_register = vx * 5;
     }
 
+    // FX30: Set I to the location of the 10-byte big font sprite for the character in VX (SUPER-CHIP)
+    fn op_fx30(&mut self, x: u8) {
+        let vx = (self.registers[x as usize] & 0xF) as u16;
+
+        self.i_register = BIG_FONT_ADDR as u16 + vx * 10;
+    }
+
     // FX33: Store the binary-coded decimal representation of VX at addresses I, I+1, and I+2
-    fn op_fx33(&mut self, x: u8) {
+    fn op_fx33(&mut self, x: u8) -> Result<(), CpuError> {
+        self.check_memory(self.i_register as usize, 3)?;
+
         let vx = self.registers[x as usize];
-        let hundreds = (vx / 100) as u8;
-        let tens = (vx % 100 / 10) as u8;
-        let ones = (vx % 10) as u8;
+        let hundreds = vx / 100;
+        let tens = vx % 100 / 10;
+        let ones = vx % 10;
 
         self.memory[self.i_register as usize] = hundreds;
         self.memory[self.i_register as usize + 1] = tens;
         self.memory[self.i_register as usize + 2] = ones;
+
+        Ok(())
+    }
+
+    // FX3A: Set the audio pattern playback pitch to VX (XO-CHIP)
+    fn op_fx3a(&mut self, x: u8) {
+        self.pitch = self.registers[x as usize];
     }
 
     // FX55: Store V0 to VX (including VX) in memory starting at address I
-    fn op_fx55(&mut self, x: u8) {
-        let dl = x + 1;
+    fn op_fx55(&mut self, x: u8) -> Result<(), CpuError> {
+        let dl = x as usize + 1;
+        self.check_memory(self.i_register as usize, dl)?;
 
-        for i in 0..dl as u16 {
-            let reg_val = self.registers[i as usize];
-            self.memory[(self.i_register + i) as usize] = reg_val;
+        for i in 0..dl {
+            let reg_val = self.registers[i];
+            self.memory[self.i_register as usize + i] = reg_val;
         }
+
+        self.increment_i_after_load_store(x);
+
+        Ok(())
     }
 
     // FX65: Fill V0 to VX (including VX) with values from memory starting at address I
-    fn op_fx65(&mut self, x: u8) {
-        let dl = x + 1;
+    fn op_fx65(&mut self, x: u8) -> Result<(), CpuError> {
+        let dl = x as usize + 1;
+        self.check_memory(self.i_register as usize, dl)?;
 
-        for i in 0..dl as u16 {
-            let i_val = self.memory[(self.i_register + i) as usize];
-            self.registers[i as usize] = i_val;
+        for i in 0..dl {
+            let i_val = self.memory[self.i_register as usize + i];
+            self.registers[i] = i_val;
         }
+
+        self.increment_i_after_load_store(x);
+
+        Ok(())
+    }
+
+    // FX75: Store V0 to VX (including VX) in the RPL user flags (SUPER-CHIP)
+    fn op_fx75(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+    }
+
+    // FX85: Fill V0 to VX (including VX) from the RPL user flags (SUPER-CHIP)
+    fn op_fx85(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
     }
 
-    pub fn press_key(&mut self, key: Option<u8>) {
-        self.key_press = key;
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::None => {}
+            MemoryIncrement::X => self.i_register = self.i_register.wrapping_add(x as u16),
+            MemoryIncrement::XPlusOne => self.i_register = self.i_register.wrapping_add(x as u16 + 1),
+        }
+    }
+
+    // Front ends may call key_down/key_up every frame; only changes in state count as presses
+    pub fn key_down(&mut self, key: u8) {
+        let bit = 1 << (key & 0xF);
+        let newly_pressed = self.keypad & bit == 0;
+        self.keypad |= bit;
+
+        if newly_pressed && self.waiting_for_key.is_some() {
+            self.keys_pressed_while_waiting |= bit;
+        }
     }
 
-    fn is_key_press(&mut self, key_code: u8) -> bool {
-        if let Some(key) = self.key_press {
-            return key == key_code;
+    pub fn key_up(&mut self, key: u8) {
+        let bit = 1 << (key & 0xF);
+        let was_down = self.keypad & bit != 0;
+        self.keypad &= !bit;
+
+        if was_down
+            && let Some(reg) = self.waiting_for_key
+            && self.keys_pressed_while_waiting & bit != 0
+        {
+            self.registers[reg as usize] = key & 0xF;
+            self.waiting_for_key = None;
+            self.keys_pressed_while_waiting = 0;
         }
+    }
 
-        return false;
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.keypad & (1 << (key & 0xF)) != 0
+    }
+}
+
+// The register `offset` steps from X towards Y, for the XO-CHIP register range opcodes
+fn register_in_range(x: u8, y: u8, offset: usize) -> usize {
+    if x <= y {
+        x as usize + offset
+    } else {
+        x as usize - offset
     }
 }
 
@@ -491,5 +911,27 @@ pub static FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// SUPER-CHIP 8x10 digits, stored right after FONT_SET
+pub const BIG_FONT_ADDR: usize = 0x50;
+
+pub static BIG_FONT_SET: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 // 0x000-0x1FF - Chip 8 interpreter
 // 0x200-0xFFF - Program ROM and work RAM
@@ -0,0 +1,867 @@
+// Compiler for Octo (.8o), the language most CHIP-8 homebrew is written in. It follows the Octo
+// manual: `: label`, :const, :alias, :unpack, :next, :org, :byte, :call, :macro, :calc,
+// loop/while/again, if ... then / if ... begin ... else ... end and the SUPER-CHIP and XO-CHIP
+// instructions. Like Octo, the program starts at the label `main`.
+
+use std::collections::HashMap;
+
+use super::assembler::AsmError;
+use super::instruction::Instruction;
+use super::symbols::{SourceRef, Symbols};
+use super::MEMORY_SIZE;
+
+const ORIGIN: usize = 0x200;
+
+// Stops a macro that expands to itself before it eats all the memory
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    Nnn,  // low 12 bits of the opcode at the address
+    Long, // the word after an F000 at the address
+    Unpack, // the two v0 := / v1 := instructions of an :unpack at the address
+}
+
+// A reference to a label that wasn't defined yet when it was compiled
+struct Fixup {
+    address: usize,
+    kind: FixupKind,
+    name: String,
+    line: usize,
+}
+
+enum Block {
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+#[derive(Clone, Copy)]
+enum Rhs {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    Equal(u8, Rhs),
+    NotEqual(u8, Rhs),
+    Less(u8, Rhs),
+    Greater(u8, Rhs),
+    LessEqual(u8, Rhs),
+    GreaterEqual(u8, Rhs),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, rhs) => Condition::NotEqual(x, rhs),
+            Condition::NotEqual(x, rhs) => Condition::Equal(x, rhs),
+            Condition::Less(x, rhs) => Condition::GreaterEqual(x, rhs),
+            Condition::Greater(x, rhs) => Condition::LessEqual(x, rhs),
+            Condition::LessEqual(x, rhs) => Condition::Greater(x, rhs),
+            Condition::GreaterEqual(x, rhs) => Condition::Less(x, rhs),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    // There's no instruction that compares for order, so like Octo these subtract in VF and
+    // test the borrow flag: VF := rhs then VF =- VX leaves 1 when VX >= rhs, and VF -= VX
+    // leaves 1 when VX <= rhs
+    fn setup(self) -> Vec<Instruction> {
+        let (x, rhs, reverse) = match self {
+            Condition::Less(x, rhs) | Condition::GreaterEqual(x, rhs) => (x, rhs, true),
+            Condition::Greater(x, rhs) | Condition::LessEqual(x, rhs) => (x, rhs, false),
+            _ => return vec![],
+        };
+        let load = match rhs {
+            Rhs::Register(y) => Instruction::Move { x: 0xF, y },
+            Rhs::Byte(nn) => Instruction::LoadImm { x: 0xF, nn },
+        };
+        let subtract = if reverse { Instruction::SubReverse { x: 0xF, y: x } } else { Instruction::Sub { x: 0xF, y: x } };
+        vec![load, subtract]
+    }
+
+    // The instruction that skips the next one when the condition is false, which is what
+    // `if cond then` compiles to after setup()
+    fn skip_unless(self) -> Instruction {
+        match self {
+            Condition::Equal(x, Rhs::Byte(nn)) => Instruction::SkipNeImm { x, nn },
+            Condition::Equal(x, Rhs::Register(y)) => Instruction::SkipNeReg { x, y },
+            Condition::NotEqual(x, Rhs::Byte(nn)) => Instruction::SkipEqImm { x, nn },
+            Condition::NotEqual(x, Rhs::Register(y)) => Instruction::SkipEqReg { x, y },
+            Condition::Less(..) | Condition::Greater(..) => Instruction::SkipNeImm { x: 0xF, nn: 0 },
+            Condition::LessEqual(..) | Condition::GreaterEqual(..) => Instruction::SkipEqImm { x: 0xF, nn: 0 },
+            Condition::Key(x) => Instruction::SkipNotKey { x },
+            Condition::NotKey(x) => Instruction::SkipKey { x },
+        }
+    }
+}
+
+// The ROM along with its labels and which line each address came from
+pub fn compile(source: &str, file: &str) -> Result<(Vec<u8>, Symbols), AsmError> {
+    let tokens = tokenize(source);
+    let last_line = tokens.last().map(|token| token.line).unwrap_or(1);
+
+    let mut compiler = Compiler::new(tokens);
+    let rom = compiler
+        .run()
+        .map_err(|(line, message)| AsmError { file: file.to_string(), line: line.unwrap_or(last_line), message })?;
+
+    let text: Vec<&str> = source.lines().collect();
+    let symbols = Symbols {
+        labels: compiler.labels.into_iter().filter(|&(_, address)| address < MEMORY_SIZE).map(|(name, address)| (address as u16, name)).collect(),
+        lines: compiler
+            .lines
+            .into_iter()
+            .map(|(address, line)| {
+                let text = text.get(line - 1).map(|text| text.trim().to_string()).unwrap_or_default();
+                (address as u16, SourceRef { file: file.to_string(), line, text })
+            })
+            .collect(),
+    };
+    Ok((rom, symbols))
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for (index, text) in source.lines().enumerate() {
+        let code = text.split('#').next().unwrap_or("");
+        tokens.extend(code.split_whitespace().map(|word| Token { text: word.to_string(), line: index + 1 }));
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+// Errors carry the line of the token they are about, when there is one
+type CompileResult<T> = Result<T, (Option<usize>, String)>;
+
+struct Compiler {
+    tokens: Vec<Token>,
+    pos: usize,
+    rom: Vec<u8>, // from ORIGIN up to the highest address written
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    started: bool, // anything emitted yet
+    statement_start: Option<usize>, // where the current statement's first byte went
+    lines: Vec<(usize, usize)>, // address and source line of each statement that emitted code
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>) -> Compiler {
+        Compiler {
+            tokens,
+            pos: 0,
+            rom: vec![],
+            here: ORIGIN,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            started: false,
+            statement_start: None,
+            lines: vec![],
+            fixups: vec![],
+            blocks: vec![],
+        }
+    }
+
+    fn run(&mut self) -> CompileResult<Vec<u8>> {
+        while self.pos < self.tokens.len() {
+            let line = self.tokens[self.pos].line;
+            self.statement_start = None;
+            self.statement()?;
+            if let Some(address) = self.statement_start {
+                self.lines.push((address, line));
+            }
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let what = match block {
+                Block::If { .. } | Block::Else { .. } => "'begin' without 'end'",
+                Block::Loop { .. } => "'loop' without 'again'",
+            };
+            return Err((None, what.to_string()));
+        }
+        if !self.labels.contains_key("main") {
+            return Err((Some(1), "the program has no 'main' label".to_string()));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&target) = self.labels.get(&fixup.name) else {
+                return Err((Some(fixup.line), format!("undefined name '{}'", fixup.name)));
+            };
+            self.patch(fixup.address, fixup.kind, target).map_err(|message| (Some(fixup.line), message))?;
+        }
+
+        Ok(std::mem::take(&mut self.rom))
+    }
+
+    fn next(&mut self) -> CompileResult<Token> {
+        let token = self.tokens.get(self.pos).cloned().ok_or((None, "unexpected end of program".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> CompileResult<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err((Some(token.line), format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(())
+    }
+
+    // Programs that start with `: main` run from there, anything else gets a jump to it first
+    fn start(&mut self) -> CompileResult<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+        if !self.labels.contains_key("main") {
+            let here = std::mem::replace(&mut self.here, ORIGIN);
+            let statement_start = self.statement_start.take();
+            self.fixups.push(Fixup { address: ORIGIN, kind: FixupKind::Nnn, name: "main".to_string(), line: 1 });
+            self.instruction(Instruction::Jump { nnn: 0 })?;
+            self.here = here.max(self.here);
+            self.statement_start = statement_start;
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> CompileResult<()> {
+        self.start()?;
+        self.statement_start.get_or_insert(self.here);
+
+        if self.here >= MEMORY_SIZE {
+            return Err((None, "program doesn't fit in memory".to_string()));
+        }
+        let offset = self.here - ORIGIN;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> CompileResult<()> {
+        let [high, low] = instruction.encode().to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn patch(&mut self, address: usize, kind: FixupKind, target: usize) -> Result<(), String> {
+        let offset = address - ORIGIN;
+        match kind {
+            FixupKind::Nnn => {
+                if target > 0xFFF {
+                    return Err(format!("address {:#X} is out of range for a 12-bit operand", target));
+                }
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+            FixupKind::Long => {
+                self.rom[offset + 2] = (target >> 8) as u8;
+                self.rom[offset + 3] = target as u8;
+            }
+            FixupKind::Unpack => {
+                if target > 0xFFF {
+                    return Err(format!("address {:#X} is out of range for :unpack", target));
+                }
+                self.rom[offset + 1] = (self.rom[offset + 1] & 0xF0) | (target >> 8) as u8;
+                self.rom[offset + 3] = target as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, token: &Token, address: usize) -> CompileResult<()> {
+        if self.register_of(&token.text).is_some() {
+            return Err((Some(token.line), format!("'{}' is a register name", token.text)));
+        }
+        if self.labels.insert(token.text.clone(), address).is_some() {
+            return Err((Some(token.line), format!("'{}' is defined more than once", token.text)));
+        }
+        Ok(())
+    }
+
+    fn register_of(&self, text: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> CompileResult<u8> {
+        let token = self.next()?;
+        self.register_of(&token.text)
+            .ok_or((Some(token.line), format!("expected a register, found '{}'", token.text)))
+    }
+
+    // A number, constant, already defined label or { calc expression }
+    fn value(&mut self) -> CompileResult<(f64, usize)> {
+        if self.peek() == Some("{") {
+            let line = self.tokens[self.pos].line;
+            return Ok((self.calc_block()?, line));
+        }
+        let token = self.next()?;
+        let value = parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|&address| address as f64))
+            .ok_or((Some(token.line), format!("undefined name '{}'", token.text)))?;
+        Ok((value, token.line))
+    }
+
+    fn byte(&mut self) -> CompileResult<u8> {
+        let (value, line) = self.value()?;
+        let value = value as i64;
+        if !(-128..=255).contains(&value) {
+            return Err((Some(line), format!("{} doesn't fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> CompileResult<u8> {
+        let (value, line) = self.value()?;
+        if !(0.0..=15.0).contains(&value) {
+            return Err((Some(line), format!("{} doesn't fit in 4 bits", value)));
+        }
+        Ok(value as u8)
+    }
+
+    // An address operand. Labels can be used before they are defined; `opcode` is compiled with
+    // the address in place and patched once the label is known.
+    fn address_operand(&mut self, kind: FixupKind, opcode: impl Fn(u16) -> Instruction) -> CompileResult<()> {
+        let max = if let FixupKind::Long = kind { 0xFFFF } else { 0xFFF };
+        let address = self.address(kind, max)?;
+
+        match kind {
+            FixupKind::Long => {
+                self.instruction(Instruction::LoadILong)?;
+                self.emit((address >> 8) as u8)?;
+                self.emit(address as u8)
+            }
+            _ => self.instruction(opcode(address)),
+        }
+    }
+
+    // The address named by the next token, or 0 with a fixup of `kind` at the current address
+    // when it's a label that comes later
+    fn address(&mut self, kind: FixupKind, max: i64) -> CompileResult<u16> {
+        let token = self.next()?;
+        let known = parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).copied())
+            .map(|value| value as i64)
+            .or_else(|| self.labels.get(&token.text).map(|&address| address as i64));
+
+        match known {
+            Some(address) if (0..=max).contains(&address) => Ok(address as u16),
+            Some(address) => Err((Some(token.line), format!("address {:#X} is out of range", address))),
+            None => {
+                self.start()?;
+                self.fixups.push(Fixup { address: self.here, kind, name: token.text, line: token.line });
+                Ok(0)
+            }
+        }
+    }
+
+    fn skip_unless(&mut self, condition: Condition) -> CompileResult<()> {
+        for instruction in condition.setup() {
+            self.instruction(instruction)?;
+        }
+        self.instruction(condition.skip_unless())
+    }
+
+    // A jump whose target is filled in later by a block keyword
+    fn placeholder_jump(&mut self) -> CompileResult<usize> {
+        let address = self.here;
+        self.instruction(Instruction::Jump { nnn: 0 })?;
+        Ok(address)
+    }
+
+    fn resolve_jump(&mut self, address: usize) -> CompileResult<()> {
+        let here = self.here;
+        self.patch(address, FixupKind::Nnn, here).map_err(|message| (None, message))
+    }
+
+    fn condition(&mut self) -> CompileResult<Condition> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let rhs = match self.peek().and_then(|text| self.register_of(text)) {
+                    Some(y) => {
+                        self.pos += 1;
+                        Rhs::Register(y)
+                    }
+                    None => Rhs::Byte(self.byte()?),
+                };
+                Ok(match op.text.as_str() {
+                    "==" => Condition::Equal(x, rhs),
+                    "!=" => Condition::NotEqual(x, rhs),
+                    _ if x == 0xF => return Err((Some(op.line), format!("vf can't be compared with '{}', the comparison works in vf", op.text))),
+                    "<" => Condition::Less(x, rhs),
+                    ">" => Condition::Greater(x, rhs),
+                    "<=" => Condition::LessEqual(x, rhs),
+                    _ => Condition::GreaterEqual(x, rhs),
+                })
+            }
+            _ => Err((Some(op.line), format!("unsupported comparison '{}'", op.text))),
+        }
+    }
+
+    // Tokens up to the matching '}', with the braces consumed
+    fn braced(&mut self) -> CompileResult<Vec<Token>> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = vec![];
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn calc_block(&mut self) -> CompileResult<f64> {
+        let tokens = self.braced()?;
+        let mut pos = 0;
+        let value = self.expression(&tokens, &mut pos)?;
+        if let Some(token) = tokens.get(pos) {
+            return Err((Some(token.line), format!("unexpected '{}' in expression", token.text)));
+        }
+        Ok(value)
+    }
+
+    // Octo evaluates expressions right to left with no operator precedence; use parentheses
+    fn expression(&self, tokens: &[Token], pos: &mut usize) -> CompileResult<f64> {
+        let lhs = self.term(tokens, pos)?;
+        let Some(op) = tokens.get(*pos) else {
+            return Ok(lhs);
+        };
+        if op.text == ")" {
+            return Ok(lhs);
+        }
+
+        *pos += 1;
+        let rhs = self.expression(tokens, pos)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "<" => (lhs < rhs) as i64 as f64,
+            ">" => (lhs > rhs) as i64 as f64,
+            "<=" => (lhs <= rhs) as i64 as f64,
+            ">=" => (lhs >= rhs) as i64 as f64,
+            "==" => (lhs == rhs) as i64 as f64,
+            "!=" => (lhs != rhs) as i64 as f64,
+            _ => return Err((Some(op.line), format!("unknown operator '{}'", op.text))),
+        })
+    }
+
+    fn term(&self, tokens: &[Token], pos: &mut usize) -> CompileResult<f64> {
+        let token = tokens.get(*pos).ok_or((None, "incomplete expression".to_string()))?;
+        *pos += 1;
+
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| self.term(tokens, pos).map(f);
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, pos)?;
+                match tokens.get(*pos) {
+                    Some(close) if close.text == ")" => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err((Some(token.line), "'(' without ')'".to_string())),
+                }
+            }
+            "-" => unary(|v| -v, pos),
+            "~" => unary(|v| !(v as i64) as f64, pos),
+            "!" => unary(|v| (v == 0.0) as i64 as f64, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "tan" => unary(f64::tan, pos),
+            "exp" => unary(f64::exp, pos),
+            "log" => unary(f64::ln, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "sign" => unary(f64::signum, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "floor" => unary(f64::floor, pos),
+            "@" => {
+                // A byte already compiled into the program
+                let address = self.term(tokens, pos)? as usize;
+                Ok(address.checked_sub(ORIGIN).and_then(|offset| self.rom.get(offset)).copied().unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            name => parse_number(name)
+                .or_else(|| self.constants.get(name).copied())
+                .or_else(|| self.labels.get(name).map(|&address| address as f64))
+                .ok_or((Some(token.line), format!("undefined name '{}'", name))),
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str, line: usize) -> CompileResult<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err((Some(line), format!("too many macro expansions, does '{}' expand to itself?", name)));
+        }
+
+        let params = self.macros[name].params.clone();
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.next()?;
+            args.insert(param, arg.text);
+        }
+
+        let body: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token { text: args.get(&token.text).unwrap_or(&token.text).clone(), line })
+            .collect();
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> CompileResult<()> {
+        let token = self.next()?;
+        let line = Some(token.line);
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                if name.text != "main" {
+                    self.start()?;
+                }
+                let here = self.here;
+                self.define_label(&name, here)?;
+            }
+            ":next" => {
+                // Labels the operand byte of the next instruction, for self-modifying code
+                let name = self.next()?;
+                self.start()?;
+                let here = self.here + 1;
+                self.define_label(&name, here)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let (value, _) = self.value()?;
+                self.constants.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":unpack" => {
+                // v0 := high nibble of the address with the given nibble on top, v1 := low byte
+                let (nibble, _) = self.value()?;
+                let address = self.address(FixupKind::Unpack, 0xFFF)?;
+                let high = ((nibble as i64) << 4) as u8 | (address >> 8) as u8;
+                self.instruction(Instruction::LoadImm { x: 0, nn: high })?;
+                self.instruction(Instruction::LoadImm { x: 1, nn: address as u8 })?;
+            }
+            ":org" => {
+                let (address, address_line) = self.value()?;
+                if !(ORIGIN as f64..MEMORY_SIZE as f64).contains(&address) {
+                    return Err((Some(address_line), format!(":org {:#X} is outside program memory", address as i64)));
+                }
+                self.here = address as usize;
+            }
+            ":byte" => {
+                let (value, _) = self.value()?;
+                self.emit(value as i64 as u8)?;
+            }
+            ":call" => self.address_operand(FixupKind::Nnn, |nnn| Instruction::Call { nnn })?,
+            ":macro" => {
+                let name = self.next()?;
+                let mut params = vec![];
+                while self.peek().is_some_and(|text| text != "{") {
+                    params.push(self.next()?.text);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name.text, Macro { params, body });
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+
+            "return" | ";" => self.instruction(Instruction::Return)?,
+            "clear" => self.instruction(Instruction::Clear)?,
+            "hires" => self.instruction(Instruction::HiRes)?,
+            "lores" => self.instruction(Instruction::LoRes)?,
+            "exit" => self.instruction(Instruction::Exit)?,
+            "scroll-left" => self.instruction(Instruction::ScrollLeft)?,
+            "scroll-right" => self.instruction(Instruction::ScrollRight)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollDown { n })?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollUp { n })?;
+            }
+            "audio" => self.instruction(Instruction::Audio)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::Plane { n })?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(Instruction::Bcd { x })?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let store = token.text == "save";
+                if self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = self.register()?;
+                    self.instruction(if store { Instruction::StoreRange { x, y } } else { Instruction::LoadRange { x, y } })?;
+                } else {
+                    self.instruction(if store { Instruction::Store { x } } else { Instruction::Load { x } })?;
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::SaveFlags { x })?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LoadFlags { x })?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(Instruction::Draw { x, y, n })?;
+            }
+            "jump" => self.address_operand(FixupKind::Nnn, |nnn| Instruction::Jump { nnn })?,
+            "jump0" => self.address_operand(FixupKind::Nnn, |nnn| Instruction::JumpOffset { nnn })?,
+            "native" => self.address_operand(FixupKind::Nnn, |nnn| Instruction::Sys { nnn })?,
+
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.skip_unless(condition)?,
+                    "begin" => {
+                        self.skip_unless(condition.negate())?;
+                        let jump = self.placeholder_jump()?;
+                        self.blocks.push(Block::If { jump });
+                    }
+                    other => return Err((Some(keyword.line), format!("expected 'then' or 'begin', found '{}'", other))),
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump }) = self.blocks.pop() else {
+                    return Err((line, "'else' without 'if ... begin'".to_string()));
+                };
+                let skip_else = self.placeholder_jump()?;
+                self.resolve_jump(jump)?;
+                self.blocks.push(Block::Else { jump: skip_else });
+            }
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump } | Block::Else { jump }) => self.resolve_jump(jump)?,
+                _ => return Err((line, "'end' without 'begin'".to_string())),
+            },
+            "loop" => {
+                let start = self.here;
+                self.blocks.push(Block::Loop { start, exits: vec![] });
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_unless(condition.negate())?;
+                let exit = self.placeholder_jump()?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return Err((line, "'while' outside a loop".to_string())),
+                }
+            }
+            "again" => {
+                let Some(Block::Loop { start, exits }) = self.blocks.pop() else {
+                    return Err((line, "'again' without 'loop'".to_string()));
+                };
+                self.instruction(Instruction::Jump { nnn: start as u16 })?;
+                for exit in exits {
+                    self.resolve_jump(exit)?;
+                }
+            }
+
+            "i" => {
+                let op = self.next()?;
+                match (op.text.as_str(), self.peek()) {
+                    (":=", Some("long")) => {
+                        self.pos += 1;
+                        self.address_operand(FixupKind::Long, |_| Instruction::LoadILong)?;
+                    }
+                    (":=", Some("hex")) => {
+                        self.pos += 1;
+                        let x = self.register()?;
+                        self.instruction(Instruction::Font { x })?;
+                    }
+                    (":=", Some("bighex")) => {
+                        self.pos += 1;
+                        let x = self.register()?;
+                        self.instruction(Instruction::BigFont { x })?;
+                    }
+                    (":=", _) => self.address_operand(FixupKind::Nnn, |nnn| Instruction::LoadI { nnn })?,
+                    ("+=", _) => {
+                        let x = self.register()?;
+                        self.instruction(Instruction::AddI { x })?;
+                    }
+                    _ => return Err((Some(op.line), format!("can't use '{}' on i", op.text))),
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.instruction(match token.text.as_str() {
+                    "delay" => Instruction::SetDelay { x },
+                    "buzzer" => Instruction::SetSound { x },
+                    _ => Instruction::Pitch { x },
+                })?;
+            }
+
+            text if self.register_of(text).is_some() => {
+                let x = self.register_of(text).unwrap_or_default();
+                self.register_statement(x)?;
+            }
+            text if parse_number(text).is_some() => {
+                self.pos -= 1;
+                let byte = self.byte()?;
+                self.emit(byte)?;
+            }
+            text if self.macros.contains_key(text) => self.expand_macro(text, token.line)?,
+            text if self.constants.contains_key(text) => {
+                return Err((line, format!("constant '{}' can't be used as a statement", text)));
+            }
+            text if text.starts_with(':') => return Err((line, format!("unknown directive '{}'", text))),
+            _ => {
+                // A bare name calls the subroutine it labels
+                self.pos -= 1;
+                self.address_operand(FixupKind::Nnn, |nnn| Instruction::Call { nnn })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // vx := ..., vx += ..., etc.
+    fn register_statement(&mut self, x: u8) -> CompileResult<()> {
+        let op = self.next()?;
+        let rhs_register = self.peek().and_then(|text| self.register_of(text));
+
+        let instruction = match (op.text.as_str(), rhs_register) {
+            (":=", Some(y)) => Instruction::Move { x, y },
+            ("+=", Some(y)) => Instruction::Add { x, y },
+            ("-=", Some(y)) => Instruction::Sub { x, y },
+            ("=-", Some(y)) => Instruction::SubReverse { x, y },
+            ("|=", Some(y)) => Instruction::Or { x, y },
+            ("&=", Some(y)) => Instruction::And { x, y },
+            ("^=", Some(y)) => Instruction::Xor { x, y },
+            (">>=", Some(y)) => Instruction::ShiftRight { x, y },
+            ("<<=", Some(y)) => Instruction::ShiftLeft { x, y },
+            (":=", None) => match self.peek() {
+                Some("key") => Instruction::WaitKey { x },
+                Some("delay") => Instruction::GetDelay { x },
+                Some("random") => {
+                    self.pos += 1;
+                    let nn = self.byte()?;
+                    return self.instruction(Instruction::Random { x, nn });
+                }
+                _ => {
+                    let nn = self.byte()?;
+                    return self.instruction(Instruction::LoadImm { x, nn });
+                }
+            },
+            ("+=", None) => {
+                let nn = self.byte()?;
+                return self.instruction(Instruction::AddImm { x, nn });
+            }
+            ("-=", None) => {
+                let nn = self.byte()?;
+                return self.instruction(Instruction::AddImm { x, nn: nn.wrapping_neg() });
+            }
+            _ => return Err((Some(op.line), format!("can't use '{}' on a register here", op.text))),
+        };
+
+        // The right hand side was a single word
+        self.pos += 1;
+        self.instruction(instruction)
+    }
+}
@@ -0,0 +1,103 @@
+// Several CHIP-8 opcodes behave differently depending on which interpreter a
+// ROM was written for. Each flag below selects one of those interpretations.
+
+use serde::{Deserialize, Serialize};
+
+// How FX55/FX65 leave the I register once they are done
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryIncrement {
+    None,     // I is left untouched (SUPER-CHIP)
+    X,        // I = I + X (CHIP-48)
+    XPlusOne, // I = I + X + 1 (COSMAC VIP)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    pub shift: bool,                       // 8XY6/8XYE shift VX in place and ignore VY
+    pub memory_increment: MemoryIncrement, // FX55/FX65 advance I
+    pub jump_vx: bool,                     // BNNN jumps to XNN + VX instead of NNN + V0
+    pub clipping: bool,                    // DXYN clips sprites at the screen edge instead of wrapping
+    pub vf_reset: bool,                    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub display_wait: bool,                // DXYN waits for the next 60 Hz tick before drawing
+}
+
+pub const PROFILE_NAMES: [&str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+impl Quirks {
+    // The original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift: false,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_vx: false,
+            clipping: true,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: true,
+            memory_increment: MemoryIncrement::X,
+            jump_vx: true,
+            clipping: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift: true,
+            memory_increment: MemoryIncrement::None,
+            jump_vx: true,
+            clipping: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift: false,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_vx: false,
+            clipping: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    // What most modern ROMs expect, and what this emulator has always done
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift: false,
+            memory_increment: MemoryIncrement::None,
+            jump_vx: false,
+            clipping: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "chip8" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" | "superchip" => Some(Quirks::superchip()),
+            "xochip" | "octo" => Some(Quirks::xo_chip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
@@ -0,0 +1,301 @@
+// Rewind history: a ring of segments, each a full snapshot (keyframe) followed by one small
+// undo record (delta) per executed step. Stepping back undoes the newest delta; once a
+// segment's deltas are used up the machine is back at its keyframe.
+//
+// When the history outgrows its budget the deltas of the oldest segments are dropped first,
+// leaving bare keyframes that can still be rewound to a whole segment at a time. After that
+// the oldest keyframes go.
+
+use std::collections::VecDeque;
+
+use super::{Clock, CpuError, CPU, MEMORY_SIZE, STACK_SIZE};
+
+// Every opcode that writes memory (5XY2, FX33, FX55) writes at most this many bytes from I
+const MEMORY_WRITE_WINDOW: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewindConfig {
+    pub max_bytes: usize,       // upper bound on memory used by the history; 0 disables rewind
+    pub snapshot_interval: u32, // frames between keyframes
+}
+
+impl Default for RewindConfig {
+    fn default() -> RewindConfig {
+        RewindConfig {
+            max_bytes: 32 * 1024 * 1024,
+            snapshot_interval: 60,
+        }
+    }
+}
+
+// Everything a single step can change apart from memory and the framebuffer. The keypad is
+// left out on purpose: it reflects the host's keys right now, not the machine's past.
+#[derive(Clone, Copy)]
+struct CoreState {
+    opcode: u16,
+    registers: [u8; 16],
+    i_register: u16,
+    program_counter: u16,
+    plane_mask: u8,
+    hires: bool,
+    delay_timer: u8,
+    sound_timer: u8,
+    stack: [u16; STACK_SIZE],
+    stack_len: u8,
+    sp: u8,
+    waiting_for_key: Option<u8>,
+    keys_pressed_while_waiting: u16,
+    vblank_wait: bool,
+    rpl_flags: [u8; 16],
+    exited: bool,
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    clock: Clock,
+    frame_cycles_left: u32,
+}
+
+impl CoreState {
+    fn capture(cpu: &CPU) -> CoreState {
+        let mut stack = [0; STACK_SIZE];
+        let stack_len = cpu.stack.len().min(STACK_SIZE);
+        stack[..stack_len].copy_from_slice(&cpu.stack[..stack_len]);
+
+        CoreState {
+            opcode: cpu.opcode,
+            registers: cpu.registers,
+            i_register: cpu.i_register,
+            program_counter: cpu.program_counter,
+            plane_mask: cpu.plane_mask,
+            hires: cpu.hires,
+            delay_timer: cpu.delay_timer,
+            sound_timer: cpu.sound_timer,
+            stack,
+            stack_len: stack_len as u8,
+            sp: cpu.sp,
+            waiting_for_key: cpu.waiting_for_key,
+            keys_pressed_while_waiting: cpu.keys_pressed_while_waiting,
+            vblank_wait: cpu.vblank_wait,
+            rpl_flags: cpu.rpl_flags,
+            exited: cpu.exited,
+            audio_pattern: cpu.audio_pattern,
+            pitch: cpu.pitch,
+            clock: cpu.clock,
+            frame_cycles_left: cpu.frame_cycles_left,
+        }
+    }
+
+    fn restore(&self, cpu: &mut CPU) {
+        cpu.opcode = self.opcode;
+        cpu.registers = self.registers;
+        cpu.i_register = self.i_register;
+        cpu.program_counter = self.program_counter;
+        cpu.plane_mask = self.plane_mask;
+        cpu.hires = self.hires;
+        cpu.delay_timer = self.delay_timer;
+        cpu.sound_timer = self.sound_timer;
+        cpu.stack.clear();
+        cpu.stack.extend_from_slice(&self.stack[..self.stack_len as usize]);
+        cpu.sp = self.sp;
+        cpu.waiting_for_key = self.waiting_for_key;
+        cpu.keys_pressed_while_waiting = self.keys_pressed_while_waiting;
+        cpu.vblank_wait = self.vblank_wait;
+        cpu.rpl_flags = self.rpl_flags;
+        cpu.exited = self.exited;
+        cpu.audio_pattern = self.audio_pattern;
+        cpu.pitch = self.pitch;
+        cpu.clock = self.clock;
+        cpu.frame_cycles_left = self.frame_cycles_left;
+    }
+}
+
+// Undo record for one step
+struct Delta {
+    core: CoreState,
+    memory_addr: usize,
+    memory: [u8; MEMORY_WRITE_WINDOW],
+    pixels: Vec<(u8, u8, u8)>, // (x, y, old colour) for every pixel the step changed
+    ticked: bool,              // the step ended a frame
+}
+
+impl Delta {
+    fn size(&self) -> usize {
+        std::mem::size_of::<Delta>() + self.pixels.len() * std::mem::size_of::<(u8, u8, u8)>()
+    }
+
+    fn undo(&self, cpu: &mut CPU) {
+        self.core.restore(cpu);
+
+        let len = MEMORY_WRITE_WINDOW.min(MEMORY_SIZE - self.memory_addr);
+        cpu.memory[self.memory_addr..self.memory_addr + len].copy_from_slice(&self.memory[..len]);
+
+        for &(x, y, colour) in self.pixels.iter() {
+            cpu.gfx[x as usize][y as usize] = colour;
+        }
+    }
+}
+
+struct Segment {
+    keyframe: Vec<u8>, // CPU::save_state from just before the segment's first step
+    deltas: Vec<Delta>,
+}
+
+pub struct Rewind {
+    pub config: RewindConfig,
+    segments: VecDeque<Segment>,
+    bytes_used: usize,
+    frames_in_segment: u32,
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Rewind {
+        Rewind {
+            config,
+            segments: VecDeque::new(),
+            bytes_used: 0,
+            frames_in_segment: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.bytes_used = 0;
+        self.frames_in_segment = 0;
+    }
+
+    pub fn bytes_used(&self) -> usize {
+        self.bytes_used
+    }
+
+    // CPU::step with an undo record kept for it. Faulting steps are recorded too, so the
+    // debugger can step back out of a fault.
+    pub fn step(&mut self, cpu: &mut CPU) -> Result<bool, CpuError> {
+        if self.config.max_bytes == 0 {
+            return cpu.step();
+        }
+
+        if self.segments.is_empty() || self.frames_in_segment >= self.config.snapshot_interval.max(1) {
+            let keyframe = cpu.save_state();
+            self.bytes_used += keyframe.len();
+            self.segments.push_back(Segment { keyframe, deltas: vec![] });
+            self.frames_in_segment = 0;
+        }
+
+        let core = CoreState::capture(cpu);
+        let memory_addr = (cpu.i_register as usize).min(MEMORY_SIZE - 1);
+        let mut memory = [0; MEMORY_WRITE_WINDOW];
+        let len = MEMORY_WRITE_WINDOW.min(MEMORY_SIZE - memory_addr);
+        memory[..len].copy_from_slice(&cpu.memory[memory_addr..memory_addr + len]);
+
+        // Only the display opcodes (0x00XX and DXYN) touch the framebuffer
+        let pc = cpu.program_counter as usize;
+        let next_op = if pc < MEMORY_SIZE { cpu.memory[pc] >> 4 } else { 0 };
+        let gfx_before = if next_op == 0x0 || next_op == 0xD { Some(cpu.gfx) } else { None };
+
+        let result = cpu.step();
+
+        let mut pixels = vec![];
+        if let Some(before) = gfx_before {
+            for (x, (old_column, new_column)) in before.iter().zip(cpu.gfx.iter()).enumerate() {
+                for (y, (&old, &new)) in old_column.iter().zip(new_column.iter()).enumerate() {
+                    if old != new {
+                        pixels.push((x as u8, y as u8, old));
+                    }
+                }
+            }
+        }
+
+        let ticked = matches!(result, Ok(true));
+        let delta = Delta { core, memory_addr, memory, pixels, ticked };
+        self.bytes_used += delta.size();
+        if let Some(segment) = self.segments.back_mut() {
+            segment.deltas.push(delta);
+        }
+        if ticked {
+            self.frames_in_segment += 1;
+        }
+
+        self.enforce_budget();
+
+        result
+    }
+
+    // Undo the most recent step. Returns false when there is no history left.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        let Some(segment) = self.segments.back_mut() else {
+            return false;
+        };
+
+        match segment.deltas.pop() {
+            Some(delta) => {
+                self.bytes_used -= delta.size();
+                delta.undo(cpu);
+            }
+            None => {
+                // Only a keyframe is left: jump straight back to it
+                let keypad = cpu.keypad;
+                if cpu.load_state(&segment.keyframe).is_err() {
+                    return false;
+                }
+                cpu.keypad = keypad;
+            }
+        }
+
+        // Back at the keyframe, so the segment holds nothing more to undo
+        if self.segments.back().is_some_and(|segment| segment.deltas.is_empty()) {
+            self.drop_newest_segment();
+        }
+        self.resume_recording_point();
+
+        true
+    }
+
+    // Undo whole steps until the previous frame boundary. Returns false when there is no history left.
+    pub fn step_back_frame(&mut self, cpu: &mut CPU) -> bool {
+        if !self.step_back(cpu) {
+            return false;
+        }
+
+        while let Some(segment) = self.segments.back()
+            && segment.deltas.last().is_some_and(|delta| !delta.ticked)
+        {
+            self.step_back(cpu);
+        }
+
+        true
+    }
+
+    fn drop_newest_segment(&mut self) {
+        if let Some(segment) = self.segments.pop_back() {
+            self.bytes_used -= segment.keyframe.len();
+            self.bytes_used -= segment.deltas.iter().map(Delta::size).sum::<usize>();
+        }
+    }
+
+    // New steps may only extend the newest segment if its deltas run right up to the current
+    // state. A bare keyframe doesn't, so recording then starts a fresh segment.
+    fn resume_recording_point(&mut self) {
+        self.frames_in_segment = match self.segments.back() {
+            Some(segment) if !segment.deltas.is_empty() => {
+                segment.deltas.iter().filter(|delta| delta.ticked).count() as u32
+            }
+            _ => u32::MAX,
+        };
+    }
+
+    fn enforce_budget(&mut self) {
+        // Thin out the oldest segments to bare keyframes first, never the one being recorded
+        let mut index = 0;
+        while self.bytes_used > self.config.max_bytes && index + 1 < self.segments.len() {
+            let freed: usize = self.segments[index].deltas.drain(..).map(|delta| delta.size()).sum();
+            self.bytes_used -= freed;
+            index += 1;
+        }
+
+        while self.bytes_used > self.config.max_bytes && self.segments.len() > 1 {
+            if let Some(segment) = self.segments.pop_front() {
+                self.bytes_used -= segment.keyframe.len();
+                self.bytes_used -= segment.deltas.iter().map(Delta::size).sum::<usize>();
+            }
+        }
+    }
+}
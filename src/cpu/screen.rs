@@ -0,0 +1,33 @@
+// Text dumps of the visible framebuffer, for headless runs and golden-screen comparisons
+
+use super::CPU;
+
+// One character per pixel colour: off, plane 1, plane 2, both planes
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+impl CPU {
+    // One line per row at the current resolution
+    pub fn screen_to_ascii(&self) -> String {
+        let mut out = String::with_capacity((self.width() + 1) * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                out.push(ASCII_PIXELS[(self.gfx[x][y] & 0x3) as usize]);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Plain (P1) PBM, any lit plane counts as black
+    pub fn screen_to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width(), self.height());
+        for y in 0..self.height() {
+            let row: Vec<&str> = (0..self.width())
+                .map(|x| if self.gfx[x][y] != 0 { "1" } else { "0" })
+                .collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+}
@@ -0,0 +1,110 @@
+// What the user changed for a ROM, remembered between sessions and put back the next time the
+// ROM loads. The store is one JSON file keyed by the SHA-1 of each ROM:
+//
+//   { "b9bbc12cee3f...": { "cycles_per_frame": 15, "breakpoints": ["break 0x22a"] } }
+//
+// Anything a ROM's entry leaves out keeps the value from the database or the command line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Clock, ClockSpeed, CPU, Quirks};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycles_per_frame: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "hex_palette")]
+    pub palette: Option<[u32; 4]>, // RGB for off, plane 1, plane 2 and both, written "#RRGGBB"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, u8>, // host key name to the CHIP-8 key it presses, on top of the keypad
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub breakpoints: Vec<String>, // as typed on the debugger's command line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_slot: Option<u8>, // the save state slot last saved to or loaded from
+}
+
+impl RomSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == RomSettings::default()
+    }
+
+    // The speed and quirks; the front end looks after the rest
+    pub fn apply(&self, cpu: &mut CPU) {
+        if let Some(quirks) = self.quirks {
+            cpu.quirks = quirks;
+        }
+        if let Some(cycles) = self.cycles_per_frame {
+            cpu.clock = Clock::new(ClockSpeed::CyclesPerFrame(cycles));
+        }
+    }
+}
+
+// "#FF6600" or "FF6600"
+pub fn parse_color(text: &str) -> Option<u32> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+mod hex_palette {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(palette: &Option<[u32; 4]>, serializer: S) -> Result<S::Ok, S::Error> {
+        let colors = palette.map(|palette| palette.map(|color| format!("#{:06X}", color)));
+        serializer.serialize_some(&colors)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u32; 4]>, D::Error> {
+        let Some(colors) = Option::<[String; 4]>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        let mut palette = [0; 4];
+        for (color, text) in palette.iter_mut().zip(&colors) {
+            *color = super::parse_color(text).ok_or_else(|| D::Error::custom(format!("bad colour '{}'", text)))?;
+        }
+        Ok(Some(palette))
+    }
+}
+
+pub struct SettingsStore {
+    path: PathBuf,
+    roms: BTreeMap<String, RomSettings>, // by SHA-1
+}
+
+impl SettingsStore {
+    // A file that doesn't exist yet is an empty store; it's created on the first save
+    pub fn load(path: &Path) -> Result<SettingsStore, String> {
+        let roms = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Ok(SettingsStore { path: path.to_path_buf(), roms })
+    }
+
+    pub fn get(&self, hash: &str) -> RomSettings {
+        self.roms.get(hash).cloned().unwrap_or_default()
+    }
+
+    // Stores a ROM's settings and writes the whole file straight away
+    pub fn set(&mut self, hash: &str, settings: RomSettings) -> Result<(), String> {
+        if settings.is_empty() {
+            self.roms.remove(hash);
+        } else {
+            self.roms.insert(hash.to_string(), settings);
+        }
+
+        let text = serde_json::to_string_pretty(&self.roms).map_err(|e| e.to_string())?;
+        fs::write(&self.path, text + "\n").map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+}
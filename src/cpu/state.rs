@@ -0,0 +1,257 @@
+// Save states: the whole machine serialized to a small versioned binary format.
+//
+// Layout (all multi-byte values little endian):
+//   magic "CH8S", u16 version, then every field of the machine in the order written by
+//   CPU::save_state. Bump STATE_VERSION whenever that order or a field's size changes.
+//
+// The clock speed is not part of the state; it belongs to whoever is running the machine. The
+// clock's leftover fraction of an instruction is, so a loaded state keeps its timing.
+
+use std::fmt;
+
+use super::quirks::{MemoryIncrement, Quirks};
+use super::clock::TIMER_HZ;
+use super::{CPU, MEMORY_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH, STACK_SIZE};
+
+pub const STATE_MAGIC: [u8; 4] = *b"CH8S";
+pub const STATE_VERSION: u16 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a CHIP-8 save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "save state version {} is not supported (expected {})", version, STATE_VERSION)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state is corrupt: invalid {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self, what: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid(what)),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+}
+
+fn memory_increment_tag(increment: MemoryIncrement) -> u8 {
+    match increment {
+        MemoryIncrement::None => 0,
+        MemoryIncrement::X => 1,
+        MemoryIncrement::XPlusOne => 2,
+    }
+}
+
+impl CPU {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MEMORY_SIZE + SCREEN_WIDTH * SCREEN_HEIGHT + 128);
+
+        out.extend_from_slice(&STATE_MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+
+        out.extend_from_slice(&self.opcode.to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.i_register.to_le_bytes());
+        out.extend_from_slice(&self.program_counter.to_le_bytes());
+        for column in self.gfx.iter() {
+            out.extend_from_slice(column);
+        }
+        out.push(self.plane_mask);
+        out.push(self.hires as u8);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+
+        out.push(self.stack.len() as u8);
+        for address in self.stack.iter() {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        out.push(self.sp);
+
+        out.extend_from_slice(&self.keypad.to_le_bytes());
+        match self.waiting_for_key {
+            Some(reg) => out.extend_from_slice(&[1, reg]),
+            None => out.extend_from_slice(&[0, 0]),
+        }
+        out.extend_from_slice(&self.keys_pressed_while_waiting.to_le_bytes());
+
+        out.extend_from_slice(&(self.program_size as u32).to_le_bytes());
+        out.push(self.quirks.shift as u8);
+        out.push(memory_increment_tag(self.quirks.memory_increment));
+        out.push(self.quirks.jump_vx as u8);
+        out.push(self.quirks.clipping as u8);
+        out.push(self.quirks.vf_reset as u8);
+        out.push(self.quirks.display_wait as u8);
+        out.push(self.vblank_wait as u8);
+        out.extend_from_slice(&self.rpl_flags);
+        out.push(self.exited as u8);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.frame_cycles_left.to_le_bytes());
+        out.extend_from_slice(&self.clock.leftover().to_le_bytes());
+
+        out
+    }
+
+    // The state is fully decoded before anything is touched, so a bad file leaves the machine as it was
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader { bytes, pos: 0 };
+
+        if r.take(4).map_err(|_| StateError::BadMagic)? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let opcode = r.u16()?;
+        let memory: [u8; MEMORY_SIZE] = r.array()?;
+        let registers: [u8; 16] = r.array()?;
+        let i_register = r.u16()?;
+        let program_counter = r.u16()?;
+        let mut gfx = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+        for column in gfx.iter_mut() {
+            *column = r.array()?;
+        }
+        // A pixel holds one bit per plane, and there are only two planes
+        if gfx.iter().flatten().any(|&pixel| pixel > 0x3) {
+            return Err(StateError::Invalid("pixel"));
+        }
+        let plane_mask = r.u8()?;
+        if plane_mask > 0x3 {
+            return Err(StateError::Invalid("plane mask"));
+        }
+        let hires = r.bool("display mode")?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+
+        let stack_len = r.u8()? as usize;
+        if stack_len > STACK_SIZE {
+            return Err(StateError::Invalid("stack depth"));
+        }
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            stack.push(r.u16()?);
+        }
+        let sp = r.u8()?;
+        if sp as usize != stack_len {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+
+        let keypad = r.u16()?;
+        let waiting = r.bool("key wait flag")?;
+        let waiting_reg = r.u8()?;
+        if waiting_reg > 0xF {
+            return Err(StateError::Invalid("key wait register"));
+        }
+        let waiting_for_key = if waiting { Some(waiting_reg) } else { None };
+        let keys_pressed_while_waiting = r.u16()?;
+
+        let program_size = r.u32()? as usize;
+        if program_size > MEMORY_SIZE {
+            return Err(StateError::Invalid("program size"));
+        }
+        let quirks = Quirks {
+            shift: r.bool("shift quirk")?,
+            memory_increment: match r.u8()? {
+                0 => MemoryIncrement::None,
+                1 => MemoryIncrement::X,
+                2 => MemoryIncrement::XPlusOne,
+                _ => return Err(StateError::Invalid("memory increment quirk")),
+            },
+            jump_vx: r.bool("jump quirk")?,
+            clipping: r.bool("clipping quirk")?,
+            vf_reset: r.bool("VF reset quirk")?,
+            display_wait: r.bool("display wait quirk")?,
+        };
+        let vblank_wait = r.bool("display wait flag")?;
+        let rpl_flags: [u8; 16] = r.array()?;
+        let exited = r.bool("exit flag")?;
+        let audio_pattern: [u8; 16] = r.array()?;
+        let pitch = r.u8()?;
+        let frame_cycles_left = r.u32()?;
+        let clock_leftover = r.u32()?;
+        if clock_leftover >= TIMER_HZ {
+            return Err(StateError::Invalid("clock leftover"));
+        }
+
+        if r.pos != bytes.len() {
+            return Err(StateError::Invalid("trailing data"));
+        }
+
+        self.opcode = opcode;
+        self.memory = memory;
+        self.registers = registers;
+        self.i_register = i_register;
+        self.program_counter = program_counter;
+        self.gfx = gfx;
+        self.plane_mask = plane_mask;
+        self.hires = hires;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.sp = sp;
+        self.keypad = keypad;
+        self.waiting_for_key = waiting_for_key;
+        self.keys_pressed_while_waiting = keys_pressed_while_waiting;
+        self.program_size = program_size;
+        self.quirks = quirks;
+        self.vblank_wait = vblank_wait;
+        self.rpl_flags = rpl_flags;
+        self.exited = exited;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.frame_cycles_left = frame_cycles_left;
+        self.clock.resume(clock_leftover);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// Labels and a source map for a program, so the debugger can show names instead of raw
+// addresses. `chip8 asm --symbols` writes them next to the ROM with a .sym extension, and the
+// debugger and `chip8 disasm` pick that file up when they load the ROM. One entry per line:
+//
+//   label   0x0200  main
+//   line    0x0200  game.asm  12  CLS
+//
+// Fields are tab separated and '#' starts a comment. A plain "0x0200 main" line is also taken
+// as a label, which covers the symbol lists most other tools write.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use super::disassembler::Labels;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceRef {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    pub labels: Labels,
+    pub lines: BTreeMap<u16, SourceRef>, // by the address of the first byte each line assembled to
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('$'))?;
+    u16::from_str_radix(hex, 16).ok()
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.splitn(5, '\t').map(str::trim).collect();
+            match fields.as_slice() {
+                ["label", address, name] => {
+                    let address = parse_address(address).ok_or_else(|| error("bad address"))?;
+                    symbols.labels.insert(address, name.to_string());
+                }
+                ["line", address, file, number, text @ ..] => {
+                    let address = parse_address(address).ok_or_else(|| error("bad address"))?;
+                    let number = number.parse().map_err(|_| error("bad line number"))?;
+                    let text = text.first().unwrap_or(&"").to_string();
+                    symbols.lines.insert(address, SourceRef { file: file.to_string(), line: number, text });
+                }
+                _ => {
+                    let words: Vec<&str> = line.split_whitespace().collect();
+                    let [address, name] = words.as_slice() else {
+                        return Err(error("expected a 'label' or 'line' entry"));
+                    };
+                    let address = parse_address(address).ok_or_else(|| error("bad address"))?;
+                    symbols.labels.insert(address, name.to_string());
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Symbols::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (address, name) in &self.labels {
+            text += &format!("label\t{:#06x}\t{}\n", address, name);
+        }
+        for (address, source) in &self.lines {
+            text += &format!("line\t{:#06x}\t{}\t{}\t{}\n", address, source.file, source.line, source.text);
+        }
+        text
+    }
+
+    // The source line an address was assembled from
+    pub fn source_at(&self, address: u16) -> Option<&SourceRef> {
+        self.lines.range(..=address).next_back().map(|(_, source)| source)
+    }
+}
+
+// An address as the nearest label at or before it plus an offset, like "draw+0x6", for return
+// addresses and other places inside a routine
+pub fn symbolize(labels: &Labels, address: u16) -> Option<String> {
+    let (&start, name) = labels.range(..=address).next_back()?;
+    Some(match address - start {
+        0 => name.clone(),
+        offset => format!("{}+{:#X}", name, offset),
+    })
+}
@@ -0,0 +1,204 @@
+// `chip8 run`: execute a ROM with no UI, for scripts and CI.
+//
+// Exit codes: 0 on success, 1 when an --assert-reg/--assert-mem check fails, 2 on a CPU fault,
+// 3 when the ROM can't be loaded or the WAV can't be written, and 64 for a bad command line.
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use clap::{Args, ValueEnum};
+
+use super::asm;
+use super::cpu;
+use super::cpu::breakpoints::{parse_number, parse_operand};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ScreenFormat {
+    Ascii,
+    Pbm,
+}
+
+#[derive(Args)]
+#[command(group(clap::ArgGroup::new("limit").required(true).multiple(true).args(["cycles", "frames"])))]
+pub struct RunArgs {
+    #[arg(help = "ROM file to run, or an Octo source (.8o)")]
+    pub rom: String,
+
+    #[arg(long, help = "Stop after this many instructions")]
+    pub cycles: Option<u64>,
+
+    #[arg(long, help = "Stop after this many 60 Hz frames")]
+    pub frames: Option<u64>,
+
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "ascii", help = "Print the final screen to stdout")]
+    pub dump_screen: Option<ScreenFormat>,
+
+    #[arg(long = "assert-reg", value_name = "REG=VALUE", help = "Check a register when the run ends, e.g. V3=0x05, I=0x300, PC=0x2A0")]
+    pub assert_reg: Vec<String>,
+
+    #[arg(long = "poke", value_name = "ADDR=VALUE", help = "Set a memory byte after the ROM is loaded, e.g. 0x1FF=1")]
+    pub poke: Vec<String>,
+
+    #[arg(long = "press", value_name = "FRAME:KEY[:HOLD]", help = "Press a key at the start of a frame and hold it for HOLD frames (default 2), e.g. 30:A")]
+    pub press: Vec<String>,
+
+    #[arg(long = "assert-mem", value_name = "ADDR=VALUE", help = "Check a memory byte when the run ends, e.g. 0x300=0x12")]
+    pub assert_mem: Vec<String>,
+}
+
+const FAULT_EXIT: u8 = 2;
+const ASSERT_EXIT: u8 = 1;
+pub const IO_EXIT: u8 = 3;
+// EX_USAGE from sysexits.h, so it can't be mistaken for anything the ROM did
+pub const USAGE_EXIT: u8 = 64;
+
+const DEFAULT_HOLD_FRAMES: u64 = 2;
+
+struct KeyPress {
+    frame: u64,
+    key: u8,
+    hold: u64,
+}
+
+fn parse_key_press(text: &str) -> Result<KeyPress, String> {
+    let parts: Vec<&str> = text.split(':').collect();
+    let (frame, key, hold) = match parts.as_slice() {
+        [frame, key] => (frame, key, None),
+        [frame, key, hold] => (frame, key, Some(hold)),
+        _ => return Err(format!("key press '{}' should look like FRAME:KEY[:HOLD]", text)),
+    };
+
+    let key = match u8::from_str_radix(key, 16) {
+        Ok(key) if key < 16 => key,
+        _ => return Err(format!("'{}' is not a keypad key (0-F)", key)),
+    };
+    let hold = match hold {
+        Some(hold) => parse_number(hold)? as u64,
+        None => DEFAULT_HOLD_FRAMES,
+    };
+
+    Ok(KeyPress { frame: parse_number(frame)? as u64, key, hold: hold.max(1) })
+}
+
+// Like CPU::run_for, but applies the scripted key presses between frames
+fn run_script(cpu: &mut cpu::CPU, args: &RunArgs, presses: &[KeyPress]) -> Result<(), cpu::CpuError> {
+    let (mut cycles, mut frame) = (0, 0);
+
+    while !cpu.exited
+        && args.cycles.is_none_or(|max| cycles < max)
+        && args.frames.is_none_or(|max| frame < max)
+    {
+        for press in presses.iter() {
+            if press.frame == frame {
+                cpu.key_down(press.key);
+            }
+            if press.frame + press.hold == frame {
+                cpu.key_up(press.key);
+            }
+        }
+
+        let (ran, _) = cpu.run_for(args.cycles.map(|max| max - cycles), Some(1))?;
+        cycles += ran;
+        frame += 1;
+    }
+
+    Ok(())
+}
+
+fn split_assertion(text: &str) -> Result<(&str, u32), String> {
+    let (target, value) = text
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should look like NAME=VALUE", text))?;
+    Ok((target.trim(), parse_number(value.trim())?))
+}
+
+// Returns a failure message when the assertion doesn't hold
+fn check_register(cpu: &cpu::CPU, assertion: &str) -> Result<Option<String>, String> {
+    let (register, expected) = split_assertion(assertion)?;
+
+    let actual = parse_operand(register)?.value(cpu) as u32;
+
+    Ok((actual != expected).then(|| format!("{} is {:#x}, expected {:#x}", register, actual, expected)))
+}
+
+fn check_memory(cpu: &cpu::CPU, assertion: &str) -> Result<Option<String>, String> {
+    let (address, expected) = split_assertion(assertion)?;
+    let address = parse_number(address)? as usize;
+
+    let Some(&actual) = cpu.memory.get(address) else {
+        return Err(format!("address {:#x} is out of range", address));
+    };
+
+    Ok((actual as u32 != expected).then(|| format!("memory[{:#x}] is {:#x}, expected {:#x}", address, actual, expected)))
+}
+
+fn fail(code: u8, message: impl std::fmt::Display) -> ExitCode {
+    eprintln!("Error: {}", message);
+    ExitCode::from(code)
+}
+
+fn poke(cpu: &mut cpu::CPU, poke: &str) -> Result<(), String> {
+    let (address, value) = split_assertion(poke)?;
+    let address = parse_number(address)? as usize;
+    match (cpu.memory.get_mut(address), u8::try_from(value)) {
+        (Some(byte), Ok(value)) => *byte = value,
+        _ => return Err(format!("can't poke '{}'", poke)),
+    }
+    Ok(())
+}
+
+pub fn run(args: &RunArgs, mut cpu: cpu::CPU) -> ExitCode {
+    let rom = match asm::read_program(Path::new(&args.rom)) {
+        Ok((rom, _)) => rom,
+        Err(e) => return fail(IO_EXIT, e),
+    };
+    if let Err(e) = cpu.load_program(&rom) {
+        return fail(IO_EXIT, format!("{}: {}", args.rom, e));
+    }
+
+    for text in args.poke.iter() {
+        if let Err(e) = poke(&mut cpu, text) {
+            return fail(USAGE_EXIT, e);
+        }
+    }
+
+    let presses = match args.press.iter().map(|press| parse_key_press(press)).collect::<Result<Vec<_>, _>>() {
+        Ok(presses) => presses,
+        Err(e) => return fail(USAGE_EXIT, e),
+    };
+    let result = run_script(&mut cpu, args, &presses);
+    if let Err(e) = cpu.audio.finish() {
+        return fail(IO_EXIT, e);
+    }
+
+    // The screen is dumped even after a fault, it's often the best clue to what went wrong
+    match args.dump_screen {
+        Some(ScreenFormat::Ascii) => print!("{}", cpu.screen_to_ascii()),
+        Some(ScreenFormat::Pbm) => print!("{}", cpu.screen_to_pbm()),
+        None => {}
+    }
+
+    if let Err(e) = result {
+        eprintln!("CPU fault: {}", e);
+        return ExitCode::from(FAULT_EXIT);
+    }
+
+    let mut failures = vec![];
+    let checks = args
+        .assert_reg
+        .iter()
+        .map(|assertion| check_register(&cpu, assertion))
+        .chain(args.assert_mem.iter().map(|assertion| check_memory(&cpu, assertion)));
+    for check in checks {
+        match check {
+            Ok(failure) => failures.extend(failure),
+            Err(e) => return fail(USAGE_EXIT, e),
+        }
+    }
+
+    for failure in failures.iter() {
+        eprintln!("assertion failed: {}", failure);
+    }
+
+    if failures.is_empty() { ExitCode::SUCCESS } else { ExitCode::from(ASSERT_EXIT) }
+}
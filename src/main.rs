@@ -1,9 +1,11 @@
 
-use clap::Parser;
-use std::fs::File;
-use std::io::Read;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+mod asm;
 mod cpu;
+mod headless;
 mod window;
 mod terminal;
 
@@ -11,31 +13,205 @@ mod terminal;
 #[command(name = "chip8")]
 #[command(about = "A CHIP-8 emulator (terminal mode by default)")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(help = "ROM to boot (.ch8, .c8, .sc8, .xo8 or an Octo .8o source); without one the terminal opens a ROM browser")]
+    rom: Option<String>,
+
     #[arg(long, help = "Run in window mode instead of terminal mode")]
     window: bool,
+
+    #[arg(long, value_name = "DIR", default_value = "roms", help = "Directory the terminal ROM browser lists")]
+    rom_dir: PathBuf,
+
+    #[arg(long, value_name = "DIR", default_value = "database", help = "Local copy of the CHIP-8 community database, for ROM titles and recommended settings")]
+    database: PathBuf,
+
+    #[arg(long, value_name = "FILE", default_value = "rom-settings.json", help = "Where the terminal remembers each ROM's speed, quirks, palette, key bindings and breakpoints")]
+    settings: PathBuf,
+
+    #[arg(long, value_name = "FILE", default_value = "keymap.json", help = "Keyboard layout and key bindings for the CHIP-8 keypad")]
+    keymap: PathBuf,
+
+    #[arg(long, global = true, value_parser = cpu::quirks::PROFILE_NAMES, help = "Quirks profile to emulate [default: the ROM database's, or modern]")]
+    quirks: Option<String>,
+
+    #[arg(long, global = true, help = "Instructions executed per second [default: the ROM database's tick rate, or 700]")]
+    speed: Option<u32>,
+
+    #[arg(long, global = true, conflicts_with = "speed", help = "Run a fixed number of instructions per 60 Hz frame instead of --speed")]
+    cycles_per_frame: Option<u32>,
+
+    #[arg(long, global = true, value_name = "FILE", help = "Record the sound to a WAV file instead of playing it")]
+    wav: Option<PathBuf>,
+
+    #[arg(long, global = true, default_value_t = 25, value_parser = clap::value_parser!(u8).range(0..=100), help = "Volume of the tone in percent")]
+    volume: u8,
+
+    #[arg(long, global = true, default_value_t = 440, help = "Pitch of the tone in Hz")]
+    frequency: u32,
+
+    #[arg(long, global = true, default_value = "square", value_parser = cpu::audio::WAVEFORM_NAMES, help = "Shape of the tone")]
+    waveform: String,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "rom", help = "Boot from a save state instead of a ROM")]
+    state: Option<String>,
+
+    #[arg(long, default_value_t = 32, help = "Memory for the terminal rewind history in MiB (0 disables rewind)")]
+    rewind_mb: usize,
+
+    #[arg(long, default_value_t = 60, help = "Frames between full snapshots in the rewind history")]
+    rewind_interval: u32,
+
+    #[arg(long, default_value = "classic", value_parser = cpu::disassembler::SYNTAX_NAMES, help = "Assembly syntax for the disassembly pane")]
+    syntax: String,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Run a ROM without any UI and report on the result")]
+    Run(headless::RunArgs),
+    #[command(about = "Assemble a source file into a ROM")]
+    Asm(asm::AsmArgs),
+    #[command(about = "Print a ROM as assembly source")]
+    Disasm(asm::DisasmArgs),
+}
+
+// --wav records to a file. Otherwise the terminal and the window play the sound, on the sound
+// card when built with the `sound` feature and with the terminal bell if not, and headless runs
+// throw it away.
+fn audio(args: &Args, interactive: bool) -> Result<cpu::audio::Audio, String> {
+    let settings = cpu::audio::AudioSettings {
+        volume: args.volume as f32 / 100.0,
+        frequency: args.frequency,
+        waveform: cpu::audio::Waveform::from_name(&args.waveform).unwrap_or_default(),
+    };
+    let sink: Box<dyn cpu::audio::AudioSink> = match &args.wav {
+        Some(path) => Box::new(cpu::audio::WavWriter::create(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None if interactive => speaker(),
+        None => Box::new(cpu::audio::NullSink),
+    };
+    Ok(cpu::audio::Audio::new(settings, sink))
+}
+
+#[cfg(feature = "sound")]
+fn speaker() -> Box<dyn cpu::audio::AudioSink> {
+    match cpu::audio::DeviceSink::open() {
+        Ok(sink) => Box::new(sink),
+        Err(e) => {
+            eprintln!("audio: {}, using the terminal bell instead", e);
+            Box::new(cpu::audio::BellSink::stderr())
+        }
+    }
 }
 
-fn read_rom_file(filename: &str) -> Result<Vec<u8>, std::io::Error> {
-    let mut file = File::open(filename)?;
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes)?;
-    Ok(bytes)
+#[cfg(not(feature = "sound"))]
+fn speaker() -> Box<dyn cpu::audio::AudioSink> {
+    Box::new(cpu::audio::BellSink::stderr())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    
+fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // --help and --version come through here too, and aren't failures
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() { ExitCode::from(headless::USAGE_EXIT) } else { ExitCode::SUCCESS };
+        }
+    };
+
+    // Errors already say where they come from, e.g. "game.8o:12: undefined name 'loop'"
+    match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let overrides = cpu::database::Overrides {
+        quirks: args.quirks.as_deref().and_then(cpu::Quirks::from_name),
+        speed: match (args.cycles_per_frame, args.speed) {
+            (Some(cycles), _) => Some(cpu::ClockSpeed::CyclesPerFrame(cycles)),
+            (None, Some(hz)) => Some(cpu::ClockSpeed::Hz(hz)),
+            (None, None) => None,
+        },
+    };
+    let (quirks, clock) = (overrides.quirks(), overrides.clock());
+
+    match &args.command {
+        Some(Command::Run(run_args)) => {
+            let mut _cpu = cpu::CPU::new(quirks);
+            _cpu.clock = clock;
+            _cpu.audio = match audio(args, false) {
+                Ok(audio) => audio,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Ok(ExitCode::from(headless::IO_EXIT));
+                }
+            };
+            return Ok(headless::run(run_args, _cpu));
+        }
+        Some(Command::Asm(asm_args)) => {
+            asm::assemble(asm_args)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Disasm(disasm_args)) => {
+            asm::disassemble(disasm_args, cpu::CPU::new(quirks))?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    let database = cpu::database::Database::load(&args.database)?;
+    let keymap_config = cpu::keymap::KeymapConfig::load(&args.keymap)?;
+    let settings = cpu::settings::SettingsStore::load(&args.settings)?;
+
     if args.window {
-        let mut _cpu = cpu::CPU::new();
-        let rom_bytes = read_rom_file("roms/PONG.c8")?;
-        _cpu.load_program(&rom_bytes);
-        let mut gui = window::WindowApp::new(_cpu);
+        let mut _cpu = cpu::CPU::new(quirks);
+        _cpu.clock = clock;
+        _cpu.audio = audio(args, true)?;
+        let mut controls = Default::default();
+        let mut rom_keys = Default::default();
+        match (&args.rom, &args.state) {
+            (Some(rom), _) => {
+                let (bytes, _) = asm::read_program(Path::new(rom))?;
+                _cpu.load_program(&bytes)?;
+                if let Some(info) = database.lookup(&bytes) {
+                    info.apply(&mut _cpu, overrides);
+                    controls = cpu::keymap::controls(&info.keys);
+                }
+                rom_keys = settings.get(&cpu::database::sha1_hex(&bytes)).keys;
+            }
+            (None, Some(path)) => _cpu.load_state(&std::fs::read(path)?)?,
+            (None, None) => return Err("window mode needs a ROM to run, e.g. chip8 --window roms/2-ibm-logo.ch8".into()),
+        }
+        let (keymap, bad_keys) = keymap_config.keymap(&controls, &rom_keys);
+        for error in bad_keys {
+            eprintln!("{}: skipped key binding: {}", args.settings.display(), error);
+        }
+        let mut gui = window::WindowApp::new(_cpu, &keymap);
         gui.run();
     } else {
-        let _cpu = cpu::CPU::new();
-        let mut term = terminal::TerminalApp::new(_cpu);
+        let mut _cpu = cpu::CPU::new(quirks);
+        _cpu.clock = clock;
+        _cpu.audio = audio(args, true)?;
+        let rewind = cpu::rewind::RewindConfig {
+            max_bytes: args.rewind_mb * 1024 * 1024,
+            snapshot_interval: args.rewind_interval,
+        };
+        let syntax = cpu::disassembler::Syntax::from_name(&args.syntax).unwrap_or_default();
+        let mut term = terminal::TerminalApp::new(_cpu, overrides, rewind, syntax, args.rom_dir.clone(), database, settings, keymap_config);
+        if let Some(rom) = &args.rom {
+            term.boot_rom(rom)?;
+        } else if let Some(path) = &args.state {
+            term.boot_from_state(path)?;
+        }
         term.run()?;
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
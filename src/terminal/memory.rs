@@ -0,0 +1,132 @@
+// Hex and ASCII view of CPU memory for the debugger, with a cursor for editing bytes in place.
+// The bytes at PC, I and the return addresses on the stack are highlighted.
+
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+
+use super::cpu;
+
+const PC_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Yellow);
+const I_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Green);
+const STACK_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Magenta);
+const CURSOR_STYLE: Style = Style::new().fg(Color::Black).bg(Color::Cyan);
+
+pub struct MemoryView {
+    cursor: u16,
+    top: u16,        // address of the first row shown
+    row_width: u16,  // bytes per row, as many as fit the pane
+    rows: u16,       // rows that fit the pane, for paging
+    pending: Option<u8>, // high nibble typed so far
+}
+
+impl MemoryView {
+    pub fn new() -> MemoryView {
+        MemoryView { cursor: 0x200, top: 0x200, row_width: 16, rows: 8, pending: None }
+    }
+
+    pub fn jump(&mut self, address: u16) {
+        self.cursor = address;
+        self.pending = None;
+    }
+
+    // Arrows move a byte or a row, Page Up/Down a screenful
+    pub fn move_cursor(&mut self, rows: i32, bytes: i32) {
+        let delta = rows * self.row_width as i32 + bytes;
+        let target = (self.cursor as i32 + delta).clamp(0, cpu::MEMORY_SIZE as i32 - 1);
+        self.jump(target as u16);
+    }
+
+    pub fn page(&mut self, pages: i32) {
+        self.move_cursor(pages * self.rows as i32, 0);
+    }
+
+    // Two hex digits make a byte, written at the cursor, which then moves on. Returns true once a
+    // byte has been written.
+    pub fn type_digit(&mut self, cpu: &mut cpu::CPU, digit: u8) -> bool {
+        match self.pending.take() {
+            None => {
+                self.pending = Some(digit);
+                false
+            }
+            Some(high) => {
+                cpu.memory[self.cursor as usize] = high << 4 | digit;
+                self.move_cursor(0, 1);
+                true
+            }
+        }
+    }
+
+    fn byte_style(&self, cpu: &cpu::CPU, address: u16, focused: bool) -> Style {
+        let pc = cpu.program_counter;
+        if focused && address == self.cursor {
+            CURSOR_STYLE
+        } else if address == pc || address == pc.wrapping_add(1) {
+            PC_STYLE
+        } else if address == cpu.i_register {
+            I_STYLE
+        } else if cpu.stack.iter().any(|&ret| address == ret || address == ret.wrapping_add(1)) {
+            STACK_STYLE
+        } else {
+            Style::default()
+        }
+    }
+
+    pub fn render(&mut self, f: &mut Frame, chunk: Rect, cpu: &cpu::CPU, focused: bool) {
+        // "0200  ", then "XX " per byte, a space and one ASCII column per byte
+        let inner_width = chunk.width.saturating_sub(2);
+        self.row_width = if inner_width >= 6 + 16 * 3 + 1 + 16 { 16 } else { 8 };
+        self.rows = chunk.height.saturating_sub(2).max(1);
+
+        // Keep the cursor on screen
+        let cursor_row = self.cursor / self.row_width * self.row_width;
+        let last_top = cursor_row.saturating_sub((self.rows - 1) * self.row_width);
+        self.top = (self.top / self.row_width * self.row_width).clamp(last_top, cursor_row);
+
+        let mut lines = vec![];
+        for row in 0..self.rows as usize {
+            let start = self.top as usize + row * self.row_width as usize;
+            if start >= cpu::MEMORY_SIZE {
+                break;
+            }
+            let end = (start + self.row_width as usize).min(cpu::MEMORY_SIZE);
+
+            let mut spans = vec![Span::styled(format!("{:04X}  ", start), Style::default().fg(Color::DarkGray))];
+            for address in start..end {
+                let style = self.byte_style(cpu, address as u16, focused);
+                let text = match self.pending {
+                    Some(high) if focused && address == self.cursor as usize => format!("{:X}_", high),
+                    _ => format!("{:02X}", cpu.memory[address]),
+                };
+                spans.push(Span::styled(text, style));
+                spans.push(Span::raw(" "));
+            }
+            spans.push(Span::raw(" "));
+            for address in start..end {
+                let byte = cpu.memory[address];
+                let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                spans.push(Span::styled(c.to_string(), self.byte_style(cpu, address as u16, focused)));
+            }
+            lines.push(Line::from(spans));
+        }
+
+        let title = if focused {
+            format!("Memory {:#06X} - arrows move, 0-F edit, g go to, m/Esc back", self.cursor)
+        } else {
+            "Memory - m to edit, :mem ADDR to go to".to_string()
+        };
+        let legend = Line::from(vec![
+            Span::styled(" PC ", PC_STYLE),
+            Span::raw(" "),
+            Span::styled(" I ", I_STYLE),
+            Span::raw(" "),
+            Span::styled(" stack ", STACK_STYLE),
+        ]);
+
+        let border_style = if focused { Style::default().add_modifier(Modifier::BOLD) } else { Style::default() };
+        let block = Block::default().borders(Borders::ALL).border_style(border_style).title(title).title_bottom(legend);
+        f.render_widget(Paragraph::new(Text::from(lines)).block(block), chunk);
+    }
+}
@@ -1,19 +1,44 @@
 extern crate crossterm;
 
-use crossterm::{terminal, event::{self, Event, KeyCode, KeyEvent, KeyEventKind}};
+mod memory;
+mod registers;
+
+use crossterm::{execute, terminal, event::{self, Event, KeyCode, KeyEvent, KeyEventKind}};
+use crossterm::event::{KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
 use std::convert::AsRef;
-use std::io::stdout;
+use std::io::{stdout, Stdout};
 use ratatui::backend::CrosstermBackend;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Style};
-use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
 use ratatui::text::{Text, Line};
 use ratatui::{Frame, Terminal};
 
+use super::asm;
 use super::cpu;
+use super::cpu::database;
 use super::cpu::disassembler;
+use super::cpu::keymap;
+use super::cpu::settings;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Read;
+
+// Wall-clock length of one CPU frame
+const FRAME_DURATION: std::time::Duration = std::time::Duration::from_nanos(1_000_000_000 / cpu::clock::TIMER_HZ as u64);
+
+// Save slots live here as <rom>.<slot>.state
+const STATE_DIR: &str = "states";
+const STATE_SLOTS: u8 = 4;
+
+// Default colours for the four XO-CHIP plane combinations: off, plane 1, plane 2, both
+const PALETTE: [Color; 4] = [Color::Black, Color::White, Color::LightRed, Color::Red];
+
+// Without key release events a key counts as held until its auto-repeat stops
+const KEY_HOLD_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+fn rgb(color: u32) -> Color {
+    Color::Rgb((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
 
 #[derive(PartialEq)]
 enum AppState {
@@ -22,61 +47,131 @@ enum AppState {
     Debugging,
 }
 
+// A file in the ROM browser and what the database knows about it
+struct RomFile {
+    name: String,
+    info: Option<database::RomInfo>,
+}
+
 pub struct TerminalApp {
     cpu: cpu::CPU,
+    rewind: cpu::rewind::Rewind,
+    rewind_last_seen: Option<std::time::Instant>,
+    step_back_requested: bool,
+    breakpoints: cpu::breakpoints::Breakpoints,
+    break_reason: Option<String>,
+    cursor: Option<usize>,
+    command: Option<String>,
     items: Vec<disassembler::Dissemble>,
+    syntax: disassembler::Syntax,
+    symbols: cpu::symbols::Symbols,
+    memory: memory::MemoryView,
+    memory_focus: bool,
+    register_cursor: Option<usize>, // selected line of the CPU info panel while editing registers
+    register_edit: Option<String>,  // hex digits typed for the selected field
+    return_depth: Option<usize>,    // run until the call stack is this shallow, for step over/out
     offset: u16,
-    current_key: Option<u8>,
-    last_key_time: std::time::Instant,
+    key_last_seen: [Option<std::time::Instant>; 16],
+    key_release_events: bool,
     app_state: AppState,
-    rom_files: Vec<String>,
+    rom_dir: std::path::PathBuf,
+    rom_files: Vec<RomFile>,
     selected_rom: usize,
+    database: database::Database,
+    overrides: database::Overrides, // the command line's quirks and speed
+    keymap_config: keymap::KeymapConfig,
+    rom_controls: BTreeMap<String, Option<u8>>, // the loaded ROM's controls from the database
+    keymap: keymap::Keymap,         // the config's keymap for the loaded ROM, with its own bindings
+    settings: settings::SettingsStore,
+    rom_hash: Option<String>,       // SHA-1 of the loaded ROM, which its settings are stored under
+    rom_settings: settings::RomSettings,
+    palette: [Color; 4],
     rom_scroll_offset: usize,
     debug_mode: bool,
     step_requested: bool,
+    fault: Option<cpu::CpuError>,
+    next_frame: std::time::Instant,
+    rom_name: String,
+    status: Option<String>,
 }
 
 impl TerminalApp {
-    pub fn new(cpu: cpu::CPU) -> TerminalApp {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        cpu: cpu::CPU,
+        overrides: database::Overrides,
+        rewind_config: cpu::rewind::RewindConfig,
+        syntax: disassembler::Syntax,
+        rom_dir: std::path::PathBuf,
+        database: database::Database,
+        settings: settings::SettingsStore,
+        keymap_config: keymap::KeymapConfig,
+    ) -> TerminalApp {
+        let (keymap, _) = keymap_config.keymap(&BTreeMap::new(), &BTreeMap::new());
         let mut app = TerminalApp {
-            cpu: cpu,
+            cpu,
+            rewind: cpu::rewind::Rewind::new(rewind_config),
+            rewind_last_seen: None,
+            step_back_requested: false,
+            breakpoints: cpu::breakpoints::Breakpoints::new(),
+            break_reason: None,
+            cursor: None,
+            command: None,
             items: vec![],
+            syntax,
+            symbols: cpu::symbols::Symbols::default(),
+            memory: memory::MemoryView::new(),
+            memory_focus: false,
+            register_cursor: None,
+            register_edit: None,
+            return_depth: None,
             offset: 0,
-            current_key: None,
-            last_key_time: std::time::Instant::now(),
+            key_last_seen: [None; 16],
+            key_release_events: false,
             app_state: AppState::RomSelection,
+            rom_dir,
             rom_files: vec![],
             selected_rom: 0,
+            database,
+            overrides,
+            keymap_config,
+            rom_controls: BTreeMap::new(),
+            keymap,
+            settings,
+            rom_hash: None,
+            rom_settings: settings::RomSettings::default(),
+            palette: PALETTE,
             rom_scroll_offset: 0,
             debug_mode: false,
             step_requested: false,
+            fault: None,
+            next_frame: std::time::Instant::now(),
+            rom_name: String::new(),
+            status: None,
         };
 
         app.scan_rom_directory();
 
-        return app;
+        app
     }
 
     fn scan_rom_directory(&mut self) {
         self.rom_files.clear();
-        if let Ok(entries) = fs::read_dir("roms") {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Some(file_name) = path.file_name() {
-                            if let Some(file_str) = file_name.to_str() {
-                                // Only include .ch8 files
-                                if file_str.ends_with(".ch8") {
-                                    self.rom_files.push(file_str.to_string());
-                                }
-                            }
-                        }
-                    }
+        if let Ok(entries) = fs::read_dir(&self.rom_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                // ROMs, and Octo sources which are compiled when loaded
+                if !path.is_file() || !asm::is_program(&path) {
+                    continue;
+                }
+
+                if let Some(file_str) = path.file_name().and_then(|name| name.to_str()) {
+                    let info = fs::read(&path).ok().and_then(|bytes| self.database.lookup(&bytes));
+                    self.rom_files.push(RomFile { name: file_str.to_string(), info });
                 }
             }
         }
-        self.rom_files.sort();
+        self.rom_files.sort_by(|a, b| a.name.cmp(&b.name));
     }
 
     fn validate_selected_rom_index(&mut self) {
@@ -87,29 +182,223 @@ impl TerminalApp {
 
     fn load_rom_with_mode(&mut self, debug_mode: bool) -> Result<(), Box<dyn std::error::Error>> {
         if self.selected_rom < self.rom_files.len() {
-            let rom_path = format!("roms/{}", self.rom_files[self.selected_rom]);
-            
-            let mut file = fs::File::open(&rom_path)?;
-            let mut bytes = Vec::new();
-            file.read_to_end(&mut bytes)?;
-            
-            self.cpu.load_program(&bytes);
-            self.items = self.cpu.disassemble_program();
-            self.offset = 0;
-            
-            if debug_mode {
-                self.debug_mode = true;
-                self.step_requested = false;
-                self.app_state = AppState::Debugging;
+            let rom_path = self.rom_dir.join(&self.rom_files[self.selected_rom].name);
+            self.load_rom(&rom_path, debug_mode)?;
+        }
+        Ok(())
+    }
+
+    // Boot straight into a ROM, skipping ROM selection
+    pub fn boot_rom(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.load_rom(std::path::Path::new(path), false)
+    }
+
+    fn load_rom(&mut self, path: &std::path::Path, debug_mode: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let (bytes, symbols) = asm::read_program(path)?;
+
+        self.cpu.reset();
+        self.cpu.load_program(&bytes)?;
+        self.rewind.clear();
+        self.breakpoints.clear();
+        self.break_reason = None;
+        self.cursor = None;
+        self.return_depth = None;
+        self.symbols = symbols;
+        self.items = self.cpu.disassemble_program(self.syntax, &self.symbols.labels);
+        self.offset = 0;
+        self.fault = None;
+        self.status = None;
+        // Names the save state slots
+        self.rom_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("rom").to_string();
+        self.apply_rom_settings(&bytes);
+
+        if debug_mode {
+            self.debug_mode = true;
+            self.step_requested = false;
+            self.app_state = AppState::Debugging;
+        } else {
+            self.debug_mode = false;
+            self.step_requested = false;
+            self.app_state = AppState::Emulating;
+        }
+        Ok(())
+    }
+
+    // The command line's quirks and speed, or the ones the database recommends for a ROM, its
+    // controls and colours, then whatever the user changed for it last time
+    fn apply_rom_settings(&mut self, rom: &[u8]) {
+        self.cpu.quirks = self.overrides.quirks();
+        self.cpu.clock = self.overrides.clock();
+        self.rom_controls.clear();
+        let mut palette = PALETTE;
+
+        let mut status = vec![];
+        if let Some(info) = self.database.lookup(rom) {
+            info.apply(&mut self.cpu, self.overrides);
+            self.rom_controls = keymap::controls(&info.keys);
+            for (slot, &color) in palette.iter_mut().zip(&info.colors) {
+                *slot = rgb(color);
+            }
+
+            let mut notes = vec![];
+            if let Some(platform) = info.platform_name().filter(|_| self.overrides.quirks.is_none()) {
+                notes.push(format!("{} quirks", platform));
+            }
+            if let Some(tickrate) = info.tickrate.filter(|_| self.overrides.speed.is_none()) {
+                notes.push(format!("{} instructions/frame", tickrate));
+            }
+            if !self.rom_controls.is_empty() {
+                notes.push("arrow key controls".to_string());
+            }
+            status.push(if notes.is_empty() {
+                info.title
             } else {
-                self.debug_mode = false;
-                self.step_requested = false;
-                self.app_state = AppState::Emulating;
+                format!("{}: {}", info.title, notes.join(", "))
+            });
+        }
+
+        let hash = database::sha1_hex(rom);
+        self.rom_settings = self.settings.get(&hash);
+        self.rom_hash = Some(hash);
+        self.rom_settings.apply(&mut self.cpu);
+        let bad_keys = self.rebuild_keymap();
+        self.palette = self.rom_settings.palette.map(|palette| palette.map(rgb)).unwrap_or(palette);
+        for command in &self.rom_settings.breakpoints {
+            if let Ok(breakpoint) = cpu::breakpoints::Breakpoint::parse(command) {
+                self.breakpoints.add(breakpoint);
             }
         }
+        if !self.rom_settings.is_empty() {
+            status.push("your settings from last time".to_string());
+        }
+        if let Some(slot) = self.rom_settings.last_slot {
+            status.push(format!("Shift+F{} loads slot {}", slot, slot));
+        }
+        if !bad_keys.is_empty() {
+            status.push(format!("skipped key bindings: {}", bad_keys.join(", ")));
+        }
+
+        if !status.is_empty() {
+            self.status = Some(status.join(" - "));
+        }
+    }
+
+    // Writes the loaded ROM's settings, with its current breakpoints, if anything changed
+    fn remember_settings(&mut self) {
+        let Some(hash) = &self.rom_hash else {
+            return;
+        };
+        self.rom_settings.breakpoints = self.breakpoints.list.iter().map(|breakpoint| breakpoint.to_string()).collect();
+        if self.rom_settings == self.settings.get(hash) {
+            return;
+        }
+        if let Err(e) = self.settings.set(hash, self.rom_settings.clone()) {
+            self.status = Some(format!("Saving settings failed: {}", e));
+        }
+    }
+
+    // The keymap config with the ROM's controls and the keys bound to it from the debugger.
+    // Returns the bindings that couldn't be made.
+    fn rebuild_keymap(&mut self) -> Vec<String> {
+        let (keymap, errors) = self.keymap_config.keymap(&self.rom_controls, &self.rom_settings.keys);
+        self.keymap = keymap;
+        errors
+    }
+
+    // The CHIP-8 key a host key presses. Terminals report the numeric keypad as the characters
+    // it types, so a "num" binding also counts for its character.
+    fn emulated_key(&self, code: KeyCode) -> Option<u8> {
+        let name = match code {
+            KeyCode::Char(c) => c.to_ascii_lowercase().to_string(),
+            KeyCode::Up => "up".to_string(),
+            KeyCode::Down => "down".to_string(),
+            KeyCode::Left => "left".to_string(),
+            KeyCode::Right => "right".to_string(),
+            KeyCode::Enter => "enter".to_string(),
+            _ => return None,
+        };
+        self.keymap.key(&name).or_else(|| self.keymap.key(&format!("num{}", name)))
+    }
+
+    // Boot straight into a saved machine, skipping ROM selection
+    pub fn boot_from_state(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let bytes = fs::read(path)?;
+        self.cpu.load_state(&bytes)?;
+        self.rewind.clear();
+        self.symbols = cpu::symbols::Symbols::default();
+        self.items = self.cpu.disassemble_program(self.syntax, &self.symbols.labels);
+        self.offset = 0;
+        self.fault = None;
+        // The ROM isn't known, so there are no settings to remember for it
+        self.rom_hash = None;
+        self.rom_settings = settings::RomSettings::default();
+        self.rom_controls.clear();
+        self.status = Some(format!("Loaded {}", path));
+        self.rom_name = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("state")
+            .to_string();
+        self.rebuild_keymap();
+        self.debug_mode = false;
+        self.step_requested = false;
+        self.app_state = AppState::Emulating;
         Ok(())
     }
 
+    fn state_slot_path(&self, slot: u8) -> std::path::PathBuf {
+        std::path::Path::new(STATE_DIR).join(format!("{}.{}.state", self.rom_name, slot))
+    }
+
+    fn save_slot(&mut self, slot: u8) {
+        let path = self.state_slot_path(slot);
+        let result = fs::create_dir_all(STATE_DIR).and_then(|_| fs::write(&path, self.cpu.save_state()));
+        self.status = Some(match result {
+            Ok(()) => {
+                self.rom_settings.last_slot = Some(slot);
+                self.remember_settings();
+                format!("Saved slot {}", slot)
+            }
+            Err(e) => format!("Saving slot {} failed: {}", slot, e),
+        });
+    }
+
+    fn load_slot(&mut self, slot: u8) {
+        let path = self.state_slot_path(slot);
+        let result = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| self.cpu.load_state(&bytes).map_err(|e| e.to_string()));
+        self.status = Some(match result {
+            Ok(()) => {
+                // Keys held in the saved machine are not held now
+                self.release_all_keys();
+                self.rewind.clear();
+                self.return_depth = None;
+                self.items = self.cpu.disassemble_program(self.syntax, &self.symbols.labels);
+                self.fault = None;
+                self.rom_settings.last_slot = Some(slot);
+                self.remember_settings();
+                format!("Loaded slot {}", slot)
+            }
+            Err(e) => format!("Loading slot {} failed: {}", slot, e),
+        });
+    }
+
+    // F1-F4 save, Shift+F1-F4 load. Some terminals report Shift+F1-F4 as F13-F16.
+    fn handle_slot_key(&mut self, key_event: &KeyEvent) -> bool {
+        let KeyCode::F(n) = key_event.code else {
+            return false;
+        };
+        let shift = key_event.modifiers.contains(KeyModifiers::SHIFT);
+        match n {
+            1..=STATE_SLOTS if shift => self.load_slot(n),
+            1..=STATE_SLOTS => self.save_slot(n),
+            13..=16 if n - 12 <= STATE_SLOTS => self.load_slot(n - 12),
+            _ => return false,
+        }
+        true
+    }
+
     fn load_selected_rom(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.load_rom_with_mode(false)
     }
@@ -120,170 +409,585 @@ impl TerminalApp {
 
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         terminal::enable_raw_mode()?;
-        
+
+        // Leave raw mode before a panic message is printed, otherwise the shell is left unusable
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+            let _ = terminal::disable_raw_mode();
+            default_hook(info);
+        }));
+
+        // Ask for key release events where the terminal supports them, so held keys stay held
+        self.key_release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if self.key_release_events {
+            execute!(stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
         let backend = CrosstermBackend::new(stdout());
-            let mut terminal = Terminal::new(backend).unwrap();
-            terminal.hide_cursor().unwrap();
-            terminal.clear().unwrap();
+        let mut terminal = Terminal::new(backend)?;
+        terminal.hide_cursor()?;
+        terminal.clear()?;
 
-            let mut last_render = std::time::Instant::now();
-            let render_interval = std::time::Duration::from_millis(16); // ~60 FPS
-            
-            loop {
-                if event::poll(std::time::Duration::from_millis(1))? {
-                    if let Event::Key(key_event) = event::read()? {
-                        if self.process_input_event(key_event) {
-                            break;
-                        }
+        let result = self.run_loop(&mut terminal);
+
+        terminal.clear()?;
+        terminal.show_cursor()?;
+        if self.key_release_events {
+            execute!(stdout(), PopKeyboardEnhancementFlags)?;
+        }
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn run_loop(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut last_render = std::time::Instant::now();
+        let render_interval = std::time::Duration::from_millis(16); // ~60 FPS
+
+        loop {
+            if event::poll(std::time::Duration::from_millis(1))?
+                && let Event::Key(key_event) = event::read()?
+                && self.process_input_event(key_event)
+            {
+                break;
+            }
+
+            match self.app_state {
+                AppState::RomSelection => {
+                    // Only redraw if enough time has passed
+                    if last_render.elapsed() >= render_interval {
+                        terminal.draw(|f| {
+                            let area = f.area();
+                            self.display_rom_selection(f, area);
+                        })?;
+                        last_render = std::time::Instant::now();
                     }
                 }
-                
-                match self.app_state {
-                    AppState::RomSelection => {
-                        // Only redraw if enough time has passed
-                        if last_render.elapsed() >= render_interval {
-                            terminal
-                                .draw(|f| {
-                                    let area = f.area();
-                                    self.display_rom_selection(f, area);
-                                })
-                                .unwrap();
-                            last_render = std::time::Instant::now();
-                        }
-                    }
-                    AppState::Emulating => {
-                        // Check if key has timed out (no repeat event for 100ms means released)
-                        if self.current_key.is_some() && self.last_key_time.elapsed() > std::time::Duration::from_millis(100) {
-                            self.current_key = None;
+                AppState::Emulating => {
+                    self.release_stale_keys();
+
+                    // Run as many 60 Hz frames as wall-clock time calls for
+                    let now = std::time::Instant::now();
+                    if now >= self.next_frame {
+                        if self.rewind_last_seen.is_some() {
+                            self.rewind_frame();
+                        } else {
+                            self.execute_frame();
                         }
-                        
-                        // Always apply the current key state
-                        self.cpu.press_key(self.current_key);
-
-                        self.cpu.do_cycle();
-
-                        // Only redraw if enough time has passed
-                        if last_render.elapsed() >= render_interval {
-                            terminal
-                                .draw(|mut f| {
-                                    let chunks = Layout::default()
-                                        .direction(Direction::Horizontal)
-                                        .margin(1)
-                                        .constraints(
-                                            [
-                                                Constraint::Percentage(20),
-                                                Constraint::Percentage(20),
-                                                Constraint::Percentage(60),
-                                            ]
-                                            .as_ref(),
-                                        )
-                                        .split(f.area());
-
-                                    self.display_disassemble_program(&mut f, chunks[0]);
-                                    self.display_executing_instruction(&mut f, chunks[1]);
-                                    self.display_grfx(&mut f, chunks[2])
-                                })
-                                .unwrap();
-                            last_render = std::time::Instant::now();
+                        self.next_frame += FRAME_DURATION;
+
+                        // Don't try to catch up after a long stall (e.g. coming back from the debugger)
+                        if now > self.next_frame + FRAME_DURATION * 4 {
+                            self.next_frame = now;
                         }
                     }
-                    AppState::Debugging => {
-                        // Check if key has timed out (no repeat event for 100ms means released)
-                        if self.current_key.is_some() && self.last_key_time.elapsed() > std::time::Duration::from_millis(100) {
-                            self.current_key = None;
-                        }
-                        
-                        // Always apply the current key state
-                        self.cpu.press_key(self.current_key);
-
-                        // Only execute next instruction if step was requested
-                        if self.step_requested {
-                            self.cpu.do_cycle();
-                            self.step_requested = false;
-                        }
 
-                        // Only redraw if enough time has passed
-                        if last_render.elapsed() >= render_interval {
-                            terminal
-                                .draw(|mut f| {
-                                    let chunks = Layout::default()
-                                        .direction(Direction::Horizontal)
-                                        .margin(1)
-                                        .constraints(
-                                            [
-                                                Constraint::Percentage(20),
-                                                Constraint::Percentage(20),
-                                                Constraint::Percentage(60),
-                                            ]
-                                            .as_ref(),
-                                        )
-                                        .split(f.area());
-
-                                    self.display_disassemble_program(&mut f, chunks[0]);
-                                    self.display_executing_instruction(&mut f, chunks[1]);
-                                    self.display_grfx(&mut f, chunks[2])
-                                })
-                                .unwrap();
-                            last_render = std::time::Instant::now();
-                        }
+                    // Only redraw if enough time has passed
+                    if last_render.elapsed() >= render_interval {
+                        terminal.draw(|f| self.display_emulator(f))?;
+                        last_render = std::time::Instant::now();
+                    }
+                }
+                AppState::Debugging => {
+                    self.release_stale_keys();
+
+                    // Only execute next instruction if step was requested
+                    if self.step_requested {
+                        self.execute_step();
+                        self.step_requested = false;
+                    }
+
+                    if self.step_back_requested {
+                        self.rewind_step();
+                        self.step_back_requested = false;
+                    }
+
+                    // Only redraw if enough time has passed
+                    if last_render.elapsed() >= render_interval {
+                        terminal.draw(|f| self.display_emulator(f))?;
+                        last_render = std::time::Instant::now();
                     }
                 }
-                
-                // Sleep briefly to prevent excessive CPU usage
-                std::thread::sleep(std::time::Duration::from_micros(500));
             }
-        
-        terminal.clear()?;
-        terminal.show_cursor()?;
-        terminal::disable_raw_mode()?;
+
+            // Sleep briefly to prevent excessive CPU usage
+            std::thread::sleep(std::time::Duration::from_micros(500));
+        }
+
         Ok(())
     }
 
+    fn press_keypad_key(&mut self, key: u8) {
+        self.key_last_seen[key as usize] = Some(std::time::Instant::now());
+        self.cpu.key_down(key);
+    }
+
+    fn release_keypad_key(&mut self, key: u8) {
+        self.key_last_seen[key as usize] = None;
+        self.cpu.key_up(key);
+    }
+
+    // Terminals that only report presses get a key released once its auto-repeat stops
+    fn release_stale_keys(&mut self) {
+        if self.key_release_events {
+            return;
+        }
+        if self.rewind_last_seen.is_some_and(|seen| seen.elapsed() > KEY_HOLD_TIMEOUT) {
+            self.rewind_last_seen = None;
+        }
+        for key in 0..16u8 {
+            if let Some(seen) = self.key_last_seen[key as usize]
+                && seen.elapsed() > KEY_HOLD_TIMEOUT
+            {
+                self.release_keypad_key(key);
+            }
+        }
+    }
+
+    fn release_all_keys(&mut self) {
+        for key in 0..16u8 {
+            self.release_keypad_key(key);
+        }
+    }
+
+    // Runs the frame one instruction at a time so a breakpoint can stop it part way through
+    fn execute_frame(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+
+        loop {
+            if let Some(reason) = self.breakpoints.check(&self.cpu) {
+                self.break_reason = Some(reason);
+                self.enter_debugger();
+                return;
+            }
+
+            let frame_done = match self.rewind.step(&mut self.cpu) {
+                Ok(frame_done) => frame_done,
+                Err(e) => {
+                    self.check_fault(Err(e));
+                    return;
+                }
+            };
+
+            if let Some(depth) = self.return_depth
+                && self.cpu.stack.len() <= depth
+            {
+                self.break_reason = Some(format!("returned to {:#05X}", self.cpu.program_counter));
+                self.enter_debugger();
+                return;
+            }
+
+            if frame_done {
+                return;
+            }
+        }
+    }
+
+    // Runs a CALL through to its return; anything else is a single step
+    fn step_over(&mut self) {
+        let pc = self.cpu.program_counter as usize;
+        let opcode = (self.cpu.memory[pc] as u16) << 8 | self.cpu.memory[(pc + 1) % cpu::MEMORY_SIZE] as u16;
+        if let Ok(cpu::instruction::Instruction::Call { .. }) = cpu::instruction::Instruction::decode(opcode) {
+            let depth = self.cpu.stack.len();
+            self.leave_debugger();
+            self.return_depth = Some(depth);
+        } else {
+            self.step_requested = true;
+            self.cursor = None;
+        }
+    }
+
+    // Runs until the current subroutine returns
+    fn step_out(&mut self) {
+        match self.cpu.stack.len() {
+            0 => self.status = Some("Not inside a subroutine".to_string()),
+            depth => {
+                self.leave_debugger();
+                self.return_depth = Some(depth - 1);
+            }
+        }
+    }
+
+    fn enter_debugger(&mut self) {
+        self.rewind_last_seen = None;
+        self.return_depth = None;
+        self.debug_mode = true;
+        self.step_requested = false;
+        self.app_state = AppState::Debugging;
+    }
+
+    fn leave_debugger(&mut self) {
+        self.debug_mode = false;
+        self.step_requested = false;
+        self.cursor = None;
+        self.command = None;
+        self.memory_focus = false;
+        self.register_cursor = None;
+        self.register_edit = None;
+        self.break_reason = None;
+        self.breakpoints.resume();
+        self.app_state = AppState::Emulating;
+    }
+
+    // After memory changes under it, e.g. an edit in the memory pane
+    fn refresh_disassembly(&mut self) {
+        self.items = self.cpu.disassemble_program(self.syntax, &self.symbols.labels);
+        self.cursor = self.cursor.filter(|&index| index < self.items.len());
+    }
+
+    // Keys while the memory pane has focus: arrows and Page Up/Down move, hex digits edit
+    fn memory_key(&mut self, key_event: &KeyEvent) {
+        match key_event.code {
+            KeyCode::Up => self.memory.move_cursor(-1, 0),
+            KeyCode::Down => self.memory.move_cursor(1, 0),
+            KeyCode::Left => self.memory.move_cursor(0, -1),
+            KeyCode::Right => self.memory.move_cursor(0, 1),
+            KeyCode::PageUp => self.memory.page(-1),
+            KeyCode::PageDown => self.memory.page(1),
+            KeyCode::Char('g') => self.command = Some("mem ".to_string()),
+            KeyCode::Char('m') | KeyCode::Esc => self.memory_focus = false,
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => {
+                let digit = c.to_digit(16).unwrap_or(0) as u8;
+                // Keep the listing in step, as self-modifying code would
+                if self.memory.type_digit(&mut self.cpu, digit) {
+                    self.refresh_disassembly();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Sets a register, timer or the PC while paused. A new PC also clears a fault, so execution
+    // can carry on from somewhere else.
+    fn set_field(&mut self, field: registers::Field, value: u32) {
+        self.status = Some(match field.set(&mut self.cpu, value) {
+            Ok(()) if field == registers::Field::ProgramCounter => {
+                self.fault = None;
+                self.cursor = None;
+                format!("PC set to {:#05X}, → runs from there", value)
+            }
+            Ok(()) => format!("{} set to {:#X}", field.label(), value),
+            Err(e) => e,
+        });
+    }
+
+    fn set_command(&mut self, name: &str, value: &str) {
+        let Some(field) = registers::Field::from_name(name) else {
+            self.status = Some(format!("Unknown register '{}'", name));
+            return;
+        };
+        // Addresses can be given by label too
+        let label = self.symbols.labels.iter().find(|(_, label)| label.as_str() == value).map(|(&address, _)| address as u32);
+        match label.map(Ok).unwrap_or_else(|| cpu::breakpoints::parse_number(value)) {
+            Ok(value) => self.set_field(field, value),
+            Err(e) => self.status = Some(e),
+        }
+    }
+
+    // Keys while the CPU info panel has focus: ↑/↓ pick a field, hex digits or Enter start typing
+    // a value, Enter sets it, z zeroes the field and +/- step it
+    fn register_key(&mut self, key_event: &KeyEvent) {
+        let Some(index) = self.register_cursor else {
+            return;
+        };
+        let field = registers::FIELDS[index];
+
+        if let Some(edit) = self.register_edit.as_mut() {
+            match key_event.code {
+                KeyCode::Char(c) if c.is_ascii_hexdigit() && edit.len() < 4 => edit.push(c.to_ascii_uppercase()),
+                KeyCode::Backspace => {
+                    edit.pop();
+                }
+                KeyCode::Enter => {
+                    let value = u32::from_str_radix(edit, 16).unwrap_or(0);
+                    self.register_edit = None;
+                    self.set_field(field, value);
+                }
+                KeyCode::Esc => self.register_edit = None,
+                _ => {}
+            }
+            return;
+        }
+
+        match key_event.code {
+            KeyCode::Up => self.register_cursor = Some(index.saturating_sub(1)),
+            KeyCode::Down => self.register_cursor = Some((index + 1).min(registers::FIELDS.len() - 1)),
+            KeyCode::Enter => self.register_edit = Some(String::new()),
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => self.register_edit = Some(c.to_ascii_uppercase().to_string()),
+            KeyCode::Char('z') => self.set_field(field, 0),
+            KeyCode::Char('+') => self.set_field(field, field.get(&self.cpu) as u32 + 1),
+            KeyCode::Char('-') => self.set_field(field, (field.get(&self.cpu) as u32).saturating_sub(1)),
+            KeyCode::Char('i') | KeyCode::Esc => self.register_cursor = None,
+            _ => {}
+        }
+    }
+
+    // "pc", "i", a label from the symbol file or a hex address
+    fn resolve_address(&self, text: &str) -> Option<u16> {
+        match text.to_ascii_lowercase().as_str() {
+            "pc" => Some(self.cpu.program_counter),
+            "i" => Some(self.cpu.i_register),
+            lower => self
+                .symbols
+                .labels
+                .iter()
+                .find(|(_, name)| name.as_str() == text)
+                .map(|(&address, _)| address)
+                .or_else(|| u16::from_str_radix(lower.trim_start_matches("0x"), 16).ok()),
+        }
+    }
+
+    fn cycle_syntax(&mut self) {
+        self.syntax = self.syntax.next();
+        self.items = self.cpu.disassemble_program(self.syntax, &self.symbols.labels);
+        self.status = Some(format!("Disassembly syntax: {}", self.syntax.name()));
+    }
+
+    // Debugger commands: anything Breakpoint::parse accepts, plus 'delete N', 'clear',
+    // 'mem ADDR' to show memory from an address and 'set FIELD VALUE' for registers, timers and PC
+    fn run_command(&mut self, command: &str) {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        self.status = match words.as_slice() {
+            [] => None,
+            ["set", name, value] => return self.set_command(name, value),
+            ["mem", target] => match self.resolve_address(target) {
+                Some(address) => {
+                    self.memory.jump(address);
+                    self.memory_focus = true;
+                    self.register_cursor = None;
+                    Some(format!("Memory at {:#06X}", address))
+                }
+                None => Some(format!("Unknown address '{}'", target)),
+            },
+            ["delete" | "d", n] => match n.parse::<usize>().ok().and_then(|n| self.breakpoints.remove(n.wrapping_sub(1))) {
+                Some(removed) => Some(format!("Deleted {}", removed)),
+                None => Some(format!("No breakpoint #{}", n)),
+            },
+            ["clear"] => {
+                self.breakpoints.clear();
+                Some("Cleared all breakpoints".to_string())
+            }
+            ["speed", cycles] => match cycles.parse::<u32>() {
+                Ok(cycles) if cycles > 0 => {
+                    self.cpu.clock = cpu::Clock::new(cpu::ClockSpeed::CyclesPerFrame(cycles));
+                    self.rom_settings.cycles_per_frame = Some(cycles);
+                    Some(format!("Speed: {} instructions/frame", cycles))
+                }
+                _ => Some(format!("Bad speed '{}'", cycles)),
+            },
+            ["quirks", name] => match cpu::Quirks::from_name(name) {
+                Some(quirks) => {
+                    self.cpu.quirks = quirks;
+                    self.rom_settings.quirks = Some(quirks);
+                    Some(format!("Quirks: {}", name))
+                }
+                None => Some(format!("Unknown quirks profile '{}' ({})", name, cpu::quirks::PROFILE_NAMES.join(", "))),
+            },
+            ["palette", off, plane1, plane2, both] => match [off, plane1, plane2, both].map(|color| settings::parse_color(color)) {
+                [Some(off), Some(plane1), Some(plane2), Some(both)] => {
+                    let palette = [off, plane1, plane2, both];
+                    self.palette = palette.map(rgb);
+                    self.rom_settings.palette = Some(palette);
+                    Some("Palette set".to_string())
+                }
+                _ => Some("Colours are written RRGGBB or #RRGGBB".to_string()),
+            },
+            ["bind", name, key] => {
+                let name = name.to_lowercase();
+                match u8::from_str_radix(key, 16) {
+                    _ if !keymap::is_host_key(&name) => Some(format!("Unknown host key '{}'", name)),
+                    Ok(key) if key < 16 => {
+                        self.rom_settings.keys.insert(name.clone(), key);
+                        self.rebuild_keymap();
+                        Some(format!("{} presses key {:X}", name, key))
+                    }
+                    _ => Some(format!("Bad CHIP-8 key '{}'", key)),
+                }
+            }
+            ["unbind", name] => match self.rom_settings.keys.remove(&name.to_lowercase()) {
+                Some(_) => {
+                    self.rebuild_keymap();
+                    Some(format!("Unbound {}", name))
+                }
+                None => Some(format!("{} isn't bound for this ROM", name)),
+            },
+            _ => match cpu::breakpoints::Breakpoint::parse(command) {
+                Ok(breakpoint) => {
+                    self.breakpoints.add(breakpoint);
+                    Some(format!("Added #{} {}", self.breakpoints.list.len(), breakpoint))
+                }
+                Err(e) => Some(e),
+            },
+        };
+        self.remember_settings();
+    }
+
+    // Typing into the command line opened with ':'
+    fn edit_command(&mut self, key_event: &KeyEvent) {
+        let Some(command) = self.command.as_mut() else {
+            return;
+        };
+
+        match key_event.code {
+            KeyCode::Char(c) => command.push(c),
+            KeyCode::Backspace => {
+                command.pop();
+            }
+            KeyCode::Enter => {
+                if let Some(command) = self.command.take() {
+                    self.run_command(&command);
+                }
+            }
+            KeyCode::Esc => self.command = None,
+            _ => {}
+        }
+    }
+
+    fn execute_step(&mut self) {
+        if self.fault.is_none() {
+            let result = self.rewind.step(&mut self.cpu).map(|_| ());
+            self.check_fault(result);
+        }
+    }
+
+    fn rewind_frame(&mut self) {
+        if self.rewind.step_back_frame(&mut self.cpu) {
+            self.fault = None;
+        }
+    }
+
+    // Undoing the faulting instruction leaves the machine runnable again
+    fn rewind_step(&mut self) {
+        if self.rewind.step_back(&mut self.cpu) {
+            self.fault = None;
+        }
+    }
+
+    // A fault pauses emulation in the debugger so it can be inspected
+    fn check_fault(&mut self, result: Result<(), cpu::CpuError>) {
+        if let Err(e) = result {
+            self.fault = Some(e);
+            self.debug_mode = true;
+            self.step_requested = false;
+            self.app_state = AppState::Debugging;
+        }
+    }
+
+    fn display_emulator(&mut self, f: &mut Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .margin(1)
+            .constraints(
+                [
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(60),
+                ]
+                .as_ref(),
+            )
+            .split(f.area());
+
+        self.display_disassemble_program(f, chunks[0]);
+        self.display_executing_instruction(f, chunks[1]);
+
+        // The memory pane only matters while paused
+        if self.app_state == AppState::Debugging {
+            let height = self.cpu.height() as u16 / if self.cpu.hires { 2 } else { 1 } + 3;
+            let right = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(height), Constraint::Min(0)].as_ref())
+                .split(chunks[2]);
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                .split(right[1]);
+            self.display_grfx(f, right[0]);
+            self.memory.render(f, bottom[0], &self.cpu, self.memory_focus);
+            self.display_stack(f, bottom[1]);
+        } else {
+            self.display_grfx(f, chunks[2])
+        }
+    }
+
     pub fn display_disassemble_program(&mut self, f: &mut Frame, chunk: Rect) {
         let style = Style::default().fg(Color::White);
         let current_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+        let cursor_style = Style::default().fg(Color::Black).bg(Color::Cyan);
+        let breakpoint_style = Style::default().fg(Color::Red);
+        let annotation_style = Style::default().fg(Color::Green);
 
         // Auto-scroll to follow program counter in debug mode
         if self.app_state == AppState::Debugging {
             self.auto_scroll_to_current_instruction(chunk.height as usize);
         }
 
-        let items: Vec<ListItem> = self.items.iter().skip(self.offset as usize).map(|item| {
-            let is_current = item.memory_location == self.cpu.program_counter - 2;
-            let item_style = if is_current && self.app_state == AppState::Debugging {
+        let items: Vec<ListItem> = self.items.iter().enumerate().skip(self.offset as usize).map(|(index, item)| {
+            let is_current = item.memory_location == self.cpu.program_counter;
+            let item_style = if self.cursor == Some(index) {
+                cursor_style
+            } else if is_current && self.app_state == AppState::Debugging {
                 current_style
             } else {
                 style
             };
+            let marker = if self.breakpoints.has_pc(item.memory_location) { "\u{25CF}" } else { " " };
             
-            ListItem::new(Line::from(vec![ratatui::text::Span::styled(
-                format!(
-                    "{:#x} {:#06X} {}",
-                    item.memory_location, item.opcode, item.assembly
+            let opcode = if item.data { format!("{:#04X}  ", item.opcode) } else { format!("{:#06X}", item.opcode) };
+            let label = item.label.as_ref().map(|label| format!("{}: ", label)).unwrap_or_default();
+
+            let mut spans = vec![
+                ratatui::text::Span::styled(marker, breakpoint_style),
+                ratatui::text::Span::styled(
+                    format!(
+                        "{:#x} {} {}{}",
+                        item.memory_location, opcode, label, item.assembly
+                    ),
+                    item_style,
                 ),
-                item_style,
-            )]))
+            ];
+            // What the instruction about to run is working with
+            if is_current && self.app_state == AppState::Debugging && !item.data {
+                let annotation = self.cpu.annotate(item.opcode);
+                if !annotation.is_empty() {
+                    spans.push(ratatui::text::Span::styled(format!("  ; {}", annotation), annotation_style));
+                }
+            }
+            ListItem::new(Line::from(spans))
         }).collect();
 
         let title = match self.app_state {
-            AppState::Debugging => "Assembly - Current instruction highlighted",
-            _ => "Assembly - pgup/pgdown to scroll",
+            AppState::Debugging => format!("Assembly ({}) - ↑/↓ select, b breakpoint, j jump here, : command, Tab syntax", self.syntax.name()),
+            _ => format!("Assembly ({}) - pgup/pgdown to scroll, Tab syntax", self.syntax.name()),
         };
 
+        // The source line the current instruction came from, when the program has a source map
+        let source = self
+            .symbols
+            .source_at(self.cpu.program_counter)
+            .map(|source| format!(" {}:{}  {} ", source.file, source.line, source.text))
+            .unwrap_or_default();
+
         let list_widget = List::new(items)
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title(title),
+                    .title(title)
+                    .title_bottom(source),
             );
         
         f.render_widget(list_widget, chunk);
     }
 
+    fn current_item_index(&self) -> Option<usize> {
+        let current_pc = self.cpu.program_counter;
+        self.items.iter().position(|item| item.memory_location == current_pc)
+    }
+
     fn auto_scroll_to_current_instruction(&mut self, visible_height: usize) {
-        // Find the index of the current instruction
-        let current_pc = self.cpu.program_counter - 2;
-        if let Some(current_index) = self.items.iter().position(|item| item.memory_location == current_pc) {
+        // Follow the selected line while there is one, otherwise the current instruction
+        if let Some(current_index) = self.cursor.or_else(|| self.current_item_index()) {
             let available_height = if visible_height > 2 { visible_height - 2 } else { 1 }; // Account for borders
             
             // Check if current instruction is visible
@@ -307,122 +1011,102 @@ impl TerminalApp {
 
         let mut keys: String = String::from("");
 
-        match self.cpu.key_press {
-            Some(0x0) => keys += " 0",
-            Some(0x1) => keys += " 1",
-            Some(0x2) => keys += " 2",
-            Some(0x3) => keys += " 3",
-            Some(0x4) => keys += " 4",
-            Some(0x5) => keys += " 5",
-            Some(0x6) => keys += " 6",
-            Some(0x7) => keys += " 7",
-            Some(0x8) => keys += " 8",
-            Some(0x9) => keys += " 9",
-            Some(0xA) => keys += " A",
-            Some(0xB) => keys += " B",
-            Some(0xC) => keys += " C",
-            Some(0xD) => keys += " D",
-            Some(0xE) => keys += " E",
-            Some(0xF) => keys += " F",
-            _ => {}
+        for key in 0..16u8 {
+            if self.cpu.is_key_down(key) {
+                keys += &format!(" {:X}", key);
+            }
+        }
+
+        let mut text = vec![Line::from(vec![ratatui::text::Span::styled(format!("Opcode: {:#x}", self.cpu.opcode), style)])];
+
+        // PC, I, V0-VF and the timers, which can be selected and edited while paused
+        let selected_style = Style::default().fg(Color::Black).bg(Color::Cyan);
+        for (index, field) in registers::FIELDS.iter().enumerate() {
+            let selected = self.register_cursor == Some(index);
+            let value = match &self.register_edit {
+                Some(edit) if selected => format!("0x{}_", edit),
+                _ => format!("{:#x}", field.get(&self.cpu)),
+            };
+            text.push(Line::from(vec![ratatui::text::Span::styled(
+                format!("{}: {}", field.label(), value),
+                if selected { selected_style } else { style },
+            )]));
+        }
+
+        text.push(Line::from(vec![ratatui::text::Span::styled(format!("Key: {}", keys), style)]));
+        text.push(Line::from(vec![ratatui::text::Span::styled(
+            format!("Rewind: {} KB", self.rewind.bytes_used() / 1024),
+            style,
+        )]));
+
+        if !self.breakpoints.list.is_empty() {
+            text.push(Line::from(vec![ratatui::text::Span::styled("Breakpoints:", style)]));
+            for (index, breakpoint) in self.breakpoints.list.iter().enumerate() {
+                text.push(Line::from(vec![ratatui::text::Span::styled(format!(" {} {}", index + 1, breakpoint), style)]));
+            }
+        }
+
+        if let Some(status) = &self.status {
+            let status_style = Style::default().fg(Color::Yellow);
+            text.insert(0, Line::from(vec![ratatui::text::Span::styled(status.clone(), status_style)]));
+        }
+
+        if let Some(reason) = &self.break_reason {
+            let break_style = Style::default().fg(Color::Black).bg(Color::Yellow);
+            text.insert(0, Line::from(vec![ratatui::text::Span::styled(format!("BREAK: {}", reason), break_style)]));
         }
 
-        let text = vec![
-            Line::from(vec![ratatui::text::Span::styled(format!("Opcode: {:#x}", self.cpu.opcode), style)]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Program Counter: {:#x}", self.cpu.program_counter),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register [I]: {:#x}", self.cpu.i_register),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 0, self.cpu.registers[0]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 1, self.cpu.registers[1]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 2, self.cpu.registers[2]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 3, self.cpu.registers[3]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 4, self.cpu.registers[4]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 5, self.cpu.registers[5]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 6, self.cpu.registers[6]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 7, self.cpu.registers[7]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 8, self.cpu.registers[8]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 9, self.cpu.registers[9]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 10, self.cpu.registers[10]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 11, self.cpu.registers[11]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 12, self.cpu.registers[12]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 13, self.cpu.registers[13]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 14, self.cpu.registers[14]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Register {}: {:#x}", 15, self.cpu.registers[15]),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Delay Counter: {:#x}", self.cpu.delay_timer),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(
-                format!("Sound Counter: {:#x}", self.cpu.sound_timer),
-                style,
-            )]),
-            Line::from(vec![ratatui::text::Span::styled(format!("Key: {}", keys), style)]),
-        ];
+        if let Some(fault) = &self.fault {
+            let fault_style = Style::default().fg(Color::White).bg(Color::Red);
+            text.insert(0, Line::from(vec![ratatui::text::Span::styled(format!("FAULT: {}", fault), fault_style)]));
+        }
+
+        if let Some(command) = &self.command {
+            let command_style = Style::default().fg(Color::Black).bg(Color::White);
+            text.insert(0, Line::from(vec![ratatui::text::Span::styled(format!(":{}_", command), command_style)]));
+        }
 
+        let title = match self.app_state {
+            AppState::Debugging if self.register_cursor.is_some() => "CPU info - ↑/↓ select, 0-F/Enter edit, z zero, +/- step, i/Esc back",
+            AppState::Debugging => "CPU info - i to edit",
+            _ => "CPU info",
+        };
         let paragraph_widget = Paragraph::new(Text::from(text))
-            .block(block.clone().title("CPU info"));
+            .block(block.clone().title(title));
         
         f.render_widget(paragraph_widget, chunk);
     }
 
-    pub fn display_grfx(&mut self, f: &mut Frame, chunk: Rect) {
+    // Call stack, innermost frame first: the return address as label+offset and the call that
+    // pushed it
+    pub fn display_stack(&mut self, f: &mut Frame, chunk: Rect) {
         let style = Style::default().fg(Color::White);
+        let call_style = Style::default().fg(Color::DarkGray);
+
+        let labels: disassembler::Labels =
+            self.items.iter().filter_map(|item| item.label.clone().map(|label| (item.memory_location, label))).collect();
+
+        let mut text = vec![];
+        for (frame, &ret) in self.cpu.stack.iter().enumerate().rev() {
+            let site = ret.wrapping_sub(2);
+            let opcode = (self.cpu.memory[site as usize] as u16) << 8 | self.cpu.memory[ret.wrapping_sub(1) as usize] as u16;
+            let call = disassembler::decode(opcode, site, self.syntax, &labels).assembly;
+            let name = cpu::symbols::symbolize(&labels, ret).map(|name| format!(" {}", name)).unwrap_or_default();
+
+            text.push(Line::from(vec![ratatui::text::Span::styled(format!("#{} {:#05X}{}", frame, ret, name), style)]));
+            text.push(Line::from(vec![ratatui::text::Span::styled(format!("   {}", call), call_style)]));
+        }
+        if text.is_empty() {
+            text.push(Line::from(vec![ratatui::text::Span::styled("(empty)", call_style)]));
+        }
 
-        let has_px = Style::default().fg(Color::White);
-        let no_pxx = Style::default().fg(Color::Black);
+        let title = format!("Stack {}/{} - o step over, u step out", self.cpu.sp, cpu::STACK_SIZE);
+        let paragraph_widget = Paragraph::new(Text::from(text)).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(paragraph_widget, chunk);
+    }
+
+    pub fn display_grfx(&mut self, f: &mut Frame, chunk: Rect) {
+        let style = Style::default().fg(Color::White);
 
         let block = Block::default()
             .borders(Borders::ALL)
@@ -430,23 +1114,34 @@ impl TerminalApp {
 
         let mut text = vec![Line::from(vec![ratatui::text::Span::styled("", style)])];
 
-        for y in 0..32 {
-            let mut line_spans = vec![];
-            for x in 0..64 {
-                let color = if self.cpu.gfx[x][y] == true {
-                    has_px
-                } else {
-                    no_pxx
-                };
+        if self.cpu.hires {
+            // 128x64 is too tall for most terminals, so pack two rows into each cell with a half block
+            for y in (0..self.cpu.height()).step_by(2) {
+                let mut line_spans = vec![];
+                for x in 0..self.cpu.width() {
+                    let top = self.palette[self.cpu.gfx[x][y] as usize];
+                    let bottom = self.palette[self.cpu.gfx[x][y + 1] as usize];
 
-                line_spans.push(ratatui::text::Span::styled(format!("{}", "\u{2588}"), color));
+                    line_spans.push(ratatui::text::Span::styled("\u{2580}", Style::default().fg(top).bg(bottom)));
+                }
+                text.push(Line::from(line_spans));
+            }
+        } else {
+            for y in 0..self.cpu.height() {
+                let mut line_spans = vec![];
+                for x in 0..self.cpu.width() {
+                    let color = Style::default().fg(self.palette[self.cpu.gfx[x][y] as usize]);
+
+                    line_spans.push(ratatui::text::Span::styled("\u{2588}", color));
+                }
+                text.push(Line::from(line_spans));
             }
-            text.push(Line::from(line_spans));
         }
 
         let title = match self.app_state {
-            AppState::Debugging => "UI - Debug Mode: → to step, Enter to run, Space to toggle, ESC to exit",
-            _ => "UI - Press Space for debug mode, ESC to return to ROM selection",
+            AppState::Debugging if self.fault.is_some() => "UI - CPU fault, emulation stopped: ← to step back, ESC to return to ROM selection",
+            AppState::Debugging => "UI - Debug Mode: → to step, ← to step back, Enter to run, Space to toggle, ESC to exit",
+            _ => "UI - Space for debug mode, hold Backspace to rewind, F1-F4 save, Shift+F1-F4 load, ESC to return to ROM selection",
         };
         
         let paragraph_widget = Paragraph::new(Text::from(text))
@@ -459,8 +1154,13 @@ impl TerminalApp {
         let style = Style::default().fg(Color::White);
         let selected_style = Style::default().fg(Color::Black).bg(Color::White);
 
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(45), Constraint::Percentage(55)].as_ref())
+            .split(area);
+
         // Calculate available height for list items (subtract 2 for borders)
-        let available_height = area.height as usize - 2;
+        let available_height = (chunks[0].height as usize).saturating_sub(2).max(1);
         
         // Ensure selected item is visible
         if self.selected_rom < self.rom_scroll_offset {
@@ -484,9 +1184,14 @@ impl TerminalApp {
             } else {
                 style
             };
-            
+
+            // Known ROMs go by their title
+            let name = match &rom_file.info {
+                Some(info) => info.title.clone(),
+                None => rom_file.name.clone(),
+            };
             list_items.push(ListItem::new(Line::from(vec![
-                ratatui::text::Span::styled(rom_file.clone(), item_style)
+                ratatui::text::Span::styled(name, item_style)
             ])));
         }
 
@@ -494,165 +1199,226 @@ impl TerminalApp {
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .title("Select ROM - Use ↑/↓ to navigate, Enter to run, Space for debug mode, Esc to quit"),
+                    .title("Select ROM - Enter to run, Space to debug, Esc to quit"),
             );
         
-        f.render_widget(list_widget, area);
+        f.render_widget(list_widget, chunks[0]);
+        self.display_rom_info(f, chunks[1]);
+    }
+
+    // Title, authors, platform and description of the selected ROM, from the database
+    fn display_rom_info(&self, f: &mut Frame, chunk: Rect) {
+        let label_style = Style::default().fg(Color::DarkGray);
+        let mut text: Vec<Line> = vec![];
+
+        if let Some(rom_file) = self.rom_files.get(self.selected_rom) {
+            match &rom_file.info {
+                Some(info) => {
+                    text.push(Line::styled(info.title.clone(), Style::default().fg(Color::Yellow)));
+                    text.push(Line::from(""));
+                    let mut fields = vec![("Author", info.authors.join(", "))];
+                    if let Some(release) = &info.release {
+                        fields.push(("Released", release.clone()));
+                    }
+                    if let Some(platform) = info.platform_name() {
+                        fields.push(("Platform", platform.to_string()));
+                    }
+                    if let Some(tickrate) = info.tickrate {
+                        fields.push(("Speed", format!("{} instructions/frame", tickrate)));
+                    }
+                    fields.push(("File", rom_file.name.clone()));
+                    for (label, value) in fields {
+                        text.push(Line::from(vec![
+                            ratatui::text::Span::styled(format!("{:<10}", label), label_style),
+                            ratatui::text::Span::raw(value),
+                        ]));
+                    }
+                    if let Some(description) = &info.description {
+                        text.push(Line::from(""));
+                        text.push(Line::from(description.clone()));
+                    }
+                }
+                None => {
+                    text.push(Line::from(rom_file.name.clone()));
+                    text.push(Line::from(""));
+                    text.push(Line::styled("Not in the ROM database", label_style));
+                }
+            }
+        }
+
+        let paragraph_widget = Paragraph::new(Text::from(text))
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title("ROM Info"));
+        f.render_widget(paragraph_widget, chunk);
     }
 
     pub fn process_input_event(&mut self, key_event: KeyEvent) -> bool {
         match self.app_state {
             AppState::RomSelection => {
-                match key_event.kind {
-                    KeyEventKind::Press => {
-                        match key_event.code {
-                            KeyCode::Up => {
-                                if self.selected_rom > 0 {
-                                    self.selected_rom -= 1;
-                                }
-                            }
-                            KeyCode::Down => {
-                                if self.selected_rom + 1 < self.rom_files.len() {
-                                    self.selected_rom += 1;
-                                }
-                            }
-                            KeyCode::Enter => {
-                                if let Err(e) = self.load_selected_rom() {
-                                    println!("Error loading ROM: {}", e);
-                                }
-                            }
-                            KeyCode::Char(' ') => {
-                                if let Err(e) = self.load_selected_rom_debug() {
-                                    println!("Error loading ROM in debug mode: {}", e);
-                                }
+                if key_event.kind == KeyEventKind::Press {
+                    match key_event.code {
+                        KeyCode::Up if self.selected_rom > 0 => {
+                            self.selected_rom -= 1;
+                        }
+                        KeyCode::Down if self.selected_rom + 1 < self.rom_files.len() => {
+                            self.selected_rom += 1;
+                        }
+                        KeyCode::Enter => {
+                            if let Err(e) = self.load_selected_rom() {
+                                println!("Error loading ROM: {}", e);
                             }
-                            KeyCode::Esc => {
-                                println!("Escape pressed, exiting...\n");
-                                return true;
+                        }
+                        KeyCode::Char(' ') => {
+                            if let Err(e) = self.load_selected_rom_debug() {
+                                println!("Error loading ROM in debug mode: {}", e);
                             }
-                            _ => {}
                         }
+                        KeyCode::Esc => {
+                            println!("Escape pressed, exiting...\n");
+                            return true;
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
             AppState::Emulating => {
                 match key_event.kind {
+                    KeyEventKind::Press if self.handle_slot_key(&key_event) => {}
                     KeyEventKind::Press | KeyEventKind::Repeat => {
                         match key_event.code {
-                            KeyCode::Char(c) => match c {
-                                '1' => { self.current_key = Some(0x1); self.last_key_time = std::time::Instant::now(); }
-                                '2' => { self.current_key = Some(0x2); self.last_key_time = std::time::Instant::now(); }
-                                '3' => { self.current_key = Some(0x3); self.last_key_time = std::time::Instant::now(); }
-                                '4' => { self.current_key = Some(0xC); self.last_key_time = std::time::Instant::now(); }
-                                'q' => { self.current_key = Some(0x4); self.last_key_time = std::time::Instant::now(); }
-                                'w' => { self.current_key = Some(0x5); self.last_key_time = std::time::Instant::now(); }
-                                'e' => { self.current_key = Some(0x6); self.last_key_time = std::time::Instant::now(); }
-                                'r' => { self.current_key = Some(0xD); self.last_key_time = std::time::Instant::now(); }
-                                'a' => { self.current_key = Some(0x7); self.last_key_time = std::time::Instant::now(); }
-                                's' => { self.current_key = Some(0x8); self.last_key_time = std::time::Instant::now(); }
-                                'd' => { self.current_key = Some(0x9); self.last_key_time = std::time::Instant::now(); }
-                                'f' => { self.current_key = Some(0xE); self.last_key_time = std::time::Instant::now(); }
-                                'z' => { self.current_key = Some(0xA); self.last_key_time = std::time::Instant::now(); }
-                                'x' => { self.current_key = Some(0x0); self.last_key_time = std::time::Instant::now(); }
-                                'c' => { self.current_key = Some(0xB); self.last_key_time = std::time::Instant::now(); }
-                                'v' => { self.current_key = Some(0xF); self.last_key_time = std::time::Instant::now(); }
-                                ' ' => {
-                                    // Toggle to debug mode
-                                    self.debug_mode = true;
-                                    self.step_requested = false;
-                                    self.app_state = AppState::Debugging;
-                                }
-                                _ => {}
+                            KeyCode::Tab => self.cycle_syntax(),
+                            KeyCode::Char(' ') => {
+                                // Toggle to debug mode
+                                self.enter_debugger();
                             }
-                            KeyCode::PageUp => {
-                                if self.offset != 0 {
-                                    self.offset -= 10;
-                                }
+                            KeyCode::Backspace => {
+                                // Rewind for as long as the key is held
+                                self.rewind_last_seen = Some(std::time::Instant::now());
                             }
-                            KeyCode::PageDown => {
-                                if self.offset + 1 < self.cpu.program_size {
-                                    self.offset += 10;
-                                }
+                            KeyCode::PageUp if self.offset != 0 => {
+                                self.offset -= 10;
+                            }
+                            KeyCode::PageDown if (self.offset as usize) + 1 < self.cpu.program_size => {
+                                self.offset += 10;
                             }
                             KeyCode::Esc => {
+                                self.release_all_keys();
                                 self.cpu.reset();
+                                self.rewind.clear();
+                                self.rewind_last_seen = None;
                                 self.items.clear();
                                 self.offset = 0;
+                                self.fault = None;
+                                self.status = None;
+                                self.break_reason = None;
+                                self.cursor = None;
                                 self.scan_rom_directory();
                                 self.validate_selected_rom_index();
                                 self.app_state = AppState::RomSelection;
                             }
-                            _ => {}
+                            code => {
+                                if let Some(key) = self.emulated_key(code) {
+                                    self.press_keypad_key(key);
+                                }
+                            }
                         }
                     }
                     KeyEventKind::Release => {
-                        match key_event.code {
-                            KeyCode::Char(c) => match c {
-                                '1' | '2' | '3' | '4' | 'q' | 'w' | 'e' | 'r' |
-                                'a' | 's' | 'd' | 'f' | 'z' | 'x' | 'c' | 'v' => {
-                                    self.current_key = None;
-                                }
-                                _ => {}
-                            }
-                            _ => {}
+                        if key_event.code == KeyCode::Backspace {
+                            self.rewind_last_seen = None;
+                        } else if let Some(key) = self.emulated_key(key_event.code) {
+                            self.release_keypad_key(key);
                         }
                     }
                 }
             }
             AppState::Debugging => {
                 match key_event.kind {
+                    KeyEventKind::Press | KeyEventKind::Repeat if self.command.is_some() => self.edit_command(&key_event),
+                    KeyEventKind::Press if self.handle_slot_key(&key_event) => {}
+                    KeyEventKind::Press | KeyEventKind::Repeat if self.memory_focus => self.memory_key(&key_event),
+                    KeyEventKind::Press | KeyEventKind::Repeat if self.register_cursor.is_some() => self.register_key(&key_event),
                     KeyEventKind::Press | KeyEventKind::Repeat => {
                         match key_event.code {
-                            KeyCode::Char(c) => match c {
-                                '1' => { self.current_key = Some(0x1); self.last_key_time = std::time::Instant::now(); }
-                                '2' => { self.current_key = Some(0x2); self.last_key_time = std::time::Instant::now(); }
-                                '3' => { self.current_key = Some(0x3); self.last_key_time = std::time::Instant::now(); }
-                                '4' => { self.current_key = Some(0xC); self.last_key_time = std::time::Instant::now(); }
-                                'q' => { self.current_key = Some(0x4); self.last_key_time = std::time::Instant::now(); }
-                                'w' => { self.current_key = Some(0x5); self.last_key_time = std::time::Instant::now(); }
-                                'e' => { self.current_key = Some(0x6); self.last_key_time = std::time::Instant::now(); }
-                                'r' => { self.current_key = Some(0xD); self.last_key_time = std::time::Instant::now(); }
-                                'a' => { self.current_key = Some(0x7); self.last_key_time = std::time::Instant::now(); }
-                                's' => { self.current_key = Some(0x8); self.last_key_time = std::time::Instant::now(); }
-                                'd' => { self.current_key = Some(0x9); self.last_key_time = std::time::Instant::now(); }
-                                'f' => { self.current_key = Some(0xE); self.last_key_time = std::time::Instant::now(); }
-                                'z' => { self.current_key = Some(0xA); self.last_key_time = std::time::Instant::now(); }
-                                'x' => { self.current_key = Some(0x0); self.last_key_time = std::time::Instant::now(); }
-                                'c' => { self.current_key = Some(0xB); self.last_key_time = std::time::Instant::now(); }
-                                'v' => { self.current_key = Some(0xF); self.last_key_time = std::time::Instant::now(); }
-                                ' ' => {
-                                    // Toggle back to normal emulation mode
-                                    self.debug_mode = false;
-                                    self.step_requested = false;
-                                    self.app_state = AppState::Emulating;
+                            KeyCode::Tab => self.cycle_syntax(),
+                            KeyCode::Char('m') => {
+                                self.memory_focus = true;
+                                self.register_cursor = None;
+                            }
+                            KeyCode::Char('i') => {
+                                self.register_cursor = Some(0);
+                                self.memory_focus = false;
+                            }
+                            KeyCode::Char('j') => {
+                                // Jump here: continue from the selected line
+                                if let Some(address) = self.cursor.and_then(|index| self.items.get(index)).map(|item| item.memory_location) {
+                                    self.set_field(registers::Field::ProgramCounter, address as u32);
+                                }
+                            }
+                            KeyCode::Char('o') => self.step_over(),
+                            KeyCode::Char('u') => self.step_out(),
+                            KeyCode::Char(' ') => {
+                                // Toggle back to normal emulation mode
+                                self.leave_debugger();
+                            }
+                            KeyCode::Char(':') => {
+                                self.command = Some(String::new());
+                            }
+                            KeyCode::Char('b') => {
+                                // Toggle a breakpoint on the selected line, or the current instruction
+                                let address = match self.cursor {
+                                    Some(index) => self.items.get(index).map(|item| item.memory_location),
+                                    None => Some(self.cpu.program_counter),
+                                };
+                                if let Some(address) = address {
+                                    self.breakpoints.toggle_pc(address);
+                                    self.remember_settings();
+                                }
+                            }
+                            KeyCode::Char(_) => {
+                                if let Some(key) = self.emulated_key(key_event.code) {
+                                    self.press_keypad_key(key);
                                 }
-                                _ => {}
                             }
                             KeyCode::Right => {
                                 // Step to next instruction
                                 self.step_requested = true;
+                                self.cursor = None;
+                            }
+                            KeyCode::Left => {
+                                // Step back to the previous instruction
+                                self.step_back_requested = true;
+                                self.cursor = None;
+                            }
+                            KeyCode::Up => {
+                                let current = self.cursor.or_else(|| self.current_item_index()).unwrap_or(0);
+                                self.cursor = Some(current.saturating_sub(1));
+                            }
+                            KeyCode::Down if !self.items.is_empty() => {
+                                let current = self.cursor.or_else(|| self.current_item_index()).unwrap_or(0);
+                                self.cursor = Some((current + 1).min(self.items.len() - 1));
                             }
                             KeyCode::Enter => {
-                                // Exit debug mode and run normally
-                                self.debug_mode = false;
-                                self.step_requested = false;
-                                self.app_state = AppState::Emulating;
+                                // Run until a breakpoint triggers
+                                self.leave_debugger();
                             }
-                            KeyCode::PageUp => {
-                                if self.offset != 0 {
-                                    self.offset -= 10;
-                                }
+                            KeyCode::PageUp if self.offset != 0 => {
+                                self.offset -= 10;
                             }
-                            KeyCode::PageDown => {
-                                if self.offset + 1 < self.cpu.program_size {
-                                    self.offset += 10;
-                                }
+                            KeyCode::PageDown if (self.offset as usize) + 1 < self.cpu.program_size => {
+                                self.offset += 10;
                             }
                             KeyCode::Esc => {
+                                self.release_all_keys();
                                 self.cpu.reset();
+                                self.rewind.clear();
+                                self.rewind_last_seen = None;
                                 self.items.clear();
                                 self.offset = 0;
+                                self.fault = None;
+                                self.status = None;
+                                self.break_reason = None;
+                                self.cursor = None;
                                 self.debug_mode = false;
                                 self.step_requested = false;
                                 self.scan_rom_directory();
@@ -663,21 +1429,18 @@ impl TerminalApp {
                         }
                     }
                     KeyEventKind::Release => {
-                        match key_event.code {
-                            KeyCode::Char(c) => match c {
-                                '1' | '2' | '3' | '4' | 'q' | 'w' | 'e' | 'r' |
-                                'a' | 's' | 'd' | 'f' | 'z' | 'x' | 'c' | 'v' => {
-                                    self.current_key = None;
-                                }
-                                _ => {}
-                            }
-                            _ => {}
+                        if key_event.code == KeyCode::Backspace {
+                            self.rewind_last_seen = None;
+                        } else if let KeyCode::Char(_) = key_event.code
+                            && let Some(key) = self.emulated_key(key_event.code)
+                        {
+                            self.release_keypad_key(key);
                         }
                     }
                 }
             }
         }
 
-        return false;
+        false
     }
 }
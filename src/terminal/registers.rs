@@ -0,0 +1,90 @@
+// The CPU fields the debugger can edit, in the order the CPU info panel lists them
+
+use super::cpu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    ProgramCounter,
+    I,
+    V(u8),
+    DelayTimer,
+    SoundTimer,
+}
+
+pub const FIELDS: [Field; 20] = [
+    Field::ProgramCounter,
+    Field::I,
+    Field::V(0x0),
+    Field::V(0x1),
+    Field::V(0x2),
+    Field::V(0x3),
+    Field::V(0x4),
+    Field::V(0x5),
+    Field::V(0x6),
+    Field::V(0x7),
+    Field::V(0x8),
+    Field::V(0x9),
+    Field::V(0xA),
+    Field::V(0xB),
+    Field::V(0xC),
+    Field::V(0xD),
+    Field::V(0xE),
+    Field::V(0xF),
+    Field::DelayTimer,
+    Field::SoundTimer,
+];
+
+impl Field {
+    // PC, I, DT, ST or V0-VF, in any case
+    pub fn from_name(name: &str) -> Option<Field> {
+        match name.to_uppercase().as_str() {
+            "PC" => Some(Field::ProgramCounter),
+            "I" => Some(Field::I),
+            "DT" => Some(Field::DelayTimer),
+            "ST" => Some(Field::SoundTimer),
+            register => match register.strip_prefix('V').map(|n| u8::from_str_radix(n, 16)) {
+                Some(Ok(n)) if n < 16 && register.len() == 2 => Some(Field::V(n)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Field::ProgramCounter => "Program Counter".to_string(),
+            Field::I => "Register [I]".to_string(),
+            Field::V(n) => format!("Register {}", n),
+            Field::DelayTimer => "Delay Counter".to_string(),
+            Field::SoundTimer => "Sound Counter".to_string(),
+        }
+    }
+
+    pub fn get(&self, cpu: &cpu::CPU) -> u16 {
+        match *self {
+            Field::ProgramCounter => cpu.program_counter,
+            Field::I => cpu.i_register,
+            Field::V(n) => cpu.registers[n as usize] as u16,
+            Field::DelayTimer => cpu.delay_timer as u16,
+            Field::SoundTimer => cpu.sound_timer as u16,
+        }
+    }
+
+    pub fn set(&self, cpu: &mut cpu::CPU, value: u32) -> Result<(), String> {
+        let max = match self {
+            Field::ProgramCounter | Field::I => cpu::MEMORY_SIZE as u32 - 1,
+            _ => 0xFF,
+        };
+        if value > max {
+            return Err(format!("{:#X} doesn't fit in {} (at most {:#X})", value, self.label(), max));
+        }
+
+        match *self {
+            Field::ProgramCounter => cpu.program_counter = value as u16,
+            Field::I => cpu.i_register = value as u16,
+            Field::V(n) => cpu.registers[n as usize] = value as u8,
+            Field::DelayTimer => cpu.delay_timer = value as u8,
+            Field::SoundTimer => cpu.sound_timer = value as u8,
+        }
+        Ok(())
+    }
+}
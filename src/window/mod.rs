@@ -3,27 +3,85 @@ use minifb::{Key, Scale, Window, WindowOptions};
 
 use super::cpu;
 use super::cpu::disassembler;
+use super::cpu::keymap;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+const WIDTH: usize = cpu::SCREEN_WIDTH;
+const HEIGHT: usize = cpu::SCREEN_HEIGHT;
+
+// The window key for a keymap host key name. Characters without a key of their own here, like
+// the shifted symbols, only work in the terminal.
+fn window_key(name: &str) -> Option<Key> {
+    const LETTERS: [Key; 26] = [
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    ];
+    const DIGITS: [Key; 10] = [
+        Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    ];
+    const NUMPAD: [Key; 10] = [
+        Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+        Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    ];
+
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return match c {
+            'a'..='z' => Some(LETTERS[c as usize - 'a' as usize]),
+            '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+            '\'' => Some(Key::Apostrophe),
+            '`' => Some(Key::Backquote),
+            '\\' => Some(Key::Backslash),
+            ',' => Some(Key::Comma),
+            '=' => Some(Key::Equal),
+            '[' => Some(Key::LeftBracket),
+            '-' => Some(Key::Minus),
+            '.' => Some(Key::Period),
+            ']' => Some(Key::RightBracket),
+            ';' => Some(Key::Semicolon),
+            '/' => Some(Key::Slash),
+            _ => None,
+        };
+    }
+
+    match name {
+        "up" => Some(Key::Up),
+        "down" => Some(Key::Down),
+        "left" => Some(Key::Left),
+        "right" => Some(Key::Right),
+        "enter" => Some(Key::Enter),
+        "num/" => Some(Key::NumPadSlash),
+        "num*" => Some(Key::NumPadAsterisk),
+        "num-" => Some(Key::NumPadMinus),
+        "num+" => Some(Key::NumPadPlus),
+        "num." => Some(Key::NumPadDot),
+        "numenter" => Some(Key::NumPadEnter),
+        _ => {
+            let digit = name.strip_prefix("num")?.parse::<usize>().ok()?;
+            NUMPAD.get(digit).copied()
+        }
+    }
+}
+
+// Colours for the four XO-CHIP plane combinations: off, plane 1, plane 2, both
+const PALETTE: [u32; 4] = [0x000, 0xFFF, 0xFF6600, 0x662200];
 
 pub struct WindowApp {
     cpu: cpu::CPU,
     items: Vec<disassembler::Dissemble>,
-    offset: u16,
+    keys: Vec<(Key, u8)>, // window key to CHIP-8 key
 }
 
 impl WindowApp {
-    pub fn new(cpu: cpu::CPU) -> WindowApp {
+    pub fn new(cpu: cpu::CPU, keymap: &keymap::Keymap) -> WindowApp {
         let mut app = WindowApp {
-            cpu: cpu,
+            cpu,
             items: vec![],
-            offset: 0,
+            keys: keymap.bindings().filter_map(|(host, key)| Some((window_key(host)?, key))).collect(),
         };
 
-        app.items = app.cpu.disassemble_program();
+        app.items = app.cpu.disassemble_program(disassembler::Syntax::default(), &disassembler::Labels::new());
 
-        return app;
+        app
     }
 
     pub fn run(&mut self) {
@@ -34,7 +92,7 @@ impl WindowApp {
             WIDTH,
             HEIGHT,
             WindowOptions {
-                scale: Scale::X16,
+                scale: Scale::X8,
                 ..WindowOptions::default()
             },
         )
@@ -42,36 +100,41 @@ impl WindowApp {
             panic!("{}", e);
         });
 
+        // Each window update is one CPU frame, so the display refresh paces the emulation
+        window.set_target_fps(cpu::clock::TIMER_HZ as usize);
+
+        let mut fault: Option<cpu::CpuError> = None;
+
         while window.is_open() && !window.is_key_down(Key::Escape) {
             let mut addr: u32 = 0;
             
-            if window.is_key_down(Key::Key1) { self.cpu.press_key(Some(0x1)); }
-            else if window.is_key_down(Key::Key2) { self.cpu.press_key(Some(0x2)); }
-            else if window.is_key_down(Key::Key3) { self.cpu.press_key(Some(0x3)); }
-            else if window.is_key_down(Key::Key4) { self.cpu.press_key(Some(0xC)); }
-            else if window.is_key_down(Key::Q) { self.cpu.press_key(Some(0x4)); }
-            else if window.is_key_down(Key::W) { self.cpu.press_key(Some(0x5)); }
-            else if window.is_key_down(Key::E) { self.cpu.press_key(Some(0x6)); }
-            else if window.is_key_down(Key::R) { self.cpu.press_key(Some(0xD)); }
-            else if window.is_key_down(Key::A) { self.cpu.press_key(Some(0x7)); }
-            else if window.is_key_down(Key::S) { self.cpu.press_key(Some(0x8)); }
-            else if window.is_key_down(Key::D) { self.cpu.press_key(Some(0x9)); }
-            else if window.is_key_down(Key::F) { self.cpu.press_key(Some(0xE)); }
-            else if window.is_key_down(Key::Z) { self.cpu.press_key(Some(0xA)); }
-            else if window.is_key_down(Key::X) { self.cpu.press_key(Some(0x0)); }
-            else if window.is_key_down(Key::C) { self.cpu.press_key(Some(0xB)); }
-            else if window.is_key_down(Key::V) { self.cpu.press_key(Some(0xF)); }
-            else { self.cpu.press_key(None); }
-
-            self.cpu.do_cycle();
-
-            for y in 0..32 {
-                for x in 0..64 {
-                    if self.cpu.gfx[x][y] == true {
-                        buffer[addr as usize] = 0xFFF;
-                    } else {
-                        buffer[addr as usize] = 0x000;
-                    }
+            // A CHIP-8 key can have several host keys, so it's held while any of them is down
+            let mut held = [false; 16];
+            for &(host_key, chip8_key) in &self.keys {
+                held[chip8_key as usize] |= window.is_key_down(host_key);
+            }
+            for (chip8_key, down) in held.into_iter().enumerate() {
+                if down {
+                    self.cpu.key_down(chip8_key as u8);
+                } else {
+                    self.cpu.key_up(chip8_key as u8);
+                }
+            }
+
+            // After a fault the last frame stays on screen and the error is shown in the title bar
+            if fault.is_none()
+                && let Err(e) = self.cpu.run_frame()
+            {
+                window.set_title(&format!("CPU fault: {} - ESC to exit", e));
+                fault = Some(e);
+            }
+
+            // Lo-res pixels are drawn as 2x2 blocks so the window size never changes
+            let scale = WIDTH / self.cpu.width();
+
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    buffer[addr as usize] = PALETTE[self.cpu.gfx[x / scale][y / scale] as usize];
                     addr += 1;
                 }
             }
@@ -0,0 +1,91 @@
+// Checks that every way `chip8` can fail gets its own exit code and a readable message, so
+// scripts can tell a failed assertion from a ROM that crashed, a file that isn't there or a typo
+// on the command line.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn chip8(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .expect("failed to start chip8")
+}
+
+fn exit_code(output: &Output) -> i32 {
+    output.status.code().expect("chip8 was killed by a signal")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+const IBM_LOGO: &str = "roms/2-ibm-logo.ch8";
+
+#[test]
+fn success_exits_with_0() {
+    // The logo starts with 00E0
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--assert-mem", "0x201=0xE0"]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+}
+
+#[test]
+fn failed_assertion_exits_with_1() {
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--assert-mem", "0x200=0x42"]);
+    assert_eq!(exit_code(&output), 1, "{}", stderr(&output));
+    assert!(stderr(&output).contains("assertion failed: memory[0x200]"), "{}", stderr(&output));
+}
+
+#[test]
+fn cpu_fault_exits_with_2() {
+    // 00EE with nothing on the stack
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--poke", "0x200=0x00", "--poke", "0x201=0xEE"]);
+    assert_eq!(exit_code(&output), 2, "{}", stderr(&output));
+    assert!(stderr(&output).contains("CPU fault"), "{}", stderr(&output));
+}
+
+#[test]
+fn missing_rom_exits_with_3_and_names_it() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("roms").join("no-such-rom.ch8");
+    let output = chip8(&["run", path.to_str().unwrap(), "--frames", "1"]);
+    assert_eq!(exit_code(&output), 3, "{}", stderr(&output));
+    assert!(stderr(&output).contains("no-such-rom.ch8"), "{}", stderr(&output));
+}
+
+#[test]
+fn bad_command_line_exits_with_64() {
+    // Caught by clap
+    let output = chip8(&["run", IBM_LOGO]);
+    assert_eq!(exit_code(&output), 64, "{}", stderr(&output));
+    let output = chip8(&["run", IBM_LOGO, "--frames", "ten"]);
+    assert_eq!(exit_code(&output), 64, "{}", stderr(&output));
+
+    // Caught by `run` itself
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--poke", "0x200"]);
+    assert_eq!(exit_code(&output), 64, "{}", stderr(&output));
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--press", "1:G"]);
+    assert_eq!(exit_code(&output), 64, "{}", stderr(&output));
+    let output = chip8(&["run", IBM_LOGO, "--frames", "1", "--assert-reg", "V10=1"]);
+    assert_eq!(exit_code(&output), 64, "{}", stderr(&output));
+}
+
+#[test]
+fn help_exits_with_0() {
+    let output = chip8(&["run", "--help"]);
+    assert_eq!(exit_code(&output), 0, "{}", stderr(&output));
+}
+
+#[test]
+fn asm_errors_print_file_and_line() {
+    let dir = std::env::temp_dir().join(format!("chip8-exit-codes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("bad.8o");
+    std::fs::write(&source, ": main\n  jump nowhere\n").unwrap();
+
+    let output = chip8(&["asm", source.to_str().unwrap()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(exit_code(&output), 1, "{}", stderr(&output));
+    assert_eq!(stderr(&output), format!("Error: {}:2: undefined name 'nowhere'\n", source.display()));
+}
@@ -0,0 +1,78 @@
+// Boots each bundled test ROM with `chip8 run`, lets it run for a fixed number of frames and
+// compares the final screen with the expected image in tests/golden/<name>.txt.
+//
+// After an intentional change to the output, regenerate the images with
+//   CHIP8_BLESS=1 cargo test --test golden_screens
+// and review the diff before committing them.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+struct Case {
+    name: &'static str,
+    rom: &'static str,
+    frames: u32,
+    args: &'static [&'static str],
+}
+
+const CASES: &[Case] = &[
+    Case { name: "1-chip8-logo", rom: "1-chip8-logo.ch8", frames: 60, args: &[] },
+    Case { name: "2-ibm-logo", rom: "2-ibm-logo.ch8", frames: 60, args: &[] },
+    Case { name: "3-corax+", rom: "3-corax+.ch8", frames: 120, args: &[] },
+    Case { name: "4-flags", rom: "4-flags.ch8", frames: 120, args: &[] },
+    // 0x1FF picks the platform to test and skips the ROM's menu
+    Case { name: "5-quirks-vip", rom: "5-quirks.ch8", frames: 1200, args: &["--quirks", "vip", "--poke", "0x1FF=1"] },
+    Case { name: "5-quirks-schip", rom: "5-quirks.ch8", frames: 1200, args: &["--quirks", "schip", "--poke", "0x1FF=2"] },
+    Case { name: "5-quirks-xochip", rom: "5-quirks.ch8", frames: 1200, args: &["--quirks", "xochip", "--poke", "0x1FF=3"] },
+    // Menu option 1 (EX9E), then hold key 5 until the end so it shows as pressed
+    Case { name: "6-keypad-ex9e", rom: "6-keypad.ch8", frames: 300, args: &["--press", "60:1", "--press", "120:5:200"] },
+    // Menu option 3 (FX0A), then press and release key 7
+    Case { name: "6-keypad-fx0a", rom: "6-keypad.ch8", frames: 300, args: &["--press", "60:3", "--press", "150:7"] },
+];
+
+fn run_case(case: &Case) -> String {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .arg("run")
+        .arg(root.join("roms").join(case.rom))
+        .args(["--frames", &case.frames.to_string(), "--dump-screen", "ascii"])
+        .args(case.args)
+        .output()
+        .expect("failed to start chip8");
+
+    assert!(
+        output.status.success(),
+        "{} exited with {}: {}",
+        case.name,
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).expect("screen dump is not UTF-8")
+}
+
+#[test]
+fn golden_screens() {
+    let golden_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let bless = std::env::var_os("CHIP8_BLESS").is_some();
+    let mut mismatches = vec![];
+
+    for case in CASES {
+        let screen = run_case(case);
+        let path = golden_dir.join(format!("{}.txt", case.name));
+
+        if bless {
+            std::fs::create_dir_all(&golden_dir).unwrap();
+            std::fs::write(&path, &screen).unwrap();
+            continue;
+        }
+
+        let expected = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("can't read {}: {} (run with CHIP8_BLESS=1 to create it)", path.display(), e));
+        if screen != expected {
+            mismatches.push(format!("{}:\n--- expected\n{}--- actual\n{}", case.name, expected, screen));
+        }
+    }
+
+    assert!(mismatches.is_empty(), "screens differ from the golden images:\n{}", mismatches.join("\n"));
+}